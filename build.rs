@@ -2,8 +2,17 @@ use std::env;
 use std::path::PathBuf;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut config = tonic_prost_build::Config::new();
+    let mut includes = vec![PathBuf::from("proto")];
+    // A protoc given in PROTOC wins; otherwise use the bundled one, so a
+    // checkout builds without installing anything
+    if env::var_os("PROTOC").is_none() {
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+        includes.push(protoc_bin_vendored::include_path()?);
+    }
+    println!("cargo:rerun-if-env-changed=PROTOC");
     tonic_prost_build::configure()
         .file_descriptor_set_path(out_dir.join("fileservice_descriptor.bin"))
-        .compile_protos(&["proto/files.proto"], &["proto"])?;
+        .compile_with_config(config, &[PathBuf::from("proto/files.proto")], &includes)?;
     Ok(())
 }
@@ -0,0 +1,212 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+use crate::config::Config;
+use crate::identity::{self, PinnedKeyIdentity, TokenIdentity};
+use crate::tls::ReloadableTls;
+
+/// The access tokens the server accepts, by the SHA-256 hash of the token.
+pub struct AccessTokens {
+    names: RwLock<HashMap<[u8; 32], String>>,
+}
+
+impl AccessTokens {
+    pub fn load(config: &Config) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        Ok(Arc::new(AccessTokens {
+            names: RwLock::new(parse_tokens(config)?),
+        }))
+    }
+
+    /// Swap in the tokens from a reloaded configuration. Requests already
+    /// authenticated keep running.
+    pub fn reload(&self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let names = parse_tokens(config)?;
+        *self.names.write().unwrap() = names;
+        Ok(())
+    }
+
+    /// Name of the client a token belongs to.
+    fn check(&self, token: &str) -> Option<String> {
+        let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, token.as_bytes());
+        let hash: [u8; 32] = digest.as_ref().try_into().ok()?;
+        self.names.read().unwrap().get(&hash).cloned()
+    }
+
+    /// Name of the client for an `Authorization` header: a bearer token, or
+    /// basic credentials with the token's name as the user and the token as
+    /// the password, for clients such as file managers that only know those.
+    fn authorize(&self, header: &http::HeaderValue) -> Option<String> {
+        let value = header.to_str().ok()?;
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            return self.check(token.trim());
+        }
+        let credentials = BASE64.decode(value.strip_prefix("Basic ")?.trim()).ok()?;
+        let (user, token) = std::str::from_utf8(&credentials).ok()?.split_once(':')?;
+        self.check(token).filter(|name| name == user)
+    }
+
+    /// Check requests against these tokens, and client certificates against
+    /// the pinned keys in `tls`. With `anonymous`, for plaintext development
+    /// servers, requests without any identity are let through.
+    pub fn layer(self: &Arc<Self>, tls: Option<Arc<ReloadableTls>>, anonymous: bool) -> AccessLayer {
+        AccessLayer {
+            tokens: self.clone(),
+            tls,
+            anonymous,
+        }
+    }
+}
+
+fn parse_tokens(config: &Config) -> Result<HashMap<[u8; 32], String>, Box<dyn std::error::Error>> {
+    let mut names = HashMap::new();
+    for token in &config.access_tokens {
+        if identity::is_uid_name(&token.name) {
+            return Err(format!("Access token name '{}' is reserved for local processes", token.name).into());
+        }
+        let hash = parse_hex(&token.sha256).ok_or_else(|| {
+            format!("Invalid hash for access token '{}': expected 64 hex digits", token.name)
+        })?;
+        if names.insert(hash, token.name.clone()).is_some() {
+            return Err(format!("Access token '{}' has the same hash as another token", token.name).into());
+        }
+    }
+    Ok(names)
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Checks `Authorization` headers on every request, gRPC and HTTP alike. A
+/// valid token sets the caller's identity; an unknown one is turned away.
+/// Requests without the header fall back to the connection's identity, and
+/// are turned away too if the connection has none. Certificates checked
+/// against pinned keys are known by the name their key is pinned under.
+#[derive(Clone)]
+pub struct AccessLayer {
+    tokens: Arc<AccessTokens>,
+    tls: Option<Arc<ReloadableTls>>,
+    anonymous: bool,
+}
+
+impl<S> Layer<S> for AccessLayer {
+    type Service = AccessService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessService {
+            inner,
+            tokens: self.tokens.clone(),
+            tls: self.tls.clone(),
+            anonymous: self.anonymous,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessService<S> {
+    inner: S,
+    tokens: Arc<AccessTokens>,
+    tls: Option<Arc<ReloadableTls>>,
+    anonymous: bool,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for AccessService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<tonic::codegen::StdError>,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        if let Some(header) = request.headers().get(http::header::AUTHORIZATION) {
+            match self.tokens.authorize(header) {
+                Some(name) => {
+                    request.extensions_mut().insert(TokenIdentity(name));
+                }
+                None => {
+                    tracing::warn!("request with an unknown access token refused");
+                    let response = unauthenticated(&request, "Unknown access token");
+                    return Box::pin(async move { Ok(response) });
+                }
+            }
+        } else if let Some(tls) = &self.tls
+            && tls.pins_clients()
+            && let Some(cert) = identity::peer_certificate(request.extensions())
+        {
+            // Keys unpinned by a reload are turned away on connections made before it
+            let Some(name) = tls.pinned_name(&cert) else {
+                tracing::warn!("request with a client key that is no longer pinned refused");
+                let response = unauthenticated(&request, "Client key is not pinned");
+                return Box::pin(async move { Ok(response) });
+            };
+            request.extensions_mut().insert(PinnedKeyIdentity(name));
+        } else if let Some(cert) = identity::peer_certificate(request.extensions())
+            && let Some(cn) = identity::common_name(cert.as_ref())
+            && identity::is_uid_name(&cn)
+        {
+            tracing::warn!(cn = %cn, "request with a certificate named like a local user refused");
+            let message = format!("Certificate name '{}' is reserved for local processes", cn);
+            let response = unauthenticated(&request, &message);
+            return Box::pin(async move { Ok(response) });
+        }
+        if !self.anonymous && identity::peer_identity(request.extensions()).is_none() {
+            tracing::warn!("request without a client certificate or access token refused");
+            let response = unauthenticated(&request, "A client certificate or access token is required");
+            return Box::pin(async move { Ok(response) });
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move { Ok(response.await?.map(tonic::body::Body::new)) })
+    }
+}
+
+/// A refusal in the caller's protocol: a gRPC status for gRPC and gRPC-Web
+/// calls, a plain 401 for everything else.
+fn unauthenticated<B>(request: &http::Request<B>, message: &str) -> http::Response<tonic::body::Body> {
+    let content_type = request.headers().get(http::header::CONTENT_TYPE);
+    if let Some(content_type) = content_type
+        && content_type.as_bytes().starts_with(b"application/grpc")
+    {
+        let mut response: http::Response<tonic::body::Body> =
+            tonic::Status::unauthenticated(message).into_http();
+        // gRPC-Web clients expect their own content type back
+        response
+            .headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type.clone());
+        return response;
+    }
+    let body = axum::body::Body::from(serde_json::json!({ "error": message }).to_string());
+    let mut response = http::Response::new(tonic::body::Body::new(body));
+    *response.status_mut() = http::StatusCode::UNAUTHORIZED;
+    let headers = response.headers_mut();
+    headers.insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/json"));
+    headers.insert(http::header::WWW_AUTHENTICATE, http::HeaderValue::from_static("Bearer"));
+    // File managers only offer to sign in when asked for basic credentials
+    headers.append(
+        http::header::WWW_AUTHENTICATE,
+        http::HeaderValue::from_static(r#"Basic realm="grpc-files""#),
+    );
+    response
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::Config;
+use crate::error::FileError;
+use crate::fileservice::{ClientLoad, StatusResponse};
+
+/// How long a client turned away for lack of a transfer slot is asked to wait.
+const BUSY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Caps how many uploads and downloads run at once, in total and per client.
+///
+/// Transfers over a cap wait in a FIFO queue for up to the configured timeout,
+/// or are turned away straight away if the timeout is zero.
+pub struct Admission {
+    caps: Mutex<Caps>,
+    clients: Mutex<HashMap<String, ClientSlots>>,
+}
+
+/// The configured caps. Zero means no cap.
+#[derive(Clone)]
+struct Caps {
+    total: Option<Arc<Semaphore>>,
+    max_total: usize,
+    max_per_client: usize,
+    queue_timeout: Duration,
+}
+
+struct ClientSlots {
+    semaphore: Option<Arc<Semaphore>>,
+    active: usize,
+    queued: usize,
+}
+
+/// A running transfer's place. Frees the slot when dropped.
+pub struct TransferPermit {
+    admission: Arc<Admission>,
+    client: String,
+    _client_permit: Option<OwnedSemaphorePermit>,
+    _total_permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Admission {
+    pub fn from_config(config: &Config) -> Arc<Self> {
+        let max_total = config.max_concurrent_transfers.unwrap_or(0);
+        Arc::new(Admission {
+            caps: Mutex::new(Caps {
+                total: (max_total > 0).then(|| Arc::new(Semaphore::new(max_total))),
+                max_total,
+                max_per_client: config.max_concurrent_transfers_per_client.unwrap_or(0),
+                queue_timeout: Duration::from_secs(config.transfer_queue_timeout_secs),
+            }),
+            clients: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Apply reloaded caps. Running transfers keep their slots: lowering a cap
+    /// holds back new transfers until enough of them finish, while turning a
+    /// cap on starts counting from the transfers admitted after the change.
+    pub fn reconfigure(&self, config: &Config) {
+        let max_total = config.max_concurrent_transfers.unwrap_or(0);
+        let max_per_client = config.max_concurrent_transfers_per_client.unwrap_or(0);
+        let mut caps = self.caps.lock().unwrap();
+        caps.total = resize(caps.total.take(), caps.max_total, max_total);
+        if max_per_client != caps.max_per_client {
+            for slots in self.clients.lock().unwrap().values_mut() {
+                slots.semaphore = resize(slots.semaphore.take(), caps.max_per_client, max_per_client);
+            }
+        }
+        caps.max_total = max_total;
+        caps.max_per_client = max_per_client;
+        caps.queue_timeout = Duration::from_secs(config.transfer_queue_timeout_secs);
+    }
+
+    /// Wait for a free transfer slot for `client`, failing with
+    /// `ResourceExhausted` if none frees up within the queue timeout.
+    pub async fn admit(self: &Arc<Self>, client: &str) -> Result<TransferPermit, FileError> {
+        let caps = self.caps.lock().unwrap().clone();
+        let client_semaphore = {
+            let mut clients = self.clients.lock().unwrap();
+            let slots = clients.entry(client.to_string()).or_insert_with(|| ClientSlots {
+                semaphore: (caps.max_per_client > 0)
+                    .then(|| Arc::new(Semaphore::new(caps.max_per_client))),
+                active: 0,
+                queued: 0,
+            });
+            slots.queued += 1;
+            slots.semaphore.clone()
+        };
+        // Leaves the queue however admission ends, including the client giving up
+        let queued = Queued {
+            admission: self,
+            client,
+        };
+
+        // Take the client's own slot first so a client waiting on itself does
+        // not hold a server-wide slot that others could use
+        let result = async {
+            let client_permit = match client_semaphore {
+                Some(semaphore) => Some(acquire(semaphore, caps.queue_timeout, "client").await?),
+                None => None,
+            };
+            let total_permit = match caps.total {
+                Some(semaphore) => Some(acquire(semaphore, caps.queue_timeout, "server").await?),
+                None => None,
+            };
+            Ok::<_, FileError>((client_permit, total_permit))
+        }
+        .await;
+
+        match result {
+            Ok((client_permit, total_permit)) => {
+                if let Some(slots) = self.clients.lock().unwrap().get_mut(client) {
+                    slots.active += 1;
+                }
+                drop(queued);
+                Ok(TransferPermit {
+                    admission: self.clone(),
+                    client: client.to_string(),
+                    _client_permit: client_permit,
+                    _total_permit: total_permit,
+                })
+            }
+            Err(error) => {
+                drop(queued);
+                tracing::warn!(client = %client, reason = %error, "transfer rejected");
+                Err(error)
+            }
+        }
+    }
+
+    /// Update a client's counters, forgetting the client once it has nothing left.
+    fn release(&self, client: &str, update: impl FnOnce(&mut ClientSlots)) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(slots) = clients.get_mut(client) {
+            update(slots);
+            if slots.active == 0 && slots.queued == 0 {
+                clients.remove(client);
+            }
+        }
+    }
+
+    /// Current load, for the status RPC. `uptime_secs` is left for the caller.
+    pub fn status(&self) -> StatusResponse {
+        let (max_total, max_per_client) = {
+            let caps = self.caps.lock().unwrap();
+            (caps.max_total, caps.max_per_client)
+        };
+        let clients = self.clients.lock().unwrap();
+        let mut loads: Vec<ClientLoad> = clients
+            .iter()
+            .map(|(client, slots)| ClientLoad {
+                client: client.clone(),
+                active_transfers: slots.active as u32,
+                queued_transfers: slots.queued as u32,
+            })
+            .collect();
+        loads.sort_by(|a, b| a.client.cmp(&b.client));
+
+        StatusResponse {
+            active_transfers: loads.iter().map(|l| l.active_transfers).sum(),
+            queued_transfers: loads.iter().map(|l| l.queued_transfers).sum(),
+            max_transfers: max_total as u32,
+            max_transfers_per_client: max_per_client as u32,
+            clients: loads,
+            uptime_secs: 0,
+        }
+    }
+}
+
+async fn acquire(
+    semaphore: Arc<Semaphore>,
+    queue_timeout: Duration,
+    scope: &'static str,
+) -> Result<OwnedSemaphorePermit, FileError> {
+    let busy = || FileError::Busy {
+        limit: scope,
+        retry_after: BUSY_RETRY_AFTER,
+    };
+
+    if queue_timeout.is_zero() {
+        return semaphore.try_acquire_owned().map_err(|_| busy());
+    }
+    match tokio::time::timeout(queue_timeout, semaphore.acquire_owned()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        _ => Err(busy()),
+    }
+}
+
+/// Change a semaphore from `old` to `new` slots, where zero means no cap.
+/// Slots held by running transfers cannot be taken back, so a smaller cap
+/// retires them as they are returned.
+fn resize(semaphore: Option<Arc<Semaphore>>, old: usize, new: usize) -> Option<Arc<Semaphore>> {
+    match semaphore {
+        _ if new == 0 => None,
+        Some(semaphore) if old > 0 => {
+            if new > old {
+                semaphore.add_permits(new - old);
+            } else if new < old {
+                let retired = semaphore.clone();
+                let count = (old - new) as u32;
+                tokio::spawn(async move {
+                    if let Ok(permits) = retired.acquire_many_owned(count).await {
+                        permits.forget();
+                    }
+                });
+            }
+            Some(semaphore)
+        }
+        _ => Some(Arc::new(Semaphore::new(new))),
+    }
+}
+
+impl Drop for TransferPermit {
+    fn drop(&mut self) {
+        self.admission.release(&self.client, |slots| slots.active -= 1);
+    }
+}
+
+struct Queued<'a> {
+    admission: &'a Admission,
+    client: &'a str,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.admission.release(self.client, |slots| slots.queued -= 1);
+    }
+}
@@ -0,0 +1,924 @@
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataValue;
+use tonic::service::{Interceptor, interceptor::InterceptedService};
+use tonic::transport::Channel;
+use tower::Layer;
+
+pub use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::{self, Config},
+    delta::{self, DeltaApplier},
+    error::{Reason, ServerError},
+    fileservice::{
+        CopyRequest, CreateDirectoryRequest, DeleteDirectoryRequest, DeleteRequest, DeltaChunk,
+        DeltaDownloadRequest, DeltaHeader, DownloadRequest, FileInfo, ListRequest, ListResponse,
+        ListVolumesRequest, MoveRequest, SignatureRequest, StatRequest, UploadChunk, VolumeInfo,
+        delta_op::Op, file_service_client::FileServiceClient,
+    },
+    logging::REQUEST_ID_HEADER,
+    throttle::{ThrottleLayer, Throttled},
+    tls,
+};
+
+/// The generated file service client, tagging every call with a request ID.
+/// [`Client::rpc`] hands one out for calls `Client` has no method for.
+pub type FileClient = FileServiceClient<InterceptedService<Throttled<Channel>, RequestId>>;
+
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Attaches a fresh `x-request-id` to each outgoing call so it can be matched
+/// against the server's logs.
+#[derive(Clone, Copy, Default)]
+pub struct RequestId;
+
+impl Interceptor for RequestId {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        let id = uuid::Uuid::new_v4().to_string();
+        tracing::debug!(request_id = %id, "sending request");
+        if let Ok(value) = MetadataValue::try_from(id.as_str()) {
+            request.metadata_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        Ok(request)
+    }
+}
+
+/// How often calls that failed because the server could not be reached, or
+/// that the server asked to repeat later, are tried again. Only calls that
+/// are safe to repeat are retried: listings, stat, downloads, which resume
+/// where they broke off, and uploads from a local file, which start over.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries in total, the first one included. 1 turns retries off.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before try `attempt + 1` after `error`, or `None` to give up.
+    fn delay(&self, attempt: u32, error: &Error) -> Option<Duration> {
+        let asked = match error {
+            Error::Connection(_) => None,
+            // Busy or rate limited: wait as long as the server says, unless
+            // that is longer than we would ever back off
+            Error::Server(ServerError {
+                retry_after: Some(wait),
+                ..
+            }) if *wait <= self.max_backoff => Some(*wait),
+            _ => return None,
+        };
+        if attempt >= self.attempts {
+            return None;
+        }
+        let backoff = self.initial_backoff.saturating_mul(1 << (attempt - 1).min(16));
+        tracing::warn!(attempt, error = %error, "call failed, retrying");
+        Some(asked.unwrap_or(backoff.min(self.max_backoff)))
+    }
+}
+
+/// Why a call to the file server failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The server turned the call down, with the reason, path and wait it gave.
+    Server(ServerError),
+    /// The server could not be reached, or the connection broke off.
+    Connection(tonic::Status),
+    /// Reading or writing a local file failed.
+    Io(io::Error),
+    /// The transfer was cancelled through its token.
+    Cancelled,
+    /// Settings or arguments that can't work, such as an unusable address or
+    /// an upload without a file name.
+    InvalidInput(String),
+}
+
+impl Error {
+    /// The gRPC code of a server error, `Unavailable` when the server could
+    /// not be reached, and `Cancelled`, `InvalidArgument` or `Unknown` for
+    /// failures on this side.
+    pub fn code(&self) -> tonic::Code {
+        match self {
+            Error::Server(error) => error.code,
+            Error::Connection(_) => tonic::Code::Unavailable,
+            Error::Io(_) => tonic::Code::Unknown,
+            Error::Cancelled => tonic::Code::Cancelled,
+            Error::InvalidInput(_) => tonic::Code::InvalidArgument,
+        }
+    }
+
+    /// What went wrong on the server, when it said.
+    pub fn reason(&self) -> Option<Reason> {
+        match self {
+            Error::Server(error) => error.reason,
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        // A connection lost halfway through a call comes back as an unknown
+        // error wrapping the transport's; errors the server sends have no source
+        match status.code() {
+            tonic::Code::Unavailable => Error::Connection(status),
+            tonic::Code::Unknown if status.source().is_some() => Error::Connection(status),
+            _ => Error::Server(ServerError::from(&status)),
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(error: tonic::transport::Error) -> Self {
+        let mut status = tonic::Status::unavailable(error.to_string());
+        status.set_source(Arc::new(error));
+        Error::Connection(status)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(error: tokio::task::JoinError) -> Self {
+        Error::Io(io::Error::other(error))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Server(error) => error.fmt(f),
+            Error::Connection(status) => write!(f, "Can't reach the server: {}", status.message()),
+            Error::Io(error) => error.fmt(f),
+            Error::Cancelled => f.write_str("Transfer cancelled"),
+            Error::InvalidInput(message) => f.write_str(message),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Connection(status) => Some(status),
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// How far a transfer has got.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub bytes: u64,
+    /// Size of the file, when known.
+    pub total: Option<u64>,
+}
+
+/// Progress reporting and cancellation for an upload or download.
+#[derive(Clone, Default)]
+pub struct Transfer {
+    progress: Option<Arc<dyn Fn(Progress) + Send + Sync>>,
+    cancel: Option<CancellationToken>,
+}
+
+impl Transfer {
+    /// Call `progress` each time a chunk has been sent or received.
+    pub fn on_progress(mut self, progress: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Stop the transfer when `token` is cancelled. A cancelled upload is
+    /// discarded by the server and a cancelled download leaves no file behind.
+    pub fn cancel_with(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn report(&self, bytes: u64, total: Option<u64>) {
+        if let Some(progress) = &self.progress {
+            progress(Progress { bytes, total });
+        }
+    }
+
+    /// Run `work` unless the transfer is cancelled first.
+    async fn guard<T>(
+        &self,
+        work: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let cancelled = async {
+            match &self.cancel {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = work => result,
+            () = cancelled => Err(Error::Cancelled),
+        }
+    }
+}
+
+/// Settings for a [`Client`], starting from a configuration whose client
+/// settings can then be changed one by one.
+pub struct ClientBuilder {
+    config: Config,
+    retry: RetryPolicy,
+}
+
+impl Default for ClientBuilder {
+    /// The built-in defaults, without reading any config file.
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl ClientBuilder {
+    pub fn new(config: Config) -> Self {
+        ClientBuilder {
+            config,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Server to connect to: `host:port`, or `unix:///path` for a Unix socket.
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.config.server_connect_address = address.into();
+        self
+    }
+
+    /// Name expected in the server's certificate.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.config.tls_server_name = name.into();
+        self
+    }
+
+    /// CA to check the server's certificate against.
+    pub fn ca_file(mut self, path: impl Into<String>) -> Self {
+        self.config.tls_ca_file = Some(path.into());
+        self
+    }
+
+    /// Certificate and private key to identify the client with.
+    pub fn client_certificate(mut self, cert: impl Into<String>, key: impl Into<String>) -> Self {
+        self.config.tls_client_cert_file = Some(cert.into());
+        self.config.tls_client_key_file = Some(key.into());
+        self
+    }
+
+    /// Talk to the server without TLS. Only loopback addresses are allowed.
+    pub fn plaintext(mut self, plaintext: bool) -> Self {
+        self.config.plaintext = plaintext;
+        self
+    }
+
+    /// Caps on this client's upload and download speed in bytes per second.
+    pub fn speed_limits(mut self, upload: Option<u64>, download: Option<u64>) -> Self {
+        self.config.local_upload_limit_bytes_per_sec = upload;
+        self.config.local_download_limit_bytes_per_sec = download;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub async fn connect(self) -> Result<Client, Error> {
+        Ok(Client {
+            inner: open(&self.config).await?,
+            retry: self.retry,
+        })
+    }
+}
+
+/// A connection to a file server. Cloning it is cheap and the clones share
+/// the connection, so it can be handed to several tasks at once.
+#[derive(Clone)]
+pub struct Client {
+    inner: FileClient,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// Connect with the client settings of `config` and the default retry policy.
+    pub async fn connect(config: &Config) -> Result<Self, Error> {
+        Ok(Client {
+            inner: open(config).await?,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// The underlying generated client, for the calls without a method here
+    /// such as tags, previews and search. Calls made through it are not retried.
+    pub fn rpc(&self) -> FileClient {
+        self.inner.clone()
+    }
+
+    /// Make a unary call, trying again while the server is unavailable.
+    async fn call<T, F, Fut>(&self, mut call: F) -> Result<T, Error>
+    where
+        F: FnMut(FileClient) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match call(self.inner.clone()).await {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status) => {
+                    let error = Error::from(status);
+                    match self.retry.delay(attempt, &error) {
+                        Some(delay) => tokio::time::sleep(delay).await,
+                        None => return Err(error),
+                    }
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    pub async fn volumes(&self) -> Result<Vec<VolumeInfo>, Error> {
+        let response = self
+            .call(|mut client| async move { client.list_volumes(ListVolumesRequest {}).await })
+            .await?;
+        Ok(response.volumes)
+    }
+
+    /// One page of a directory listing.
+    pub async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        self.call(|mut client| {
+            let request = request.clone();
+            async move { client.list_files(request).await }
+        })
+        .await
+    }
+
+    pub async fn stat(&self, volume: &str, path: &str) -> Result<FileInfo, Error> {
+        let request = StatRequest {
+            path: path.to_string(),
+            volume: volume.to_string(),
+        };
+        let response = self
+            .call(|mut client| {
+                let request = request.clone();
+                async move { client.stat(request).await }
+            })
+            .await?;
+        response
+            .info
+            .ok_or_else(|| tonic::Status::internal("Server sent no file info").into())
+    }
+
+    pub async fn create_directory(&self, volume: &str, parent: &str, name: &str) -> Result<(), Error> {
+        self.rpc()
+            .create_directory(CreateDirectoryRequest {
+                path: parent.to_string(),
+                name: name.to_string(),
+                volume: volume.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Create a directory and any missing parents. Directories that already
+    /// exist are fine.
+    pub async fn create_directories(&self, volume: &str, path: &str) -> Result<(), Error> {
+        let mut parent = String::new();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            match self.create_directory(volume, &parent, component).await {
+                Ok(()) => {}
+                Err(error) if error.code() == tonic::Code::AlreadyExists => {}
+                Err(error) => return Err(error),
+            }
+            parent = match parent.as_str() {
+                "" => component.to_string(),
+                _ => format!("{}/{}", parent, component),
+            };
+        }
+        Ok(())
+    }
+
+    pub async fn delete_file(&self, volume: &str, path: &str) -> Result<(), Error> {
+        self.rpc()
+            .delete_file(DeleteRequest {
+                file_name: path.to_string(),
+                volume: volume.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a directory, which has to be empty unless `recursive` is set.
+    pub async fn delete_directory(&self, volume: &str, path: &str, recursive: bool) -> Result<(), Error> {
+        self.rpc()
+            .delete_directory(DeleteDirectoryRequest {
+                path: path.to_string(),
+                recursive,
+                volume: volume.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Move or rename a file or directory within a volume.
+    pub async fn rename(&self, volume: &str, source: &str, destination: &str) -> Result<(), Error> {
+        self.rpc()
+            .move_file(MoveRequest {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                volume: volume.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Copy a file, or a directory and everything in it, within a volume.
+    pub async fn copy(&self, volume: &str, source: &str, destination: &str) -> Result<(), Error> {
+        self.rpc()
+            .copy_file(CopyRequest {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                volume: volume.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Upload a local file to `path`, replacing any file there. Returns the
+    /// number of bytes the server stored.
+    #[tracing::instrument(skip(self, transfer))]
+    pub async fn upload_file(
+        &self,
+        local: &Path,
+        volume: &str,
+        path: &str,
+        transfer: &Transfer,
+    ) -> Result<u64, Error> {
+        if !local.is_file() {
+            return Err(Error::InvalidInput(format!("{} is not a file", local.display())));
+        }
+        let start = std::time::Instant::now();
+        let mut attempt = 1;
+        let size = loop {
+            let result = async {
+                let file = File::open(local).await?;
+                let size = file.metadata().await?.len();
+                self.send(file, size, volume, path, transfer).await
+            };
+            match transfer.guard(result).await {
+                Ok(size) => break size,
+                Err(e) => match self.retry.delay(attempt, &e) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return Err(e),
+                },
+            }
+            attempt += 1;
+        };
+        let elapsed = start.elapsed();
+        tracing::info!(
+            bytes = size,
+            elapsed = ?elapsed,
+            mb_per_s = (size as f64 / 1024.0 / 1024.0) / elapsed.as_secs_f64(),
+            "upload complete"
+        );
+        Ok(size)
+    }
+
+    /// Upload everything `reader` gives to `path`. `size` is what the reader
+    /// will give, if known, so the server can refuse a file that will not fit
+    /// before it arrives. Reading can't be repeated, so this is not retried.
+    pub async fn upload<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        size: Option<u64>,
+        volume: &str,
+        path: &str,
+        transfer: &Transfer,
+    ) -> Result<u64, Error> {
+        let upload = self.send(reader, size.unwrap_or(0), volume, path, transfer);
+        transfer.guard(upload).await
+    }
+
+    /// Stream an upload. If reading fails the upload is abandoned, so the
+    /// server discards it rather than keeping a truncated file.
+    async fn send<R: AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+        size: u64,
+        volume: &str,
+        path: &str,
+        transfer: &Transfer,
+    ) -> Result<u64, Error> {
+        let (directory, name) = split_target(path);
+        if name.is_empty() {
+            return Err(Error::InvalidInput("Give a file name to upload to".to_string()));
+        }
+        let total = (size > 0).then_some(size);
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let first = UploadChunk {
+            upload_id: upload_id.clone(),
+            filename: name.to_string(),
+            chunk_index: 0,
+            data: Vec::new(),
+            target_directory: directory.to_string(),
+            file_size: size,
+            volume: volume.to_string(),
+        };
+
+        let read = async move {
+            let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+            let mut first = Some(first);
+            let mut chunk_index = 0;
+            let mut sent = 0;
+            loop {
+                let n = reader.read(&mut buffer).await?;
+                // The first chunk goes out even when empty, as it names the file
+                if n == 0 && first.is_none() {
+                    break;
+                }
+                let mut chunk = first.take().unwrap_or_else(|| UploadChunk {
+                    upload_id: upload_id.clone(),
+                    chunk_index,
+                    ..Default::default()
+                });
+                chunk.data = buffer[..n].to_vec();
+                // A closed stream means the server ended the upload; its response says why
+                if tx.send(chunk).await.is_err() || n == 0 {
+                    break;
+                }
+                chunk_index += 1;
+                sent += n as u64;
+                transfer.report(sent, total);
+            }
+            Ok::<_, Error>(())
+        };
+        let upload = async {
+            let response = self.rpc().upload(ReceiverStream::new(rx)).await?;
+            Ok::<_, Error>(response.into_inner().size)
+        };
+        let ((), size) = tokio::try_join!(read, upload)?;
+        Ok(size)
+    }
+
+    /// Download a file to `local`. The data goes to a temporary file next to
+    /// it first, so an interrupted download never leaves a partial file under
+    /// the real name. Returns the number of bytes downloaded.
+    #[tracing::instrument(skip(self, transfer))]
+    pub async fn download_file(
+        &self,
+        volume: &str,
+        path: &str,
+        local: &Path,
+        transfer: &Transfer,
+    ) -> Result<u64, Error> {
+        let name = local
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| Error::InvalidInput(format!("Invalid file name {}", local.display())))?;
+        let temp_path = local.with_file_name(format!(".{}.partial", name));
+        let result = async {
+            let mut file = File::create(&temp_path).await?;
+            let size = self.download(volume, path, &mut file, transfer).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temp_path, local).await?;
+            Ok(size)
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    /// Download a file into `writer`. A download cut off by a lost
+    /// connection carries on from where it stopped.
+    pub async fn download<W: AsyncWrite + Unpin>(
+        &self,
+        volume: &str,
+        path: &str,
+        writer: &mut W,
+        transfer: &Transfer,
+    ) -> Result<u64, Error> {
+        let total = match transfer.progress {
+            Some(_) => self.stat(volume, path).await.ok().map(|info| info.size),
+            None => None,
+        };
+        let download = async {
+            let mut received = 0;
+            let mut attempt = 1;
+            loop {
+                let request = DownloadRequest {
+                    file_name: path.to_string(),
+                    volume: volume.to_string(),
+                    offset: received,
+                    length: 0,
+                };
+                let result = async {
+                    let mut stream = self.rpc().download(request).await?.into_inner();
+                    while let Some(chunk) = stream.message().await? {
+                        writer.write_all(&chunk.data).await?;
+                        received += chunk.data.len() as u64;
+                        transfer.report(received, total);
+                    }
+                    writer.flush().await?;
+                    Ok::<_, Error>(())
+                }
+                .await;
+                match result {
+                    Ok(()) => return Ok(received),
+                    Err(e) => match self.retry.delay(attempt, &e) {
+                        Some(delay) => tokio::time::sleep(delay).await,
+                        None => return Err(e),
+                    },
+                }
+                attempt += 1;
+            }
+        };
+        transfer.guard(download).await
+    }
+
+    /// Upload a file that already exists on the server by sending only the
+    /// blocks that differ from the server's copy.
+    #[tracing::instrument(skip(self))]
+    pub async fn delta_upload_file(
+        &self,
+        local: &Path,
+        volume: &str,
+        remote_path: &str,
+    ) -> Result<(), Error> {
+        if !local.is_file() {
+            return Err(Error::InvalidInput(format!("{} is not a file", local.display())));
+        }
+
+        let signature = self.stat_signatures(volume, remote_path).await?;
+
+        let file = std::fs::File::open(local)?;
+        let file_size = file.metadata()?.len();
+        let block_size = signature.block_size;
+        let (tx, rx) = tokio::sync::mpsc::channel(32);
+
+        let header = DeltaChunk {
+            header: Some(DeltaHeader {
+                path: remote_path.to_string(),
+                block_size,
+                file_size,
+                volume: volume.to_string(),
+            }),
+            ..Default::default()
+        };
+        tx.send(header)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Upload stream closed"))?;
+
+        let delta_task = tokio::task::spawn_blocking(move || {
+            let trailer = delta::compute_delta(file, block_size, &signature.blocks, |ops| {
+                tx.blocking_send(DeltaChunk {
+                    ops,
+                    ..Default::default()
+                })
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Upload stream closed"))
+            })?;
+            tx.blocking_send(DeltaChunk {
+                trailer: Some(trailer),
+                ..Default::default()
+            })
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Upload stream closed"))
+        });
+
+        let response = self.rpc().delta_upload(ReceiverStream::new(rx)).await;
+        let delta_result = delta_task.await?;
+        let response = response?.into_inner();
+        delta_result?;
+        tracing::info!(bytes = response.size, "delta upload complete");
+
+        Ok(())
+    }
+
+    async fn stat_signatures(
+        &self,
+        volume: &str,
+        path: &str,
+    ) -> Result<crate::fileservice::SignatureResponse, Error> {
+        let request = SignatureRequest {
+            path: path.to_string(),
+            block_size: 0,
+            volume: volume.to_string(),
+        };
+        self.call(|mut client| {
+            let request = request.clone();
+            async move { client.get_signatures(request).await }
+        })
+        .await
+    }
+
+    /// Download a file to `local_path` by sending the signatures of the existing
+    /// local copy, so only changed blocks travel over the network. Without a local
+    /// copy the whole file is sent. Returns the number of bytes reused locally.
+    #[tracing::instrument(skip(self))]
+    pub async fn delta_download_file(
+        &self,
+        volume: &str,
+        remote_path: &str,
+        local_path: &Path,
+    ) -> Result<u64, Error> {
+        let filename = local_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| Error::InvalidInput(format!("Invalid file name {}", local_path.display())))?;
+        let temp_path = local_path.with_file_name(format!(".{}.partial", filename));
+
+        let (block_size, blocks) = if local_path.is_file() {
+            let local = std::fs::File::open(local_path)?;
+            let block_size = delta::block_size_for(local.metadata()?.len());
+            let blocks =
+                tokio::task::spawn_blocking(move || delta::signatures(local, block_size)).await??;
+            (block_size, blocks)
+        } else {
+            (0, Vec::new())
+        };
+
+        let mut stream = self
+            .rpc()
+            .delta_download(DeltaDownloadRequest {
+                path: remote_path.to_string(),
+                block_size,
+                blocks,
+                volume: volume.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        let result: Result<u64, Error> = async {
+            let mut basis = File::open(local_path).await.ok();
+            let mut out = File::create(&temp_path).await?;
+            let mut applier = DeltaApplier::new(block_size);
+            let mut trailer = None;
+            let mut literal_bytes = 0u64;
+
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                for op in chunk.ops {
+                    if let Some(Op::Literal(data)) = &op.op {
+                        literal_bytes += data.len() as u64;
+                    }
+                    applier.apply(basis.as_mut(), &mut out, op).await?;
+                }
+                if chunk.trailer.is_some() {
+                    trailer = chunk.trailer;
+                }
+            }
+
+            let trailer = trailer
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Download ended early"))?;
+            let size = applier.verify(&trailer)?;
+            out.flush().await?;
+            out.sync_all().await?;
+            Ok(size - literal_bytes)
+        }
+        .await;
+
+        match result {
+            Ok(reused) => {
+                tokio::fs::rename(&temp_path, local_path).await?;
+                tracing::info!(reused_bytes = reused, "delta download complete");
+                Ok(reused)
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "delta download failed");
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Open a channel to the configured server, over TLS unless plaintext is
+/// turned on, capped at the configured local upload and download speeds.
+/// Split an upload target into its directory and file name, ignoring
+/// leading and trailing slashes.
+fn split_target(path: &str) -> (&str, &str) {
+    let trimmed = path.trim_matches('/');
+    trimmed.rsplit_once('/').unwrap_or(("", trimmed))
+}
+
+async fn open(config: &Config) -> Result<FileClient, Error> {
+    let address = config.server_connect_address.clone();
+    // Unix socket connections ignore the address; it only has to be a valid URI
+    let uri = match config.connect_socket_path() {
+        Some(_) => "http://localhost".to_string(),
+        None => format!("http://{}", address),
+    };
+    let endpoint = Channel::from_shared(uri)
+        .map_err(|e| Error::InvalidInput(format!("Invalid server address '{}': {}", address, e)))?
+        .initial_connection_window_size(1024 * 1024)
+        .initial_stream_window_size(1024 * 1024);
+
+    let channel = if let Some(path) = config.connect_socket_path() {
+        // The server knows who is calling from the socket's peer credentials
+        let path = path.to_path_buf();
+        endpoint
+            .connect_with_connector(tower::service_fn(move |_: http::Uri| {
+                let path = path.clone();
+                async move { Ok::<_, io::Error>(TokioIo::new(connect_unix(&path).await?)) }
+            }))
+            .await?
+    } else if config.plaintext {
+        config::check_plaintext(&address).map_err(|e| Error::InvalidInput(e.to_string()))?;
+        endpoint.connect().await?
+    } else {
+        let tls_config = tls::client_config(config).map_err(|e| Error::InvalidInput(e.to_string()))?;
+        let tls = TlsConnector::from(Arc::new(tls_config));
+        let server_name = ServerName::try_from(config.tls_server_name.clone())
+            .map_err(|_| Error::InvalidInput(format!("Invalid tls_server_name '{}'", config.tls_server_name)))?;
+        endpoint
+            .connect_with_connector(tower::service_fn(move |_: http::Uri| {
+                let (tls, server_name, address) = (tls.clone(), server_name.clone(), address.clone());
+                async move {
+                    let stream = TcpStream::connect(&address).await?;
+                    stream.set_nodelay(true)?;
+                    let stream = tls.connect(server_name, stream).await?;
+                    Ok::<_, io::Error>(TokioIo::new(stream))
+                }
+            }))
+            .await?
+    };
+
+    let channel = ThrottleLayer::new(
+        config.local_upload_limit_bytes_per_sec,
+        config.local_download_limit_bytes_per_sec,
+    )
+    .layer(channel);
+
+    Ok(FileServiceClient::with_interceptor(channel, RequestId))
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &Path) -> io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(path).await
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_path: &Path) -> io::Result<TcpStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_targets_split_into_directory_and_name() {
+        assert_eq!(split_target("docs/report.txt"), ("docs", "report.txt"));
+        assert_eq!(split_target("/report.txt"), ("", "report.txt"));
+        assert_eq!(split_target("report.txt/"), ("", "report.txt"));
+        assert_eq!(split_target("/docs/2024/report.txt/"), ("docs/2024", "report.txt"));
+        assert_eq!(split_target("/"), ("", ""));
+    }
+
+    #[test]
+    fn errors_expose_their_cause() {
+        let error = Error::from(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        let source = error.source().and_then(|source| source.downcast_ref::<io::Error>());
+        assert_eq!(source.map(io::Error::kind), Some(io::ErrorKind::PermissionDenied));
+
+        let error = Error::Connection(tonic::Status::unavailable("connection refused"));
+        assert!(error.source().is_some_and(|source| source.is::<tonic::Status>()));
+    }
+}
@@ -1,43 +1,395 @@
-use serde::Deserialize;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+use crate::sandbox::SymlinkPolicy;
+
+/// Prefix of environment variables that override settings, e.g. `GRPC_FILES_LOG_LEVEL`.
+pub const ENV_PREFIX: &str = "GRPC_FILES_";
+/// Environment variable naming the config file to load.
+pub const CONFIG_FILE_ENV: &str = "GRPC_FILES_CONFIG";
+/// Environment variable naming the profile to use.
+pub const PROFILE_ENV: &str = "GRPC_FILES_PROFILE";
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
+    /// TCP address the server listens on. Empty to only listen on `server_socket_path`.
+    #[serde(default = "default_server_bind_address")]
     pub server_bind_address: String,
+    /// Unix domain socket the server also listens on, for local processes.
+    #[serde(default)]
+    pub server_socket_path: Option<String>,
+    /// Server the clients connect to: `host:port`, or `unix:///path` for a Unix socket.
+    #[serde(default = "default_server_connect_address")]
     pub server_connect_address: String,
+    #[serde(default = "default_upload_directory")]
     pub upload_directory: String,
+    #[serde(default = "default_download_directory")]
     pub download_directory: String,
+    /// Directory holding the TLS certificates. Defaults to `auth` in the config directory.
+    #[serde(default)]
+    pub auth_directory: Option<String>,
+    /// Name the client expects in the server's certificate.
+    #[serde(default = "default_tls_server_name")]
+    pub tls_server_name: String,
+    /// Server certificate. Defaults to `server-cert.pem` in the auth directory.
+    #[serde(default)]
+    pub tls_server_cert_file: Option<String>,
+    /// Server private key. Defaults to `server-key.pem` in the auth directory.
+    #[serde(default)]
+    pub tls_server_key_file: Option<String>,
+    /// Client certificate. Defaults to `client-cert.pem` in the auth directory.
+    #[serde(default)]
+    pub tls_client_cert_file: Option<String>,
+    /// Client private key. Defaults to `client-key.pem` in the auth directory.
+    #[serde(default)]
+    pub tls_client_key_file: Option<String>,
+    /// CA both sides check each other's certificates against. Defaults to
+    /// `ca-cert.pem` in the auth directory.
+    #[serde(default)]
+    pub tls_ca_file: Option<String>,
+    /// Whether the server turns away clients without a certificate.
+    #[serde(default = "default_tls_require_client_cert")]
+    pub tls_require_client_cert: bool,
+    /// Public keys the client accepts from the server instead of checking its
+    /// certificate against the CA: base64 SHA-256 hashes of the SubjectPublicKeyInfo.
+    #[serde(default)]
+    pub tls_server_public_key_pins: Vec<String>,
+    /// Public keys the server accepts from clients instead of checking their
+    /// certificates against the CA, each with the client identity it stands for.
+    #[serde(default)]
+    pub tls_client_public_key_pins: Vec<ClientKeyPinConfig>,
+    /// Talk to the server without TLS. Only allowed on loopback addresses, for development.
+    #[serde(default)]
+    pub plaintext: bool,
+    /// Bearer tokens that identify HTTP and gRPC clients without a certificate.
+    #[serde(default)]
+    pub access_tokens: Vec<AccessTokenConfig>,
+    /// Origins of web pages allowed to call the server from a browser.
+    #[serde(default)]
+    pub web_allowed_origins: Vec<String>,
+    /// Address of an optional WebDAV listener, served with the same TLS and
+    /// access rules as the gRPC listener. Disabled when unset.
+    #[serde(default)]
+    pub webdav_bind_address: Option<String>,
+    /// Address of an optional SFTP listener. Clients sign in with a key from
+    /// `sftp_authorized_keys`. Disabled when unset.
+    #[serde(default)]
+    pub sftp_bind_address: Option<String>,
+    /// Ed25519 host key of the SFTP listener, in OpenSSH format. Defaults to
+    /// `ssh_host_ed25519_key` in the auth directory.
+    #[serde(default)]
+    pub sftp_host_key_file: Option<String>,
+    /// SSH keys SFTP clients sign in with.
+    #[serde(default)]
+    pub sftp_authorized_keys: Vec<AuthorizedKeyConfig>,
+    /// How long the server lets running transfers finish after SIGINT/SIGTERM.
+    #[serde(default = "default_shutdown_grace_period_secs")]
+    pub shutdown_grace_period_secs: u64,
+    /// Address for the Prometheus `/metrics` endpoint. Disabled when unset.
+    #[serde(default)]
+    pub metrics_bind_address: Option<String>,
+    /// Log filter such as `info` or `grpc_files=debug`. `RUST_LOG` takes precedence.
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Directory for log files. The server logs to stderr when unset, the TUI
+    /// client to `logs` in the config directory so the screen is not corrupted.
+    #[serde(default)]
+    pub log_directory: Option<String>,
+    /// Server-wide cap on upload bandwidth across all clients.
+    #[serde(default)]
+    pub upload_limit_bytes_per_sec: Option<u64>,
+    /// Server-wide cap on download bandwidth across all clients.
+    #[serde(default)]
+    pub download_limit_bytes_per_sec: Option<u64>,
+    /// Upload bandwidth cap for each client identity.
+    #[serde(default)]
+    pub client_upload_limit_bytes_per_sec: Option<u64>,
+    /// Download bandwidth cap for each client identity.
+    #[serde(default)]
+    pub client_download_limit_bytes_per_sec: Option<u64>,
+    /// Rate of listing, search, tag and other non-transfer RPCs allowed per client identity.
+    #[serde(default)]
+    pub metadata_requests_per_sec: Option<u64>,
+    /// Uploads and downloads the server runs at once across all clients.
+    #[serde(default)]
+    pub max_concurrent_transfers: Option<usize>,
+    /// Uploads and downloads the server runs at once for each client identity.
+    #[serde(default)]
+    pub max_concurrent_transfers_per_client: Option<usize>,
+    /// How long a transfer over the limits waits for a slot. Zero rejects it at once.
+    #[serde(default)]
+    pub transfer_queue_timeout_secs: u64,
+    /// Largest file the server accepts in a single upload.
+    #[serde(default)]
+    pub max_upload_size_bytes: Option<u64>,
+    /// Space that must remain free on the storage volume after an upload.
+    #[serde(default)]
+    pub min_free_space_bytes: u64,
+    /// How long an unfinished upload may sit idle in the staging area before it is removed.
+    #[serde(default = "default_staging_idle_timeout_secs")]
+    pub staging_idle_timeout_secs: u64,
+    /// Whether paths may go through symbolic links inside the storage directory.
+    #[serde(default)]
+    pub symlink_policy: SymlinkPolicy,
+    /// Named storage roots served by the server. When empty, `upload_directory`
+    /// is served as a single volume called `default`.
+    #[serde(default)]
+    pub volumes: Vec<VolumeConfig>,
+    /// Confine each client to a home directory named after its certificate CN.
+    #[serde(default)]
+    pub homes: Option<HomesConfig>,
+    /// Speed cap for uploads from this client (TUI and sync).
+    #[serde(default)]
+    pub local_upload_limit_bytes_per_sec: Option<u64>,
+    /// Speed cap for downloads to this client (TUI and sync).
+    #[serde(default)]
+    pub local_download_limit_bytes_per_sec: Option<u64>,
+}
+
+/// One storage root the server makes available under a name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VolumeConfig {
+    pub name: String,
+    pub path: String,
+    /// Refuse every request that would change the volume.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Most bytes the volume may hold. Uploads and copies that would go over it are refused.
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+}
+
+/// A bearer token and the client identity it stands for.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccessTokenConfig {
+    /// Used like a certificate CN for home directories, admins and rate limits.
+    pub name: String,
+    /// Hex SHA-256 hash of the token, so the config file does not hold the token itself.
+    pub sha256: String,
+}
+
+/// A client's pinned public key and the client identity it stands for.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClientKeyPinConfig {
+    /// Used like a certificate CN for home directories, admins and rate limits.
+    pub name: String,
+    /// Base64 SHA-256 hash of the certificate's SubjectPublicKeyInfo.
+    pub pin: String,
+}
+
+/// An SSH public key and the client identity it stands for.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuthorizedKeyConfig {
+    /// Used like a certificate CN for home directories, admins and rate limits.
+    pub name: String,
+    /// The key as in an `authorized_keys` file: `ssh-ed25519 AAAA... comment`.
+    pub key: String,
+}
+
+/// Per-client home directories, kept in every volume.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HomesConfig {
+    /// Directory inside each volume holding one home per client.
+    #[serde(default = "default_homes_directory")]
+    pub directory: String,
+    /// Directory inside each volume holding the shared directories.
+    #[serde(default = "default_shared_directory")]
+    pub shared_directory: String,
+    /// Certificate CNs that see whole volumes instead of a home directory.
+    #[serde(default)]
+    pub admins: Vec<String>,
+    #[serde(default)]
+    pub shared: Vec<SharedDirectoryConfig>,
+}
+
+/// A directory several clients can use, shown at the top of each member's home.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SharedDirectoryConfig {
+    pub name: String,
+    /// Certificate CNs of the clients that may use it.
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+fn default_server_bind_address() -> String {
+    "0.0.0.0:50051".to_string()
+}
+
+fn default_server_connect_address() -> String {
+    "127.0.0.1:50051".to_string()
+}
+
+/// `$XDG_DATA_HOME/grpc-files/uploads`, or `uploads` in the working directory
+/// if there is no home to put it in.
+fn default_upload_directory() -> String {
+    let data_home = xdg_dir("XDG_DATA_HOME", ".local/share");
+    data_home
+        .map(|dir| dir.join("grpc-files").join("uploads"))
+        .unwrap_or_else(|| PathBuf::from("uploads"))
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_download_directory() -> String {
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join("Downloads"))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_tls_server_name() -> String {
+    "localhost".to_string()
+}
+
+fn default_tls_require_client_cert() -> bool {
+    true
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_homes_directory() -> String {
+    "homes".to_string()
+}
+
+fn default_shared_directory() -> String {
+    "shared".to_string()
+}
+
+fn default_staging_idle_timeout_secs() -> u64 {
+    60 * 60
+}
+
+fn default_shutdown_grace_period_secs() -> u64 {
+    30
+}
+
+impl Default for Config {
+    /// The built-in defaults, without any file, environment variables or flags.
+    fn default() -> Self {
+        serde_json::from_value(Value::Object(Map::new())).expect("every setting has a default")
+    }
 }
 
 impl Config {
+    /// Load the configuration from the usual file and environment variables,
+    /// without any command-line flags.
     pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let home_dir = std::env::var("HOME")
-            .map_err(|_| "HOME environment variable not set")?;
-        let config_path = PathBuf::from(home_dir)
-            .join(".file_server")
-            .join("config.json");
+        Ok(Self::load_with(&ConfigArgs::default())?.config)
+    }
 
-        if !config_path.exists() {
-            return Err(format!(
-                "Config file not found at {}. Please create it with the following structure:\n\n\
-                 {{\n  \"server_bind_address\": \"0.0.0.0:50051\",\n  \"server_connect_address\": \"192.168.1.xxx:50051\",\n  \"upload_directory\": \"/path/to/uploads\",\n  \"download_directory\": \"/path/to/downloads\"\n}}",
-                config_path.display()
-            ).into());
+    /// Build the configuration from its layers: built-in defaults, the config
+    /// file and the selected profile in it, `GRPC_FILES_*` environment
+    /// variables, then command-line flags. Each layer replaces whole settings
+    /// of the ones before it.
+    pub fn load_with(args: &ConfigArgs) -> Result<LoadedConfig, Box<dyn std::error::Error>> {
+        let defaults = default_settings();
+        let mut layers = Layers {
+            settings: defaults.clone(),
+            sources: defaults.keys().map(|key| (key.clone(), Source::Default)).collect(),
+            unknown: Vec::new(),
+        };
+
+        let file = match args.file.clone().or_else(|| std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from)) {
+            Some(path) if !path.exists() => {
+                return Err(format!("Config file not found at {}", path.display()).into());
+            }
+            Some(path) => Some(path),
+            None => find_config_file(),
+        };
+        let mut profile = args.profile.clone().or_else(|| std::env::var(PROFILE_ENV).ok());
+
+        if let Some(path) = &file {
+            let mut table = read_config_file(path)?;
+            let profiles = table.remove("profiles");
+            // The file may pick a profile for when none is asked for
+            if let Some(Value::String(name)) = table.remove("profile")
+                && profile.is_none()
+            {
+                profile = Some(name);
+            }
+            layers.apply(table, Source::File(path.clone()));
+
+            if let Some(name) = &profile {
+                let Some(Value::Object(overlay)) = profiles.as_ref().and_then(|p| p.get(name)).cloned() else {
+                    return Err(format!("No profile named '{}' in {}", name, path.display()).into());
+                };
+                layers.apply(overlay, Source::Profile(name.clone()));
+            }
+        } else if let Some(name) = &profile {
+            return Err(format!("Profile '{}' was asked for, but there is no config file", name).into());
+        }
+
+        for (var, raw) in std::env::vars() {
+            let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            if defaults.contains_key(&key) {
+                let value = parse_value(&defaults, &key, &raw);
+                layers.set(key, value, Source::Env(var.clone()));
+            }
+        }
+        for (key, raw) in &args.settings {
+            let flag = format!("--{}", key.replace('_', "-"));
+            layers.set(key.clone(), parse_value(&defaults, key, raw), Source::Flag(flag));
         }
 
-        let config_content = std::fs::read_to_string(&config_path)
-            .map_err(|e| format!("Failed to read config file: {}", e))?;
+        let config = layers.build(&defaults)?;
+        Ok(LoadedConfig {
+            config,
+            file,
+            profile,
+            sources: layers.sources,
+            unknown: layers.unknown,
+        })
+    }
 
-        let config: Config = serde_json::from_str(&config_content)
-            .map_err(|e| format!("Failed to parse config.json: {}", e))?;
+    /// The volumes the server should serve, falling back to `upload_directory`.
+    pub fn volumes(&self) -> Vec<VolumeConfig> {
+        if !self.volumes.is_empty() {
+            return self.volumes.clone();
+        }
+        vec![VolumeConfig {
+            name: crate::volume::DEFAULT_VOLUME.to_string(),
+            path: self.upload_directory.clone(),
+            read_only: false,
+            quota_bytes: None,
+        }]
+    }
 
-        Ok(config)
+    /// Directory for the client's own files, such as certificates and logs:
+    /// `$HOME/.file_server` if it exists, otherwise `$XDG_CONFIG_HOME/grpc-files`.
+    pub fn get_config_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if let Some(legacy) = legacy_config_dir()
+            && legacy.exists()
+        {
+            return Ok(legacy);
+        }
+        xdg_dir("XDG_CONFIG_HOME", ".config")
+            .map(|dir| dir.join("grpc-files"))
+            .ok_or_else(|| "Neither HOME nor XDG_CONFIG_HOME is set".into())
     }
 
-    pub fn get_auth_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
-        let home_dir = std::env::var("HOME")
-            .map_err(|_| "HOME environment variable not set")?;
-        let auth_dir = PathBuf::from(home_dir).join(".file_server").join("auth");
+    pub fn auth_dir(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let auth_dir = match &self.auth_directory {
+            Some(dir) => PathBuf::from(dir),
+            None => Self::get_config_dir()?.join("auth"),
+        };
 
         if !auth_dir.exists() {
             return Err(format!(
@@ -48,4 +400,254 @@ impl Config {
 
         Ok(auth_dir)
     }
+
+    /// The socket path when `server_connect_address` is a `unix:///path`
+    /// address, or `None` for a TCP address.
+    pub fn connect_socket_path(&self) -> Option<&Path> {
+        self.server_connect_address.strip_prefix("unix://").map(Path::new)
+    }
+
+    /// Path of a TLS file: the configured one, or `name` in the auth directory.
+    pub fn tls_file(&self, configured: &Option<String>, name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        match configured {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(self.auth_dir()?.join(name)),
+        }
+    }
+}
+
+/// Refuse plaintext on anything but a loopback address, where traffic never
+/// leaves the machine.
+pub fn check_plaintext(address: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !is_loopback(address) {
+        return Err(format!(
+            "plaintext is only allowed on a loopback address such as 127.0.0.1, not {}",
+            address
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Whether a `host:port` address is on the local machine only.
+fn is_loopback(address: &str) -> bool {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host == "localhost" || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Profile(String),
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Profile(name) => write!(f, "profile {}", name),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// Configuration taken from the command line: which file and profile to use,
+/// and settings given as flags.
+#[derive(Debug, Default)]
+pub struct ConfigArgs {
+    pub file: Option<PathBuf>,
+    pub profile: Option<String>,
+    settings: Vec<(String, String)>,
+}
+
+impl ConfigArgs {
+    /// Take `--config <file>`, `--profile <name>` and `--<setting> <value>`
+    /// out of `args`, returning the arguments that are left. Settings are
+    /// written with dashes, as in `--log-level debug`, and `--name=value`
+    /// works too.
+    pub fn parse(args: Vec<String>) -> Result<(Self, Vec<String>), Box<dyn std::error::Error>> {
+        let known = default_settings();
+        let mut parsed = ConfigArgs::default();
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                rest.push(arg);
+                continue;
+            };
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let key = name.replace('-', "_");
+            if key != "config" && key != "profile" && !known.contains_key(&key) {
+                rest.push(arg);
+                continue;
+            }
+            let value = match inline.or_else(|| args.next()) {
+                Some(value) => value,
+                None => return Err(format!("--{} needs a value", name).into()),
+            };
+            match key.as_str() {
+                "config" => parsed.file = Some(PathBuf::from(value)),
+                "profile" => parsed.profile = Some(value),
+                _ => parsed.settings.push((key, value)),
+            }
+        }
+        Ok((parsed, rest))
+    }
+}
+
+/// The effective configuration, with where it was loaded from and where
+/// each setting came from. Its `Display` output is what `config check` prints.
+pub struct LoadedConfig {
+    pub config: Config,
+    pub file: Option<PathBuf>,
+    pub profile: Option<String>,
+    sources: BTreeMap<String, Source>,
+    /// Keys in the file that are not settings, such as misspelt ones.
+    pub unknown: Vec<(String, Source)>,
+}
+
+impl fmt::Display for LoadedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(path) => writeln!(f, "Config file: {}", path.display())?,
+            None => writeln!(f, "Config file: none found, using defaults")?,
+        }
+        writeln!(f, "Profile: {}", self.profile.as_deref().unwrap_or("none"))?;
+        writeln!(f)?;
+
+        let Ok(Value::Object(settings)) = serde_json::to_value(&self.config) else {
+            return Err(fmt::Error);
+        };
+        let width = settings.keys().map(String::len).max().unwrap_or(0);
+        for (key, value) in &settings {
+            let source = self.sources.get(key).unwrap_or(&Source::Default);
+            writeln!(f, "{:<width$} = {}  ({})", key, value, source, width = width)?;
+        }
+
+        for (key, source) in &self.unknown {
+            writeln!(f, "warning: unknown setting '{}' in {}", key, source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings as they are merged, layer by layer.
+struct Layers {
+    settings: Map<String, Value>,
+    sources: BTreeMap<String, Source>,
+    unknown: Vec<(String, Source)>,
+}
+
+impl Layers {
+    fn set(&mut self, key: String, value: Value, source: Source) {
+        self.settings.insert(key.clone(), value);
+        self.sources.insert(key, source);
+    }
+
+    /// Apply the settings from a file or profile, noting keys that are not settings.
+    fn apply(&mut self, table: Map<String, Value>, source: Source) {
+        for (key, value) in table {
+            if self.sources.contains_key(&key) {
+                self.set(key, value, source.clone());
+            } else {
+                self.unknown.push((key, source.clone()));
+            }
+        }
+    }
+
+    /// Turn the merged settings into a `Config`. If that fails, find the first
+    /// setting that is to blame so the error can say where it came from.
+    fn build(&self, defaults: &Map<String, Value>) -> Result<Config, Box<dyn std::error::Error>> {
+        let error = match serde_json::from_value(Value::Object(self.settings.clone())) {
+            Ok(config) => return Ok(config),
+            Err(e) => e,
+        };
+        for (key, source) in &self.sources {
+            if *source == Source::Default {
+                continue;
+            }
+            let mut single = defaults.clone();
+            single.insert(key.clone(), self.settings[key].clone());
+            if let Err(e) = serde_json::from_value::<Config>(Value::Object(single)) {
+                return Err(format!("Invalid value for {} from {}: {}", key, source, e).into());
+            }
+        }
+        Err(format!("Invalid configuration: {}", error).into())
+    }
+}
+
+/// Every setting with its built-in default.
+fn default_settings() -> Map<String, Value> {
+    match serde_json::to_value(Config::default()) {
+        Ok(Value::Object(settings)) => settings,
+        _ => unreachable!("Config serializes to an object"),
+    }
+}
+
+/// Interpret a value for `key` from the environment or the command line.
+/// Settings that take text take it as it is, going by the type of their field
+/// rather than their default, so an all-digit server name stays a name and
+/// `null` does not unset a path. Anything else is read as JSON, so numbers,
+/// `true`, `null` and lists work, falling back to plain text.
+fn parse_value(defaults: &Map<String, Value>, key: &str, raw: &str) -> Value {
+    let text = Value::String(raw.to_string());
+    let mut settings = defaults.clone();
+    settings.insert(key.to_string(), text.clone());
+    if serde_json::from_value::<Config>(Value::Object(settings)).is_ok() {
+        return text;
+    }
+    serde_json::from_str(raw).unwrap_or(text)
+}
+
+/// The first config file found: `config.toml` or `config.json` in
+/// `$XDG_CONFIG_HOME/grpc-files`, then the older `$HOME/.file_server/config.json`.
+fn find_config_file() -> Option<PathBuf> {
+    let xdg = xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("grpc-files"));
+    let candidates = [
+        xdg.as_ref().map(|dir| dir.join("config.toml")),
+        xdg.as_ref().map(|dir| dir.join("config.json")),
+        legacy_config_dir().map(|dir| dir.join("config.json")),
+    ];
+    candidates.into_iter().flatten().find(|path| path.exists())
+}
+
+/// Read a TOML or JSON config file, going by its extension.
+fn read_config_file(path: &Path) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let value = if path.extension().is_some_and(|ext| ext == "toml") {
+        let table: toml::Table = toml::from_str(&content)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        serde_json::to_value(table)?
+    } else {
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?
+    };
+    match value {
+        Value::Object(table) => Ok(table),
+        _ => Err(format!("{} must hold a table of settings", path.display()).into()),
+    }
+}
+
+fn legacy_config_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".file_server"))
+}
+
+/// An XDG base directory from `var`, or `$HOME/<fallback>` when it is unset.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
 }
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::io::{self, Read, SeekFrom};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::fileservice::{BlockRange, BlockSignature, DeltaOp, DeltaTrailer, delta_op::Op};
+
+/// Number of bytes of the strong hash kept per block signature.
+pub const STRONG_HASH_LEN: usize = 16;
+/// Maximum bytes of literal data batched into a single delta chunk.
+pub const MAX_LITERAL_LEN: usize = 1024 * 1024;
+/// Maximum number of operations batched into a single delta chunk.
+const MAX_OPS_PER_CHUNK: usize = 1024;
+/// Keep the number of signatures low enough to fit comfortably in one message.
+const MAX_BLOCKS: u64 = 64 * 1024;
+const MIN_BLOCK_SIZE: u64 = 2 * 1024;
+const MAX_BLOCK_SIZE: u64 = 1024 * 1024;
+/// Drop already-processed bytes from the delta buffer once it grows past this.
+const COMPACT_THRESHOLD: usize = 4 * 1024 * 1024;
+
+/// Whether a block size sent by a peer is in the range [`block_size_for`]
+/// picks from. Larger ones would make buffers of that size.
+pub fn valid_block_size(block_size: u32) -> bool {
+    (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&u64::from(block_size))
+}
+
+/// Pick a block size for a file: roughly the square root of its size, so the
+/// signature list and the expected literal data stay balanced.
+pub fn block_size_for(file_size: u64) -> u32 {
+    let sqrt = (file_size as f64).sqrt() as u64;
+    let size = sqrt.max(file_size.div_ceil(MAX_BLOCKS));
+    // Round up to a multiple of 1 KiB
+    let size = size.div_ceil(1024) * 1024;
+    size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE) as u32
+}
+
+/// rsync-style rolling checksum over a fixed-size window.
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, &byte) in window.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        Rolling { a, b, len }
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+
+    /// Slide the window one byte forward.
+    fn roll(&mut self, out: u8, inn: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inn as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+}
+
+pub fn weak_checksum(data: &[u8]) -> u32 {
+    Rolling::new(data).digest()
+}
+
+pub fn strong_hash(data: &[u8]) -> Vec<u8> {
+    blake3::hash(data).as_bytes()[..STRONG_HASH_LEN].to_vec()
+}
+
+/// Read until `buf` is full or the reader is exhausted.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Compute the signature of every block of an existing file.
+pub fn signatures<R: Read>(mut reader: R, block_size: u32) -> io::Result<Vec<BlockSignature>> {
+    let mut buffer = vec![0u8; block_size as usize];
+    let mut blocks = Vec::new();
+    loop {
+        let n = read_full(&mut reader, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        blocks.push(BlockSignature {
+            weak: weak_checksum(&buffer[..n]),
+            strong: strong_hash(&buffer[..n]),
+        });
+        if n < buffer.len() {
+            break;
+        }
+    }
+    Ok(blocks)
+}
+
+/// Groups delta operations into chunk-sized batches, merging runs of copied blocks.
+struct OpBatch<F> {
+    ops: Vec<DeltaOp>,
+    literal_bytes: usize,
+    pending_copy: Option<BlockRange>,
+    emit: F,
+}
+
+impl<F: FnMut(Vec<DeltaOp>) -> io::Result<()>> OpBatch<F> {
+    fn new(emit: F) -> Self {
+        OpBatch {
+            ops: Vec::new(),
+            literal_bytes: 0,
+            pending_copy: None,
+            emit,
+        }
+    }
+
+    fn copy(&mut self, block: u64) -> io::Result<()> {
+        if let Some(range) = &mut self.pending_copy
+            && range.start + range.count == block
+        {
+            range.count += 1;
+            return Ok(());
+        }
+        self.flush_copy()?;
+        self.pending_copy = Some(BlockRange {
+            start: block,
+            count: 1,
+        });
+        Ok(())
+    }
+
+    fn literal(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.flush_copy()?;
+        self.ops.push(DeltaOp {
+            op: Some(Op::Literal(data.to_vec())),
+        });
+        self.literal_bytes += data.len();
+        if self.literal_bytes >= MAX_LITERAL_LEN || self.ops.len() >= MAX_OPS_PER_CHUNK {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush_copy(&mut self) -> io::Result<()> {
+        if let Some(range) = self.pending_copy.take() {
+            self.ops.push(DeltaOp {
+                op: Some(Op::Copy(range)),
+            });
+            if self.ops.len() >= MAX_OPS_PER_CHUNK {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.ops.is_empty() {
+            (self.emit)(std::mem::take(&mut self.ops))?;
+            self.literal_bytes = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.flush_copy()?;
+        self.flush()
+    }
+}
+
+/// Compare a new file against the signatures of an old one and emit the
+/// operations needed to rebuild the new file from the old.
+///
+/// Operations are passed to `emit` in batches as the input is read, so the
+/// new file never has to be held in memory. Returns the size and hash of the
+/// new file for the receiver to verify against.
+pub fn compute_delta<R: Read>(
+    mut reader: R,
+    block_size: u32,
+    signatures: &[BlockSignature],
+    emit: impl FnMut(Vec<DeltaOp>) -> io::Result<()>,
+) -> io::Result<DeltaTrailer> {
+    let block_size = block_size as usize;
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, sig) in signatures.iter().enumerate() {
+        index.entry(sig.weak).or_default().push(i);
+    }
+
+    let mut batch = OpBatch::new(emit);
+    let mut hasher = blake3::Hasher::new();
+    let mut file_size = 0u64;
+    let mut read_buf = vec![0u8; 256 * 1024];
+
+    // Nothing to match against, so the whole file is literal data
+    if index.is_empty() || block_size == 0 {
+        loop {
+            let n = read_full(&mut reader, &mut read_buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&read_buf[..n]);
+            file_size += n as u64;
+            batch.literal(&read_buf[..n])?;
+        }
+        batch.finish()?;
+        return Ok(DeltaTrailer {
+            file_size,
+            file_hash: hasher.finalize().as_bytes().to_vec(),
+        });
+    }
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut eof = false;
+    let mut pos = 0;
+    let mut literal_start = 0;
+    let mut rolling: Option<Rolling> = None;
+
+    loop {
+        // Keep a full window plus the next byte buffered so the window can roll
+        while !eof && buf.len() < pos + block_size + 1 {
+            let n = reader.read(&mut read_buf)?;
+            if n == 0 {
+                eof = true;
+            } else {
+                hasher.update(&read_buf[..n]);
+                file_size += n as u64;
+                buf.extend_from_slice(&read_buf[..n]);
+            }
+        }
+        if buf.len() < pos + block_size {
+            break;
+        }
+
+        let window = &buf[pos..pos + block_size];
+        let checksum = rolling.get_or_insert_with(|| Rolling::new(window));
+        let matched = index.get(&checksum.digest()).and_then(|candidates| {
+            let strong = strong_hash(window);
+            candidates
+                .iter()
+                .copied()
+                .find(|&i| signatures[i].strong == strong)
+        });
+
+        if let Some(block) = matched {
+            batch.literal(&buf[literal_start..pos])?;
+            batch.copy(block as u64)?;
+            pos += block_size;
+            literal_start = pos;
+            rolling = None;
+        } else if buf.len() == pos + block_size {
+            // At the end of the input with no further byte to roll in
+            break;
+        } else {
+            checksum.roll(buf[pos], buf[pos + block_size]);
+            pos += 1;
+            if pos - literal_start >= MAX_LITERAL_LEN {
+                batch.literal(&buf[literal_start..pos])?;
+                literal_start = pos;
+            }
+        }
+
+        if literal_start >= COMPACT_THRESHOLD {
+            buf.drain(..literal_start);
+            pos -= literal_start;
+            literal_start = 0;
+        }
+    }
+
+    batch.literal(&buf[literal_start..])?;
+    batch.finish()?;
+
+    Ok(DeltaTrailer {
+        file_size,
+        file_hash: hasher.finalize().as_bytes().to_vec(),
+    })
+}
+
+/// Rebuilds a file from delta operations, reading copied blocks from the old copy.
+pub struct DeltaApplier {
+    block_size: u64,
+    hasher: blake3::Hasher,
+    written: u64,
+    buffer: Vec<u8>,
+}
+
+impl DeltaApplier {
+    pub fn new(block_size: u32) -> Self {
+        DeltaApplier {
+            block_size: block_size as u64,
+            hasher: blake3::Hasher::new(),
+            written: 0,
+            buffer: vec![0u8; 256 * 1024],
+        }
+    }
+
+    pub async fn apply<B, W>(&mut self, basis: Option<&mut B>, out: &mut W, op: DeltaOp) -> io::Result<()>
+    where
+        B: AsyncRead + AsyncSeek + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        match op.op {
+            Some(Op::Literal(data)) => {
+                out.write_all(&data).await?;
+                self.hasher.update(&data);
+                self.written += data.len() as u64;
+            }
+            Some(Op::Copy(range)) => {
+                let basis = basis.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Delta references a missing file")
+                })?;
+                if self.block_size == 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Block size is zero"));
+                }
+                if range.count == 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Delta copies no blocks"));
+                }
+                let beyond_end = || {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Delta references blocks beyond the end of the file",
+                    )
+                };
+                let offset = range.start.checked_mul(self.block_size).ok_or_else(beyond_end)?;
+                let length = range.count.checked_mul(self.block_size).ok_or_else(beyond_end)?;
+
+                basis.seek(SeekFrom::Start(offset)).await?;
+                let mut remaining = length;
+                let mut copied = 0u64;
+                while remaining > 0 {
+                    let want = remaining.min(self.buffer.len() as u64) as usize;
+                    let n = basis.read(&mut self.buffer[..want]).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    out.write_all(&self.buffer[..n]).await?;
+                    self.hasher.update(&self.buffer[..n]);
+                    remaining -= n as u64;
+                    copied += n as u64;
+                }
+
+                // Only the final block of the old file may be short
+                if copied <= length - self.block_size {
+                    return Err(beyond_end());
+                }
+                self.written += copied;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Bytes written to the rebuilt file so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Check the rebuilt file against the sender's size and hash.
+    pub fn verify(self, trailer: &DeltaTrailer) -> io::Result<u64> {
+        if self.written != trailer.file_size
+            || self.hasher.finalize().as_bytes()[..] != trailer.file_hash[..]
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Rebuilt file does not match the sender's checksum",
+            ));
+        }
+        Ok(self.written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BLOCK: u32 = 2048;
+
+    /// Deterministic bytes that don't repeat within a block.
+    fn data(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// Rebuild `new` from `old` through a delta, returning the literal bytes sent.
+    async fn round_trip(old: &[u8], new: &[u8]) -> u64 {
+        let blocks = signatures(old, BLOCK).unwrap();
+        let mut ops = Vec::new();
+        let trailer = compute_delta(new, BLOCK, &blocks, |batch| {
+            ops.extend(batch);
+            Ok(())
+        })
+        .unwrap();
+
+        let mut basis = Cursor::new(old.to_vec());
+        let mut out = Vec::new();
+        let mut applier = DeltaApplier::new(BLOCK);
+        let mut literal = 0;
+        for op in ops {
+            if let Some(Op::Literal(data)) = &op.op {
+                literal += data.len() as u64;
+            }
+            applier.apply(Some(&mut basis), &mut out, op).await.unwrap();
+        }
+        assert_eq!(applier.verify(&trailer).unwrap(), new.len() as u64);
+        assert_eq!(out, new);
+        literal
+    }
+
+    async fn apply_copy(start: u64, count: u64) -> io::Result<()> {
+        let mut basis = Cursor::new(data(4 * BLOCK as usize, 1));
+        let op = DeltaOp {
+            op: Some(Op::Copy(BlockRange { start, count })),
+        };
+        DeltaApplier::new(BLOCK).apply(Some(&mut basis), &mut Vec::new(), op).await
+    }
+
+    #[tokio::test]
+    async fn unchanged_file_sends_nothing() {
+        let old = data(10 * BLOCK as usize, 1);
+        assert_eq!(round_trip(&old, &old).await, 0);
+    }
+
+    #[tokio::test]
+    async fn insert_sends_only_the_new_bytes() {
+        let old = data(10 * BLOCK as usize, 1);
+        let mut new = old.clone();
+        new.splice(5 * BLOCK as usize..5 * BLOCK as usize, data(100, 2));
+        assert_eq!(round_trip(&old, &new).await, 100);
+    }
+
+    #[tokio::test]
+    async fn delete_sends_the_broken_block() {
+        let old = data(10 * BLOCK as usize, 1);
+        let mut new = old.clone();
+        new.drain(3 * BLOCK as usize + 10..3 * BLOCK as usize + 20);
+        assert_eq!(round_trip(&old, &new).await, u64::from(BLOCK) - 10);
+    }
+
+    #[tokio::test]
+    async fn shifted_data_is_still_matched() {
+        let old = data(10 * BLOCK as usize, 1);
+        let mut new = data(7, 3);
+        new.extend_from_slice(&old);
+        assert_eq!(round_trip(&old, &new).await, 7);
+    }
+
+    #[tokio::test]
+    async fn short_final_block_round_trips() {
+        // Only whole blocks are matched, so the old file's short tail is resent
+        let old = data(5 * BLOCK as usize + 300, 1);
+        let mut new = data(BLOCK as usize, 4);
+        new.extend_from_slice(&old);
+        assert_eq!(round_trip(&old, &new).await, u64::from(BLOCK) + 300);
+    }
+
+    #[tokio::test]
+    async fn new_file_is_all_literal() {
+        let new = data(3 * BLOCK as usize + 5, 5);
+        assert_eq!(round_trip(&[], &new).await, new.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_copies() {
+        assert!(apply_copy(0, 4).await.is_ok());
+        for (start, count) in [(0, 0), (4, 1), (2, 3), (u64::MAX, 1), (0, u64::MAX)] {
+            let error = apply_copy(start, count).await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{} {}", start, count);
+        }
+    }
+
+    #[test]
+    fn block_sizes_stay_in_range() {
+        for size in [0, 1, 1 << 20, 1 << 40, u64::MAX] {
+            assert!(valid_block_size(block_size_for(size)));
+        }
+        assert!(!valid_block_size(0));
+        assert!(!valid_block_size(u32::MAX));
+    }
+}
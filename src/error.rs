@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use tonic::Code;
+use tonic_types::{ErrorDetails, StatusExt};
+
+use crate::sandbox::PathError;
+
+/// Domain of the `google.rpc.ErrorInfo` attached to the file service's errors.
+pub const ERROR_DOMAIN: &str = "grpc-files";
+
+/// Why a file service call failed. Sent as the reason of a
+/// `google.rpc.ErrorInfo`, so clients can tell failures apart without
+/// reading messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    NotFound,
+    NoSuchVolume,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    InvalidRequest,
+    Unauthenticated,
+    PermissionDenied,
+    ReadOnly,
+    QuotaExceeded,
+    StorageFull,
+    TooLarge,
+    SizeMismatch,
+    RateLimited,
+    Busy,
+    UploadExpired,
+    Corrupt,
+    OutOfRange,
+    Internal,
+}
+
+const REASONS: &[(Reason, &str, Code)] = &[
+    (Reason::NotFound, "NOT_FOUND", Code::NotFound),
+    (Reason::NoSuchVolume, "NO_SUCH_VOLUME", Code::NotFound),
+    (Reason::AlreadyExists, "ALREADY_EXISTS", Code::AlreadyExists),
+    (Reason::NotADirectory, "NOT_A_DIRECTORY", Code::FailedPrecondition),
+    (Reason::IsADirectory, "IS_A_DIRECTORY", Code::FailedPrecondition),
+    (Reason::DirectoryNotEmpty, "DIRECTORY_NOT_EMPTY", Code::FailedPrecondition),
+    (Reason::InvalidPath, "INVALID_PATH", Code::InvalidArgument),
+    (Reason::InvalidRequest, "INVALID_REQUEST", Code::InvalidArgument),
+    (Reason::Unauthenticated, "UNAUTHENTICATED", Code::Unauthenticated),
+    (Reason::PermissionDenied, "PERMISSION_DENIED", Code::PermissionDenied),
+    (Reason::ReadOnly, "READ_ONLY", Code::PermissionDenied),
+    (Reason::QuotaExceeded, "QUOTA_EXCEEDED", Code::ResourceExhausted),
+    (Reason::StorageFull, "STORAGE_FULL", Code::ResourceExhausted),
+    (Reason::TooLarge, "TOO_LARGE", Code::FailedPrecondition),
+    (Reason::SizeMismatch, "SIZE_MISMATCH", Code::InvalidArgument),
+    (Reason::RateLimited, "RATE_LIMITED", Code::ResourceExhausted),
+    (Reason::Busy, "BUSY", Code::ResourceExhausted),
+    (Reason::UploadExpired, "UPLOAD_EXPIRED", Code::Aborted),
+    (Reason::Corrupt, "CORRUPT", Code::DataLoss),
+    (Reason::OutOfRange, "OUT_OF_RANGE", Code::OutOfRange),
+    (Reason::Internal, "INTERNAL", Code::Internal),
+];
+
+impl Reason {
+    /// The reason as it appears in `ErrorInfo`, such as `NOT_FOUND`.
+    pub fn as_str(self) -> &'static str {
+        REASONS.iter().find(|(r, _, _)| *r == self).map_or("INTERNAL", |(_, name, _)| name)
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        REASONS.iter().find(|(_, n, _)| *n == name).map(|(reason, _, _)| *reason)
+    }
+
+    /// The gRPC code errors with this reason are sent with.
+    pub fn code(self) -> Code {
+        REASONS.iter().find(|(r, _, _)| *r == self).map_or(Code::Internal, |(_, _, code)| *code)
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A failed file operation on the server. Paths are the ones the client
+/// sent, never locations in storage.
+#[derive(Debug)]
+pub enum FileError {
+    NotFound { path: String },
+    NoSuchVolume { volume: String },
+    AlreadyExists { path: String },
+    NotADirectory { path: String },
+    IsADirectory { path: String },
+    DirectoryNotEmpty { path: String },
+    InvalidPath { path: String, error: PathError },
+    InvalidRequest(String),
+    /// The caller has neither a certificate nor an access token.
+    Unauthenticated,
+    PermissionDenied { path: Option<String>, message: String },
+    ReadOnly { volume: String },
+    QuotaExceeded { volume: String, remaining: u64 },
+    /// The disk lacks room for an upload of the declared size.
+    NotEnoughSpace { needed: u64, available: u64 },
+    /// The disk filled up while writing.
+    StorageFull,
+    TooLarge { size: u64, limit: u64 },
+    /// An upload sent more or less than the size it declared.
+    SizeMismatch { declared: u64, received: u64 },
+    RateLimited { retry_after: Duration },
+    /// No transfer slot came free in time; `limit` says whose cap was reached.
+    Busy { limit: &'static str, retry_after: Duration },
+    /// The staged upload was swept away while the client was idle.
+    UploadExpired,
+    Corrupt(String),
+    OutOfRange { offset: u64, size: u64 },
+    /// Storage failed; `action` says what was being done to `path`.
+    Io { action: &'static str, path: String, source: io::Error },
+    Internal(String),
+}
+
+impl FileError {
+    pub fn not_found(path: impl fmt::Display) -> Self {
+        FileError::NotFound { path: path.to_string() }
+    }
+
+    pub fn io(action: &'static str, path: impl fmt::Display, source: io::Error) -> Self {
+        FileError::Io { action, path: path.to_string(), source }
+    }
+
+    pub fn reason(&self) -> Reason {
+        match self {
+            FileError::NotFound { .. } => Reason::NotFound,
+            FileError::NoSuchVolume { .. } => Reason::NoSuchVolume,
+            FileError::AlreadyExists { .. } => Reason::AlreadyExists,
+            FileError::NotADirectory { .. } => Reason::NotADirectory,
+            FileError::IsADirectory { .. } => Reason::IsADirectory,
+            FileError::DirectoryNotEmpty { .. } => Reason::DirectoryNotEmpty,
+            // Paths that only go wrong once resolved are refused rather than invalid
+            FileError::InvalidPath { error: PathError::Escapes | PathError::Symlink, .. } => {
+                Reason::PermissionDenied
+            }
+            FileError::InvalidPath { .. } => Reason::InvalidPath,
+            FileError::InvalidRequest(_) => Reason::InvalidRequest,
+            FileError::Unauthenticated => Reason::Unauthenticated,
+            FileError::PermissionDenied { .. } => Reason::PermissionDenied,
+            FileError::ReadOnly { .. } => Reason::ReadOnly,
+            FileError::QuotaExceeded { .. } => Reason::QuotaExceeded,
+            FileError::NotEnoughSpace { .. } | FileError::StorageFull => Reason::StorageFull,
+            FileError::TooLarge { .. } => Reason::TooLarge,
+            FileError::SizeMismatch { .. } => Reason::SizeMismatch,
+            FileError::RateLimited { .. } => Reason::RateLimited,
+            FileError::Busy { .. } => Reason::Busy,
+            FileError::UploadExpired => Reason::UploadExpired,
+            FileError::Corrupt(_) => Reason::Corrupt,
+            FileError::OutOfRange { .. } => Reason::OutOfRange,
+            FileError::Io { .. } | FileError::Internal(_) => Reason::Internal,
+        }
+    }
+
+    /// The path the error is about, if there is one.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            FileError::NotFound { path }
+            | FileError::AlreadyExists { path }
+            | FileError::NotADirectory { path }
+            | FileError::IsADirectory { path }
+            | FileError::DirectoryNotEmpty { path }
+            | FileError::InvalidPath { path, .. }
+            | FileError::Io { path, .. } => Some(path),
+            FileError::PermissionDenied { path, .. } => path.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn volume(&self) -> Option<&str> {
+        match self {
+            FileError::NoSuchVolume { volume }
+            | FileError::ReadOnly { volume }
+            | FileError::QuotaExceeded { volume, .. } => Some(volume),
+            _ => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FileError::RateLimited { retry_after } | FileError::Busy { retry_after, .. } => {
+                Some(*retry_after)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The root of a volume or home directory is the empty path.
+fn shown(path: &str) -> &str {
+    if path.is_empty() { "/" } else { path }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::NotFound { path } => write!(f, "'{}' does not exist", shown(path)),
+            FileError::NoSuchVolume { volume } => write!(f, "No volume named '{}'", volume),
+            FileError::AlreadyExists { path } => write!(f, "'{}' already exists", shown(path)),
+            FileError::NotADirectory { path } => write!(f, "'{}' is not a directory", shown(path)),
+            FileError::IsADirectory { path } => write!(f, "'{}' is a directory", shown(path)),
+            FileError::DirectoryNotEmpty { path } => {
+                write!(f, "Directory '{}' is not empty; delete it recursively", shown(path))
+            }
+            FileError::InvalidPath { path, error } => write!(f, "{}: '{}'", error, path),
+            FileError::InvalidRequest(message) | FileError::Corrupt(message) | FileError::Internal(message) => {
+                f.write_str(message)
+            }
+            FileError::Unauthenticated => {
+                write!(f, "A client certificate or access token is required")
+            }
+            FileError::PermissionDenied { message, .. } => f.write_str(message),
+            FileError::ReadOnly { volume } => write!(f, "Volume '{}' is read-only", volume),
+            FileError::QuotaExceeded { volume, remaining } => {
+                write!(f, "Volume '{}' is over its quota: {} bytes left", volume, remaining)
+            }
+            FileError::NotEnoughSpace { needed, available } => write!(
+                f,
+                "Not enough free space on the server: {} bytes needed, {} available",
+                needed, available
+            ),
+            FileError::StorageFull => write!(f, "The server's storage is full"),
+            FileError::TooLarge { size, limit } => write!(
+                f,
+                "Upload of {} bytes is over the server's {} byte upload limit",
+                size, limit
+            ),
+            FileError::SizeMismatch { declared, received } if received > declared => {
+                write!(f, "Upload sent more than its declared size of {} bytes", declared)
+            }
+            FileError::SizeMismatch { declared, received } => {
+                write!(f, "Upload ended after {} of {} declared bytes", received, declared)
+            }
+            FileError::RateLimited { .. } => write!(f, "Request rate limit exceeded"),
+            FileError::Busy { limit, .. } => {
+                write!(f, "Too many concurrent transfers ({} limit reached)", limit)
+            }
+            FileError::UploadExpired => {
+                write!(f, "Upload was idle for too long and has been discarded")
+            }
+            FileError::OutOfRange { offset, size } => {
+                write!(f, "Offset {} is past the end of the file ({} bytes)", offset, size)
+            }
+            FileError::Io { action, path, source } => {
+                write!(f, "Failed to {} '{}': {}", action, shown(path), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FileError::InvalidPath { error, .. } => Some(error),
+            FileError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The status sent to the client, carrying the reason, path and volume in a
+/// `google.rpc.ErrorInfo` and any wait before retrying in a `google.rpc.RetryInfo`.
+impl From<FileError> for tonic::Status {
+    fn from(error: FileError) -> Self {
+        let reason = error.reason();
+        let mut metadata = HashMap::new();
+        if let Some(path) = error.path() {
+            metadata.insert("path".to_string(), path.to_string());
+        }
+        if let Some(volume) = error.volume() {
+            metadata.insert("volume".to_string(), volume.to_string());
+        }
+        let mut details = ErrorDetails::with_error_info(reason.as_str(), ERROR_DOMAIN, metadata);
+        if let Some(retry_after) = error.retry_after() {
+            details.set_retry_info(Some(retry_after));
+        }
+        tonic::Status::with_error_details(reason.code(), error.to_string(), details)
+    }
+}
+
+/// An error status from the file service with its details decoded.
+#[derive(Debug, Clone)]
+pub struct ServerError {
+    pub code: Code,
+    /// Missing when the status did not come from the file service itself,
+    /// for example from a proxy.
+    pub reason: Option<Reason>,
+    pub message: String,
+    pub path: Option<String>,
+    pub volume: Option<String>,
+    /// How long the server asks to wait before trying again.
+    pub retry_after: Option<Duration>,
+}
+
+impl From<&tonic::Status> for ServerError {
+    fn from(status: &tonic::Status) -> Self {
+        let details = status.get_error_details();
+        let info = details.error_info().filter(|info| info.domain == ERROR_DOMAIN);
+        let field = |key: &str| info.and_then(|info| info.metadata.get(key).cloned());
+        ServerError {
+            code: status.code(),
+            reason: info.and_then(|info| Reason::parse(&info.reason)),
+            message: status.message().to_string(),
+            path: field("path"),
+            volume: field("volume"),
+            retry_after: details.retry_info().and_then(|info| info.retry_delay),
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(wait) = self.retry_after {
+            // Round up, so the advice is never to retry too early
+            write!(f, ", try again in {}s", wait.as_secs() + u64::from(wait.subsec_nanos() > 0))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ServerError {}
@@ -0,0 +1,591 @@
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, Multipart, Query, State};
+use axum::http::{Extensions, HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::Code;
+#[cfg(unix)]
+use tonic::transport::server::UdsConnectInfo;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tower::Service;
+use tower::util::BoxCloneSyncService;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::error::{FileError, Reason, ServerError};
+use crate::fileservice::file_service_client::FileServiceClient;
+use crate::fileservice::{
+    CreateDirectoryRequest, DeleteDirectoryRequest, DeleteRequest, DownloadRequest, FileInfo,
+    ListRequest, ListVolumesRequest, StatRequest, UploadChunk,
+};
+use crate::identity::{PinnedKeyIdentity, SshIdentity, TokenIdentity};
+
+/// The file service, called in-process instead of over a connection.
+pub(crate) type LocalFileService = BoxCloneSyncService<
+    http::Request<tonic::body::Body>,
+    http::Response<tonic::body::Body>,
+    Infallible,
+>;
+
+/// REST/JSON routes under `/api/v1` for browsers and scripts that cannot
+/// speak gRPC. Each route calls `service`, the same file service gRPC clients
+/// use, in-process and as the caller, so paths, permissions, limits and
+/// metrics behave the same either way.
+pub fn router<S>(service: S) -> Router
+where
+    S: Service<
+            http::Request<tonic::body::Body>,
+            Response = http::Response<tonic::body::Body>,
+            Error = Infallible,
+        > + Clone
+        + Send
+        + Sync
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let gateway = Gateway {
+        service: BoxCloneSyncService::new(service),
+    };
+    Router::new()
+        .route("/api/v1/volumes", get(volumes))
+        .route("/api/v1/list", get(list))
+        .route("/api/v1/stat", get(stat))
+        .route("/api/v1/mkdir", post(mkdir))
+        .route(
+            "/api/v1/files",
+            get(download).put(upload).post(upload_form).delete(delete),
+        )
+        // Upload size is up to the server's own limits
+        .layer(DefaultBodyLimit::disable())
+        .with_state(gateway)
+}
+
+#[derive(Clone)]
+struct Gateway {
+    service: LocalFileService,
+}
+
+impl Gateway {
+    fn client(&self) -> FileServiceClient<LocalFileService> {
+        FileServiceClient::new(self.service.clone())
+    }
+
+    /// Upload `data` as `name` in `directory`, feeding it to the Upload RPC as
+    /// it arrives. If the data stream fails, the RPC is dropped unfinished so
+    /// no partial file is kept.
+    async fn upload<S, E>(
+        &self,
+        extensions: &Extensions,
+        volume: &str,
+        directory: &str,
+        name: &str,
+        size: u64,
+        data: S,
+    ) -> Result<Uploaded, ApiError>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: std::fmt::Display,
+    {
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let first = UploadChunk {
+            upload_id: upload_id.clone(),
+            filename: name.to_string(),
+            chunk_index: 0,
+            data: Vec::new(),
+            target_directory: directory.to_string(),
+            file_size: size,
+            volume: volume.to_string(),
+        };
+
+        let send = async move {
+            tokio::pin!(data);
+            if tx.send(first).await.is_err() {
+                return Ok(());
+            }
+            let mut chunk_index = 0;
+            while let Some(bytes) = data.next().await {
+                let bytes = bytes.map_err(|e| {
+                    tonic::Status::invalid_argument(format!("Failed to read the upload: {}", e))
+                })?;
+                chunk_index += 1;
+                let chunk = UploadChunk {
+                    upload_id: upload_id.clone(),
+                    chunk_index,
+                    data: bytes.to_vec(),
+                    ..Default::default()
+                };
+                // The RPC ended early; its error is what gets reported
+                if tx.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+            Ok::<_, ApiError>(())
+        };
+        let mut client = self.client();
+        let call = async {
+            client
+                .upload(forward(extensions, ReceiverStream::new(rx)))
+                .await
+                .map_err(ApiError::from)
+        };
+        let ((), response) = tokio::try_join!(send, call)?;
+
+        let response = response.into_inner();
+        Ok(Uploaded {
+            path: join(directory, &response.filename),
+            name: response.filename,
+            size: response.size,
+        })
+    }
+}
+
+/// A request to the file service carrying the caller's connection, token,
+/// pinned key or SSH key, which is where the service finds out who is calling.
+pub(crate) fn forward<T>(extensions: &Extensions, message: T) -> tonic::Request<T> {
+    fn copy<E: Clone + Send + Sync + 'static>(from: &Extensions, to: &mut Extensions) {
+        if let Some(value) = from.get::<E>() {
+            to.insert(value.clone());
+        }
+    }
+
+    let mut request = tonic::Request::new(message);
+    let to = request.extensions_mut();
+    copy::<TlsConnectInfo<TcpConnectInfo>>(extensions, to);
+    copy::<TcpConnectInfo>(extensions, to);
+    #[cfg(unix)]
+    copy::<UdsConnectInfo>(extensions, to);
+    copy::<TokenIdentity>(extensions, to);
+    copy::<PinnedKeyIdentity>(extensions, to);
+    copy::<SshIdentity>(extensions, to);
+    request
+}
+
+fn join(directory: &str, name: &str) -> String {
+    match directory.trim_end_matches('/') {
+        "" => name.to_string(),
+        directory => format!("{}/{}", directory, name),
+    }
+}
+
+/// A file service error as an HTTP status and a JSON body, following the
+/// usual mapping of gRPC codes to HTTP. The body carries the error's reason
+/// and path, and a wait the server asks for becomes `Retry-After`.
+struct ApiError(tonic::Status);
+
+impl From<tonic::Status> for ApiError {
+    fn from(status: tonic::Status) -> Self {
+        ApiError(status)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let error = ServerError::from(&self.0);
+        let status = match (error.reason, error.code) {
+            (Some(Reason::TooLarge), _) => StatusCode::PAYLOAD_TOO_LARGE,
+            (Some(Reason::QuotaExceeded | Reason::StorageFull), _) => StatusCode::INSUFFICIENT_STORAGE,
+            (_, code) => http_status(code),
+        };
+        let mut body = serde_json::json!({ "error": error.message });
+        if let Some(reason) = error.reason {
+            body["reason"] = reason.as_str().into();
+        }
+        if let Some(path) = &error.path {
+            body["path"] = path.as_str().into();
+        }
+        let mut response = (status, Json(body)).into_response();
+        if let Some(wait) = error.retry_after {
+            // Whole seconds, rounded up so clients never come back too early
+            let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(Deserialize)]
+struct PathQuery {
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    volume: String,
+    /// For deletes: remove a directory and everything in it.
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    volume: String,
+    #[serde(default)]
+    show_hidden: bool,
+    #[serde(default)]
+    glob: String,
+    #[serde(default)]
+    page_size: u32,
+    #[serde(default)]
+    page_token: String,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    path: String,
+    size: u64,
+    is_directory: bool,
+    /// Unix time in seconds.
+    modified: Option<i64>,
+    tags: Vec<String>,
+    attributes: BTreeMap<String, String>,
+}
+
+impl From<FileInfo> for Entry {
+    fn from(info: FileInfo) -> Self {
+        Entry {
+            name: info.filename,
+            path: info.path,
+            size: info.size,
+            is_directory: info.is_directory,
+            modified: info.upload_time.map(|time| time.seconds),
+            tags: info.tags,
+            attributes: info.attributes.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Listing {
+    path: String,
+    entries: Vec<Entry>,
+    /// Empty when there are no more entries.
+    next_page_token: String,
+    total_entries: u64,
+}
+
+#[derive(Serialize)]
+struct Volume {
+    name: String,
+    read_only: bool,
+    quota_bytes: Option<u64>,
+    free_bytes: u64,
+    is_default: bool,
+}
+
+#[derive(Serialize)]
+struct Uploaded {
+    name: String,
+    path: String,
+    size: u64,
+}
+
+async fn volumes(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+) -> Result<Json<Vec<Volume>>, ApiError> {
+    let response = gateway
+        .client()
+        .list_volumes(forward(&extensions, ListVolumesRequest {}))
+        .await?
+        .into_inner();
+    let volumes = response
+        .volumes
+        .into_iter()
+        .map(|volume| Volume {
+            name: volume.name,
+            read_only: volume.read_only,
+            quota_bytes: (volume.quota_bytes > 0).then_some(volume.quota_bytes),
+            free_bytes: volume.free_bytes,
+            is_default: volume.is_default,
+        })
+        .collect();
+    Ok(Json(volumes))
+}
+
+async fn list(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Listing>, ApiError> {
+    let request = ListRequest {
+        path: query.path,
+        show_hidden: query.show_hidden,
+        name_glob: query.glob,
+        page_size: query.page_size,
+        page_token: query.page_token,
+        volume: query.volume,
+        ..Default::default()
+    };
+    let response = gateway
+        .client()
+        .list_files(forward(&extensions, request))
+        .await?
+        .into_inner();
+    Ok(Json(Listing {
+        path: response.current_path,
+        entries: response.files.into_iter().map(Entry::from).collect(),
+        next_page_token: response.next_page_token,
+        total_entries: response.total_entries,
+    }))
+}
+
+async fn stat_info(
+    gateway: &Gateway,
+    extensions: &Extensions,
+    query: &PathQuery,
+) -> Result<FileInfo, ApiError> {
+    let request = StatRequest {
+        path: query.path.clone(),
+        volume: query.volume.clone(),
+    };
+    let response = gateway.client().stat(forward(extensions, request)).await?;
+    Ok(response.into_inner().info.unwrap_or_default())
+}
+
+async fn stat(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    Query(query): Query<PathQuery>,
+) -> Result<Json<Entry>, ApiError> {
+    Ok(Json(stat_info(&gateway, &extensions, &query).await?.into()))
+}
+
+async fn mkdir(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    Query(query): Query<PathQuery>,
+) -> Result<StatusCode, ApiError> {
+    let (parent, name) = query.path.trim_end_matches('/').rsplit_once('/').unwrap_or(("", &query.path));
+    let request = CreateDirectoryRequest {
+        path: parent.to_string(),
+        name: name.to_string(),
+        volume: query.volume.clone(),
+    };
+    gateway
+        .client()
+        .create_directory(forward(&extensions, request))
+        .await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn delete(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    Query(query): Query<PathQuery>,
+) -> Result<StatusCode, ApiError> {
+    let info = stat_info(&gateway, &extensions, &query).await?;
+    let mut client = gateway.client();
+    if info.is_directory {
+        let request = DeleteDirectoryRequest {
+            path: query.path,
+            recursive: query.recursive,
+            volume: query.volume,
+        };
+        client.delete_directory(forward(&extensions, request)).await?;
+    } else {
+        let request = DeleteRequest {
+            file_name: query.path,
+            volume: query.volume,
+        };
+        client.delete_file(forward(&extensions, request)).await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The part of a file asked for by a `Range` header.
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    /// No usable range, so the whole file is sent. Multiple ranges are
+    /// answered this way too, which HTTP allows.
+    Whole,
+    /// First and last byte, inclusive.
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+fn parse_range(value: &str, size: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Whole;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Whole;
+    };
+    if spec.contains(',') {
+        return ByteRange::Whole;
+    }
+    let last = size.saturating_sub(1);
+    let (start, end) = match (start.trim().parse::<u64>(), end.trim().parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(last)),
+        (Ok(start), Err(_)) if end.trim().is_empty() => (start, last),
+        // The last `suffix` bytes
+        (Err(_), Ok(suffix)) if start.trim().is_empty() => match suffix {
+            0 => return ByteRange::Unsatisfiable,
+            suffix => (size.saturating_sub(suffix), last),
+        },
+        _ => return ByteRange::Whole,
+    };
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Part(start, end)
+}
+
+/// `Content-Disposition` naming the download, percent-encoded so any file
+/// name survives.
+fn attachment(name: &str) -> String {
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    format!("attachment; filename*=UTF-8''{}", encoded)
+}
+
+async fn download(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    headers: HeaderMap,
+    Query(query): Query<PathQuery>,
+) -> Result<Response, ApiError> {
+    let info = stat_info(&gateway, &extensions, &query).await?;
+    if info.is_directory {
+        let path = query.path.trim_matches('/').to_string();
+        return Err(tonic::Status::from(FileError::IsADirectory { path }).into());
+    }
+    let size = info.size;
+    let range = match headers.get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) => parse_range(value, size),
+        None => ByteRange::Whole,
+    };
+    let (offset, length) = match range {
+        ByteRange::Whole => (0, size),
+        ByteRange::Part(start, end) => (start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{}", size);
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, [(header::CONTENT_RANGE, content_range)]).into_response());
+        }
+    };
+
+    let request = DownloadRequest {
+        file_name: query.path,
+        volume: query.volume,
+        offset,
+        length,
+    };
+    let stream = gateway
+        .client()
+        .download(forward(&extensions, request))
+        .await?
+        .into_inner();
+    let body = Body::from_stream(stream.map(|chunk| chunk.map(|chunk| Bytes::from(chunk.data))));
+
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, length)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_DISPOSITION, attachment(&info.filename));
+    if let ByteRange::Part(start, end) = range {
+        response = response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size));
+    }
+    response
+        .body(body)
+        .map_err(|e| tonic::Status::internal(e.to_string()).into())
+}
+
+/// `PUT /api/v1/files?path=dir/name`: the request body is the file.
+async fn upload(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    headers: HeaderMap,
+    Query(query): Query<PathQuery>,
+    body: Body,
+) -> Result<(StatusCode, Json<Uploaded>), ApiError> {
+    let (directory, name) = query.path.rsplit_once('/').unwrap_or(("", &query.path));
+    let size = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .unwrap_or(0);
+    let uploaded = gateway
+        .upload(&extensions, &query.volume, directory, name, size, body.into_data_stream())
+        .await?;
+    Ok((StatusCode::CREATED, Json(uploaded)))
+}
+
+/// `POST /api/v1/files?path=dir`: every file in a `multipart/form-data` body
+/// is uploaded into the directory, as a browser form sends them.
+async fn upload_form(
+    State(gateway): State<Gateway>,
+    extensions: Extensions,
+    Query(query): Query<PathQuery>,
+    mut form: Multipart,
+) -> Result<(StatusCode, Json<Vec<Uploaded>>), ApiError> {
+    let mut uploaded = Vec::new();
+    while let Some(field) = form
+        .next_field()
+        .await
+        .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?
+    {
+        let Some(name) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+        uploaded.push(gateway.upload(&extensions, &query.volume, &query.path, &name, 0, field).await?);
+    }
+    Ok((StatusCode::CREATED, Json(uploaded)))
+}
+
+/// CORS for browser pages served from `origins`, covering both the gateway
+/// and gRPC-Web. Requests from pages elsewhere get no CORS headers, so
+/// browsers do not let those pages read the responses.
+pub fn cors(origins: &[String]) -> Result<CorsLayer, Box<dyn std::error::Error>> {
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin).map_err(|_| format!("Invalid web origin '{}'", origin))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+        .expose_headers([
+            HeaderName::from_static("grpc-status"),
+            HeaderName::from_static("grpc-message"),
+            HeaderName::from_static("grpc-status-details-bin"),
+            header::CONTENT_RANGE,
+            header::CONTENT_DISPOSITION,
+            header::ACCEPT_RANGES,
+        ]))
+}
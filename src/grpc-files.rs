@@ -0,0 +1,913 @@
+use grpc_files::{
+    client::{self, Client, Transfer},
+    config::{Config, ConfigArgs},
+    fileservice::{FileInfo, ListRequest},
+    logging,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::io::AsyncWriteExt;
+use tonic::{Code, Status};
+
+const USAGE: &str = "Usage: grpc-files [--config <file>] [--profile <name>] [--<setting> <value>]... <command> [--json] [options] <args>
+
+Commands:
+  ls [-l] [-a] [-r] [path]...          list directories, or show files
+  stat <path>...                       show details of files and directories
+  get [-r] <path>... <local|->         download, to standard output with -
+  put [-r] <local|->... <path>         upload, from standard input with -
+  rm [-r] [-f] <path>...               delete files, and directories with -r
+  mkdir [-p] <path>...                 create directories, and their parents with -p
+  rmdir <path>...                      delete empty directories
+  mv <path>... <path>                  move or rename within a volume
+  cp [-r] <path>... <path>             copy within a volume
+  config check                         show the effective configuration
+
+Server paths are written [volume:]path and may contain globs (*, ?, [...]).";
+
+/// Exit status for bad arguments, as in sysexits.h.
+const EXIT_USAGE: u8 = 64;
+/// Exit status for errors reading or writing local files, as in sysexits.h.
+const EXIT_IO: u8 = 74;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(status) => ExitCode::from(status),
+        Err(e) => {
+            eprintln!("grpc-files: {}", describe(e.as_ref()));
+            ExitCode::from(exit_status(e.as_ref()))
+        }
+    }
+}
+
+async fn run() -> Result<u8, Box<dyn Error>> {
+    let (config_args, args) = ConfigArgs::parse(std::env::args().skip(1).collect())?;
+    let loaded = Config::load_with(&config_args)?;
+    // --json may come before the command too
+    let json = args.first().is_some_and(|arg| arg == "--json");
+    let args = if json { &args[1..] } else { &args[..] };
+    let Some((command, args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return Ok(EXIT_USAGE);
+    };
+    if command == "config" && args == ["check"] {
+        print!("{}", loaded);
+        return Ok(0);
+    }
+    let Some(command) = Command::parse(command) else {
+        eprintln!("grpc-files: unknown command '{}'\n\n{}", command, USAGE);
+        return Ok(EXIT_USAGE);
+    };
+    let (mut options, args) = match Options::parse(command, args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("grpc-files: {}\n\n{}", e, USAGE);
+            return Ok(EXIT_USAGE);
+        }
+    };
+
+    options.json |= json;
+    let config = loaded.config;
+    let _log_guard = logging::init(&config, "grpc-files", true)?;
+    let client = Client::connect(&config).await?;
+    let mut output = Output {
+        json: options.json,
+        status: 0,
+    };
+    match command {
+        Command::Ls => ls(&client, &options, &args, &mut output).await,
+        Command::Stat => stat(&client, &args, &mut output).await,
+        Command::Get => get(&client, &options, &args, &mut output).await,
+        Command::Put => put(&client, &options, &args, &mut output).await,
+        Command::Rm => rm(&client, &options, &args, &mut output).await,
+        Command::Mkdir => mkdir(&client, &options, &args, &mut output).await,
+        Command::Rmdir => rmdir(&client, &args, &mut output).await,
+        Command::Mv | Command::Cp => transfer(&client, command, &options, &args, &mut output).await,
+    }
+    Ok(output.status)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Ls,
+    Stat,
+    Get,
+    Put,
+    Rm,
+    Mkdir,
+    Rmdir,
+    Mv,
+    Cp,
+}
+
+impl Command {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "ls" => Command::Ls,
+            "stat" => Command::Stat,
+            "get" => Command::Get,
+            "put" => Command::Put,
+            "rm" => Command::Rm,
+            "mkdir" => Command::Mkdir,
+            "rmdir" => Command::Rmdir,
+            "mv" => Command::Mv,
+            "cp" => Command::Cp,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Command::Ls => "ls",
+            Command::Stat => "stat",
+            Command::Get => "get",
+            Command::Put => "put",
+            Command::Rm => "rm",
+            Command::Mkdir => "mkdir",
+            Command::Rmdir => "rmdir",
+            Command::Mv => "mv",
+            Command::Cp => "cp",
+        }
+    }
+
+    /// Short flags the command takes.
+    fn flags(self) -> &'static str {
+        match self {
+            Command::Ls => "lar",
+            Command::Get | Command::Put | Command::Cp => "r",
+            Command::Rm => "rf",
+            Command::Mkdir => "p",
+            Command::Stat | Command::Rmdir | Command::Mv => "",
+        }
+    }
+
+    /// How many arguments the command needs at least.
+    fn min_args(self) -> usize {
+        match self {
+            Command::Ls => 0,
+            Command::Stat | Command::Rm | Command::Mkdir | Command::Rmdir => 1,
+            Command::Get | Command::Put | Command::Mv | Command::Cp => 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    json: bool,
+    long: bool,
+    all: bool,
+    recursive: bool,
+    force: bool,
+    parents: bool,
+}
+
+impl Options {
+    /// Split a command's arguments into options and operands. Short flags
+    /// can be combined, as in `-rf`, and `--` ends the options.
+    fn parse(command: Command, args: &[String]) -> Result<(Self, Vec<String>), String> {
+        let mut options = Options::default();
+        let mut operands = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flags = match arg.as_str() {
+                "--" => {
+                    operands.extend(args.by_ref().cloned());
+                    break;
+                }
+                "--json" => {
+                    options.json = true;
+                    continue;
+                }
+                "--long" => "l",
+                "--all" => "a",
+                "--recursive" => "r",
+                "--force" => "f",
+                "--parents" => "p",
+                "-" => {
+                    operands.push(arg.clone());
+                    continue;
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+                _ => match arg.strip_prefix('-') {
+                    Some(flags) => flags,
+                    None => {
+                        operands.push(arg.clone());
+                        continue;
+                    }
+                },
+            };
+            for flag in flags.chars() {
+                let flag = if flag == 'R' { 'r' } else { flag };
+                if !command.flags().contains(flag) {
+                    return Err(format!("{} does not take -{}", command.name(), flag));
+                }
+                match flag {
+                    'l' => options.long = true,
+                    'a' => options.all = true,
+                    'r' => options.recursive = true,
+                    'f' => options.force = true,
+                    _ => options.parents = true,
+                }
+            }
+        }
+        if operands.len() < command.min_args() {
+            return Err(format!("{} needs more arguments", command.name()));
+        }
+        Ok((options, operands))
+    }
+}
+
+/// A path on the server, written `[volume:]path`. An empty volume is the
+/// server's default one.
+#[derive(Clone)]
+struct Remote {
+    volume: String,
+    path: String,
+}
+
+impl Remote {
+    fn parse(arg: &str) -> Self {
+        let (volume, path) = arg.split_once(':').unwrap_or(("", arg));
+        Remote {
+            volume: volume.to_string(),
+            path: path.trim_matches('/').to_string(),
+        }
+    }
+
+    fn join(&self, name: &str) -> Self {
+        let path = match self.path.as_str() {
+            "" => name.to_string(),
+            path => format!("{}/{}", path, name),
+        };
+        Remote {
+            volume: self.volume.clone(),
+            path,
+        }
+    }
+
+    /// The directory the path is in and its last component.
+    fn split(&self) -> (&str, &str) {
+        self.path.rsplit_once('/').unwrap_or(("", &self.path))
+    }
+
+    fn name(&self) -> &str {
+        self.split().1
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() { "/" } else { &self.path };
+        match self.volume.as_str() {
+            "" => write!(f, "{}", path),
+            volume => write!(f, "{}:{}", volume, path),
+        }
+    }
+}
+
+/// Whether an argument names a directory to put things in, like `backup/`
+/// or `archive:`.
+fn names_directory(arg: &str) -> bool {
+    arg.ends_with('/') || arg.ends_with(':')
+}
+
+/// Prints results and errors, as text or as one JSON object per line, and
+/// keeps the exit status of the first failure.
+struct Output {
+    json: bool,
+    status: u8,
+}
+
+impl Output {
+    fn error(&mut self, subject: &dyn fmt::Display, error: &(dyn Error + 'static)) {
+        let status = exit_status(error);
+        if self.status == 0 {
+            self.status = status;
+        }
+        if self.json {
+            let (code, reason) = match error.downcast_ref::<client::Error>() {
+                Some(client::Error::Io(_)) | None => ("Local".to_string(), None),
+                Some(error) => (format!("{:?}", error.code()), error.reason().map(|reason| reason.as_str())),
+            };
+            let mut record = serde_json::json!({
+                "path": subject.to_string(),
+                "error": describe(error),
+                "code": code,
+            });
+            if let Some(reason) = reason {
+                record["reason"] = reason.into();
+            }
+            eprintln!("{}", record);
+        } else {
+            eprintln!("grpc-files: {}: {}", subject, describe(error));
+        }
+    }
+
+    /// Report a finished change. Only JSON output mentions it; text output
+    /// stays quiet like the Unix tools.
+    fn done(&self, command: Command, path: &dyn fmt::Display, destination: Option<&dyn fmt::Display>, bytes: Option<u64>) {
+        if !self.json {
+            return;
+        }
+        let mut record = serde_json::json!({
+            "action": command.name(),
+            "path": path.to_string(),
+        });
+        if let Some(destination) = destination {
+            record["destination"] = destination.to_string().into();
+        }
+        if let Some(bytes) = bytes {
+            record["bytes"] = bytes.into();
+        }
+        println!("{}", record);
+    }
+}
+
+/// A file or directory as JSON output shows it.
+#[derive(Serialize)]
+struct Entry<'a> {
+    volume: &'a str,
+    path: &'a str,
+    name: &'a str,
+    size: u64,
+    is_directory: bool,
+    /// Unix time in seconds.
+    modified: Option<i64>,
+    tags: &'a [String],
+    attributes: BTreeMap<&'a str, &'a str>,
+}
+
+impl<'a> Entry<'a> {
+    fn new(remote: &'a Remote, info: &'a FileInfo) -> Self {
+        Entry {
+            volume: &remote.volume,
+            path: &remote.path,
+            name: remote.name(),
+            size: info.size,
+            is_directory: info.is_directory,
+            modified: info.upload_time.as_ref().map(|time| time.seconds),
+            tags: &info.tags,
+            attributes: info
+                .attributes
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+        }
+    }
+}
+
+/// `2026-01-31 12:00:00`, or `-` when the server sent no time.
+fn format_time(info: &FileInfo) -> String {
+    match &info.upload_time {
+        Some(time) => {
+            let time = time.to_string();
+            let (date, time) = time.split_once('T').unwrap_or((&time, ""));
+            format!("{} {}", date, time.split(['.', 'Z']).next().unwrap_or_default())
+        }
+        None => "-".to_string(),
+    }
+}
+
+async fn ls(client: &Client, options: &Options, args: &[String], output: &mut Output) {
+    let args = if args.is_empty() { &[String::new()][..] } else { args };
+    let mut targets = Vec::new();
+    for arg in args {
+        match expand(client, arg).await {
+            Ok(paths) => targets.extend(paths),
+            Err(e) => output.error(arg, &e),
+        }
+    }
+    // Names are shown relative to the listed directory unless there are several
+    let relative = targets.len() == 1;
+    for target in targets {
+        if let Err(e) = list(client, &target, options, relative, output).await {
+            output.error(&target, &e);
+        }
+    }
+}
+
+async fn list(
+    client: &Client,
+    target: &Remote,
+    options: &Options,
+    relative: bool,
+    output: &mut Output,
+) -> Result<(), client::Error> {
+    let info = stat_remote(client, target).await?;
+    if !info.is_directory {
+        print_entry(target, &target.to_string(), &info, options.long, output.json);
+        return Ok(());
+    }
+    // Depth first, so a directory's contents follow it
+    let mut pending = vec![(target.clone(), String::new(), list_all(client, target, options.all).await?.into_iter())];
+    while let Some((directory, prefix, entries)) = pending.last_mut() {
+        let Some(info) = entries.next() else {
+            pending.pop();
+            continue;
+        };
+        let path = directory.join(&info.filename);
+        let shown = if relative {
+            format!("{}{}", prefix, info.filename)
+        } else {
+            path.to_string()
+        };
+        print_entry(&path, &shown, &info, options.long, output.json);
+        if options.recursive && info.is_directory {
+            match list_all(client, &path, options.all).await {
+                Ok(children) => pending.push((path, format!("{}/", shown), children.into_iter())),
+                Err(e) => output.error(&path, &e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(remote: &Remote, shown: &str, info: &FileInfo, long: bool, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string(&Entry::new(remote, info)).unwrap_or_default());
+        return;
+    }
+    let suffix = if info.is_directory { "/" } else { "" };
+    if long {
+        let kind = if info.is_directory { 'd' } else { '-' };
+        println!("{} {:>14} {} {}{}", kind, info.size, format_time(info), shown, suffix);
+    } else {
+        println!("{}{}", shown, suffix);
+    }
+}
+
+async fn stat(client: &Client, args: &[String], output: &mut Output) {
+    let mut first = true;
+    for arg in args {
+        let paths = match expand(client, arg).await {
+            Ok(paths) => paths,
+            Err(e) => {
+                output.error(arg, &e);
+                continue;
+            }
+        };
+        for path in paths {
+            let info = match stat_remote(client, &path).await {
+                Ok(info) => info,
+                Err(e) => {
+                    output.error(&path, &e);
+                    continue;
+                }
+            };
+            if output.json {
+                println!("{}", serde_json::to_string(&Entry::new(&path, &info)).unwrap_or_default());
+                continue;
+            }
+            if !first {
+                println!();
+            }
+            first = false;
+            let mut attributes: Vec<String> = info.attributes.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            attributes.sort();
+            println!("Path:     {}", path);
+            println!("Type:     {}", if info.is_directory { "directory" } else { "file" });
+            println!("Size:     {}", info.size);
+            println!("Modified: {}", format_time(&info));
+            let tags: Vec<String> = info.tags.iter().cloned().chain(attributes).collect();
+            println!("Tags:     {}", if tags.is_empty() { "-".to_string() } else { tags.join(" ") });
+        }
+    }
+}
+
+async fn get(client: &Client, options: &Options, args: &[String], output: &mut Output) {
+    let (destination, sources) = args.split_last().expect("checked by Options::parse");
+    let mut paths = Vec::new();
+    for source in sources {
+        match expand(client, source).await {
+            Ok(expanded) => paths.extend(expanded),
+            Err(e) => output.error(source, &e),
+        }
+    }
+
+    if destination == "-" {
+        let mut stdout = tokio::io::stdout();
+        for path in paths {
+            let result = async {
+                if stat_remote(client, &path).await?.is_directory {
+                    return Err(is_a_directory(false));
+                }
+                client.download(&path.volume, &path.path, &mut stdout, &Transfer::default()).await
+            }
+            .await;
+            match result {
+                Ok(bytes) => output.done(Command::Get, &path, Some(&"-"), Some(bytes)),
+                Err(e) => output.error(&path, &e),
+            }
+        }
+        if let Err(e) = stdout.flush().await {
+            output.error(&"-", &e);
+        }
+        return;
+    }
+
+    let destination_path = PathBuf::from(destination);
+    let into = destination_path.is_dir() || paths.len() > 1 || names_directory(destination);
+    for path in paths {
+        let target = if into {
+            destination_path.join(path.name())
+        } else {
+            destination_path.clone()
+        };
+        fetch(client, &path, &target, options.recursive, output).await;
+    }
+}
+
+/// Download a file, or a directory and everything in it, to `target`.
+async fn fetch(client: &Client, source: &Remote, target: &Path, recursive: bool, output: &mut Output) {
+    let info = match stat_remote(client, source).await {
+        Ok(info) => info,
+        Err(e) => return output.error(source, &e),
+    };
+    if !info.is_directory {
+        return fetch_file(client, source, target, output).await;
+    }
+    if !recursive {
+        return output.error(source, &is_a_directory(true));
+    }
+
+    let mut pending = vec![(source.clone(), target.to_path_buf())];
+    while let Some((directory, local)) = pending.pop() {
+        if let Err(e) = tokio::fs::create_dir_all(&local).await {
+            output.error(&local.display(), &e);
+            continue;
+        }
+        let entries = match list_all(client, &directory, true).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                output.error(&directory, &e);
+                continue;
+            }
+        };
+        for info in entries {
+            let path = directory.join(&info.filename);
+            let local = local.join(&info.filename);
+            if info.is_directory {
+                pending.push((path, local));
+            } else {
+                fetch_file(client, &path, &local, output).await;
+            }
+        }
+    }
+}
+
+async fn fetch_file(client: &Client, source: &Remote, target: &Path, output: &mut Output) {
+    match client.download_file(&source.volume, &source.path, target, &Transfer::default()).await {
+        Ok(bytes) => output.done(Command::Get, source, Some(&target.display()), Some(bytes)),
+        Err(e) => output.error(source, &e),
+    }
+}
+
+async fn put(client: &Client, options: &Options, args: &[String], output: &mut Output) {
+    let (destination_arg, sources) = args.split_last().expect("checked by Options::parse");
+    let destination = Remote::parse(destination_arg);
+
+    if sources.iter().any(|source| source == "-") {
+        if sources.len() > 1 {
+            output.error(&"-", &io::Error::new(io::ErrorKind::InvalidInput, "standard input can't be combined with other files"));
+            return;
+        }
+        if names_directory(destination_arg) {
+            output.error(&destination, &client::Error::InvalidInput("Give a file name to upload standard input to".to_string()));
+            return;
+        }
+        let transfer = Transfer::default();
+        match client.upload(tokio::io::stdin(), None, &destination.volume, &destination.path, &transfer).await {
+            Ok(bytes) => output.done(Command::Put, &"-", Some(&destination), Some(bytes)),
+            Err(e) => output.error(&destination, &e),
+        }
+        return;
+    }
+
+    let into = sources.len() > 1
+        || names_directory(destination_arg)
+        || stat_remote(client, &destination).await.is_ok_and(|info| info.is_directory);
+    for source in sources {
+        let source = Path::new(source);
+        let target = match source.file_name().and_then(|name| name.to_str()) {
+            Some(name) if into => destination.join(name),
+            Some(_) => destination.clone(),
+            None => {
+                output.error(&source.display(), &io::Error::new(io::ErrorKind::InvalidInput, "not a file name"));
+                continue;
+            }
+        };
+        send(client, source, &target, options.recursive, output).await;
+    }
+}
+
+/// Upload a file, or a directory and everything in it, to `target`.
+async fn send(client: &Client, source: &Path, target: &Remote, recursive: bool, output: &mut Output) {
+    let metadata = match tokio::fs::metadata(source).await {
+        Ok(metadata) => metadata,
+        Err(e) => return output.error(&source.display(), &e),
+    };
+    if !metadata.is_dir() {
+        return send_file(client, source, target, output).await;
+    }
+    if !recursive {
+        return output.error(&source.display(), &is_a_directory(true));
+    }
+
+    let mut pending = vec![(source.to_path_buf(), target.clone())];
+    while let Some((local, directory)) = pending.pop() {
+        if let Err(e) = create_directory(client, &directory, true).await {
+            output.error(&directory, &e);
+            continue;
+        }
+        let entries = match std::fs::read_dir(&local) {
+            Ok(entries) => entries,
+            Err(e) => {
+                output.error(&local.display(), &e);
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    output.error(&local.display(), &e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                output.error(&path.display(), &io::Error::new(io::ErrorKind::InvalidData, "file name is not valid UTF-8"));
+                continue;
+            };
+            if path.is_dir() {
+                pending.push((path, directory.join(&name)));
+            } else {
+                send_file(client, &path, &directory.join(&name), output).await;
+            }
+        }
+    }
+}
+
+async fn send_file(client: &Client, source: &Path, target: &Remote, output: &mut Output) {
+    match client.upload_file(source, &target.volume, &target.path, &Transfer::default()).await {
+        Ok(bytes) => output.done(Command::Put, &source.display(), Some(target), Some(bytes)),
+        Err(e) => output.error(&source.display(), &e),
+    }
+}
+
+async fn rm(client: &Client, options: &Options, args: &[String], output: &mut Output) {
+    for arg in args {
+        let paths = match expand(client, arg).await {
+            Ok(paths) => paths,
+            Err(e) if options.force && is_not_found(&e) => continue,
+            Err(e) => {
+                output.error(arg, &e);
+                continue;
+            }
+        };
+        for path in paths {
+            let result = async {
+                if stat_remote(client, &path).await?.is_directory {
+                    if !options.recursive {
+                        return Err(is_a_directory(true));
+                    }
+                    client.delete_directory(&path.volume, &path.path, true).await?;
+                } else {
+                    client.delete_file(&path.volume, &path.path).await?;
+                }
+                Ok::<_, client::Error>(())
+            }
+            .await;
+            match result {
+                Ok(()) => output.done(Command::Rm, &path, None, None),
+                Err(e) if options.force && is_not_found(&e) => {}
+                Err(e) => output.error(&path, &e),
+            }
+        }
+    }
+}
+
+async fn mkdir(client: &Client, options: &Options, args: &[String], output: &mut Output) {
+    for arg in args {
+        let path = Remote::parse(arg);
+        let result = if options.parents {
+            // Each missing ancestor first, then the directory itself
+            let mut ancestor = Remote {
+                volume: path.volume.clone(),
+                path: String::new(),
+            };
+            let mut result = Ok(());
+            for component in path.path.split('/') {
+                ancestor = ancestor.join(component);
+                result = create_directory(client, &ancestor, true).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            result
+        } else {
+            create_directory(client, &path, false).await
+        };
+        match result {
+            Ok(()) => output.done(Command::Mkdir, &path, None, None),
+            Err(e) => output.error(&path, &e),
+        }
+    }
+}
+
+async fn rmdir(client: &Client, args: &[String], output: &mut Output) {
+    for arg in args {
+        let paths = match expand(client, arg).await {
+            Ok(paths) => paths,
+            Err(e) => {
+                output.error(arg, &e);
+                continue;
+            }
+        };
+        for path in paths {
+            match client.delete_directory(&path.volume, &path.path, false).await {
+                Ok(()) => output.done(Command::Rmdir, &path, None, None),
+                Err(e) => output.error(&path, &e),
+            }
+        }
+    }
+}
+
+/// `mv` and `cp`, which the server does within a volume.
+async fn transfer(client: &Client, command: Command, options: &Options, args: &[String], output: &mut Output) {
+    let (destination_arg, sources) = args.split_last().expect("checked by Options::parse");
+    let destination = Remote::parse(destination_arg);
+    let mut paths = Vec::new();
+    for source in sources {
+        match expand(client, source).await {
+            Ok(expanded) => paths.extend(expanded),
+            Err(e) => output.error(source, &e),
+        }
+    }
+    let into = paths.len() > 1
+        || names_directory(destination_arg)
+        || stat_remote(client, &destination).await.is_ok_and(|info| info.is_directory);
+
+    for path in paths {
+        let target = if into {
+            destination.join(path.name())
+        } else {
+            destination.clone()
+        };
+        let result = async {
+            let volume = match (path.volume.as_str(), destination.volume.as_str()) {
+                (source, target) if !source.is_empty() && !target.is_empty() && source != target => {
+                    return Err(client::Error::InvalidInput("Can't move or copy between volumes; use get and put".to_string()));
+                }
+                (source, "") => source,
+                (_, target) => target,
+            };
+            if command == Command::Cp {
+                if stat_remote(client, &path).await?.is_directory && !options.recursive {
+                    return Err(is_a_directory(true));
+                }
+                client.copy(volume, &path.path, &target.path).await?;
+            } else {
+                client.rename(volume, &path.path, &target.path).await?;
+            }
+            Ok::<_, client::Error>(())
+        }
+        .await;
+        match result {
+            Ok(()) => output.done(command, &path, Some(&target), None),
+            Err(e) => output.error(&path, &e),
+        }
+    }
+}
+
+/// The paths an argument stands for: the path itself, or everything its
+/// globs match. Globs work in any component, and like in a shell `*` and
+/// `?` don't match a leading dot.
+async fn expand(client: &Client, arg: &str) -> Result<Vec<Remote>, client::Error> {
+    let remote = Remote::parse(arg);
+    let is_glob = |component: &str| component.contains(['*', '?', '[']);
+    if !is_glob(&remote.path) {
+        return Ok(vec![remote]);
+    }
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let components: Vec<&str> = remote.path.split('/').collect();
+    let mut matches = vec![Remote {
+        volume: remote.volume.clone(),
+        path: String::new(),
+    }];
+    for (i, component) in components.iter().enumerate() {
+        if !is_glob(component) {
+            matches = matches.iter().map(|directory| directory.join(component)).collect();
+            continue;
+        }
+        let pattern = glob::Pattern::new(component)
+            .map_err(|e| client::Error::InvalidInput(format!("Invalid pattern '{}': {}", component, e)))?;
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for directory in &matches {
+            let entries = match list_all(client, directory, true).await {
+                Ok(entries) => entries,
+                Err(e) if e.code() == Code::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            next.extend(
+                entries
+                    .iter()
+                    .filter(|info| (last || info.is_directory) && pattern.matches_with(&info.filename, options))
+                    .map(|info| directory.join(&info.filename)),
+            );
+        }
+        matches = next;
+    }
+    if matches.is_empty() {
+        return Err(Status::not_found(format!("No match for '{}'", arg)).into());
+    }
+    Ok(matches)
+}
+
+async fn stat_remote(client: &Client, path: &Remote) -> Result<FileInfo, client::Error> {
+    client.stat(&path.volume, &path.path).await
+}
+
+async fn list_all(client: &Client, directory: &Remote, show_hidden: bool) -> Result<Vec<FileInfo>, client::Error> {
+    let response = client
+        .list(ListRequest {
+            path: directory.path.clone(),
+            show_hidden,
+            volume: directory.volume.clone(),
+            ..Default::default()
+        })
+        .await?;
+    Ok(response.files)
+}
+
+/// Create a directory whose parent exists. With `existing_ok` a directory
+/// that is already there counts as created.
+async fn create_directory(client: &Client, path: &Remote, existing_ok: bool) -> Result<(), client::Error> {
+    let (parent, name) = path.split();
+    if name.is_empty() {
+        // The volume's root always exists
+        return Ok(());
+    }
+    match client.create_directory(&path.volume, parent, name).await {
+        Ok(()) => Ok(()),
+        Err(e) if existing_ok && e.code() == Code::AlreadyExists => {
+            if stat_remote(client, path).await?.is_directory {
+                Ok(())
+            } else {
+                Err(Status::already_exists("A file with that name exists").into())
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn is_a_directory(hint: bool) -> client::Error {
+    let message = if hint { "Is a directory (use -r)" } else { "Is a directory" };
+    Status::failed_precondition(message).into()
+}
+
+fn is_not_found(error: &client::Error) -> bool {
+    error.code() == Code::NotFound
+}
+
+/// The message of an error, with the causes of connection errors, which
+/// say little on their own.
+fn describe(error: &(dyn Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        // Wrapped I/O errors often repeat the same text, and a status only
+        // repeats its message
+        if !cause.is::<Status>() && !message.ends_with(&cause_message) {
+            message = format!("{}: {}", message, cause_message);
+        }
+        source = cause.source();
+    }
+    message
+}
+
+/// The gRPC status code for errors from the server, `UNAVAILABLE` (14) when
+/// it can't be reached, and sysexits-style codes for everything else.
+fn exit_status(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(error) = error.downcast_ref::<client::Error>() {
+        return match error {
+            client::Error::Io(_) => EXIT_IO,
+            error => error.code() as u8,
+        };
+    }
+    if error.is::<io::Error>() {
+        return EXIT_IO;
+    }
+    1
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+#[cfg(unix)]
+use tonic::transport::server::UdsConnectInfo;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::transport::CertificateDer;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Prefix of the names local processes on the Unix socket go by. No other
+/// identity may start with it, so none can pass for a local user.
+const UID_PREFIX: &str = "uid:";
+
+/// Whether a name is one only the Unix socket hands out.
+pub fn is_uid_name(name: &str) -> bool {
+    name.starts_with(UID_PREFIX)
+}
+
+/// Common name (CN) of a DER encoded certificate's subject.
+pub fn common_name(cert: &[u8]) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(str::to_string)
+}
+
+/// Name of the access token a request was authenticated with, added to the
+/// request by [`crate::access::AccessLayer`].
+#[derive(Debug, Clone)]
+pub struct TokenIdentity(pub String);
+
+/// Name a client certificate's public key is pinned under, added to the
+/// request by [`crate::access::AccessLayer`] when clients are checked against
+/// pinned keys. Takes the place of the certificate's CN.
+#[derive(Debug, Clone)]
+pub struct PinnedKeyIdentity(pub String);
+
+/// A client of the SFTP listener: the name its SSH key is authorized under
+/// and where it connected from.
+#[derive(Debug, Clone)]
+pub struct SshIdentity {
+    pub name: String,
+    pub remote_addr: std::net::SocketAddr,
+}
+
+/// Identity of the client that sent a request: the name of its access token,
+/// pinned key or SSH key, the CN of its certificate, or `uid:<uid>` for a
+/// local process connected over the Unix socket. A certificate whose CN looks
+/// like a uid gives no identity.
+pub fn peer_identity(extensions: &http::Extensions) -> Option<String> {
+    if let Some(TokenIdentity(name)) = extensions.get::<TokenIdentity>() {
+        return Some(name.clone());
+    }
+    if let Some(PinnedKeyIdentity(name)) = extensions.get::<PinnedKeyIdentity>() {
+        return Some(name.clone());
+    }
+    if let Some(ssh) = extensions.get::<SshIdentity>() {
+        return Some(ssh.name.clone());
+    }
+    #[cfg(unix)]
+    if let Some(info) = extensions.get::<UdsConnectInfo>() {
+        return info.peer_cred.map(|cred| format!("{}{}", UID_PREFIX, cred.uid()));
+    }
+    common_name(peer_certificate(extensions)?.as_ref()).filter(|cn| !is_uid_name(cn))
+}
+
+/// The certificate a TLS client presented, already verified in the handshake.
+pub fn peer_certificate(extensions: &http::Extensions) -> Option<CertificateDer<'static>> {
+    let certs: Arc<Vec<CertificateDer<'static>>> = extensions
+        .get::<TlsConnectInfo<TcpConnectInfo>>()?
+        .peer_certs()?;
+    certs.first().cloned()
+}
+
+/// Key used to group a client's requests for rate limiting and accounting:
+/// its certificate CN, or its address if it did not present one.
+pub fn client_name(extensions: &http::Extensions) -> String {
+    peer_identity(extensions)
+        .or_else(|| remote_addr(extensions).map(|a| a.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Where a request came from, for the logs: the client's address, or the
+/// process and group for the Unix socket.
+pub fn peer_address(extensions: &http::Extensions) -> Option<String> {
+    #[cfg(unix)]
+    if let Some(info) = extensions.get::<UdsConnectInfo>() {
+        return info.peer_cred.map(|cred| match cred.pid() {
+            Some(pid) => format!("unix pid={} gid={}", pid, cred.gid()),
+            None => format!("unix gid={}", cred.gid()),
+        });
+    }
+    remote_addr(extensions).map(|addr| addr.to_string())
+}
+
+pub fn remote_addr(extensions: &http::Extensions) -> Option<std::net::SocketAddr> {
+    if let Some(ssh) = extensions.get::<SshIdentity>() {
+        return Some(ssh.remote_addr);
+    }
+    match extensions.get::<TlsConnectInfo<TcpConnectInfo>>() {
+        Some(tls) => tls.get_ref().remote_addr(),
+        // Plaintext connections
+        None => extensions.get::<TcpConnectInfo>()?.remote_addr(),
+    }
+}
@@ -1,7 +1,31 @@
+pub mod access;
+pub mod admission;
+pub mod client;
 pub mod config;
+pub mod delta;
+pub mod error;
+pub mod gateway;
+pub mod identity;
+pub mod listing;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
 pub mod fileservice {
     tonic::include_proto!("fileservice");
     pub const FILE_DESCRIPTOR_SET: &[u8] =
         tonic::include_file_descriptor_set!("fileservice_descriptor");
 }
+pub mod preview;
+pub mod reload;
+pub mod sandbox;
+pub mod sftp;
+pub mod ssh;
+pub mod staging;
+pub mod sync;
+pub mod throttle;
+pub mod tls;
 pub mod tui;
+#[cfg(unix)]
+pub mod unix_socket;
+pub mod volume;
+pub mod webdav;
@@ -0,0 +1,113 @@
+use std::cmp::Ordering;
+use std::fs::Metadata;
+use std::time::SystemTime;
+
+use crate::error::FileError;
+use crate::fileservice::{EntryType, ListRequest, SortField};
+
+/// Most entries a single listing page may hold, whatever the client asks for.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// How a directory listing is filtered, ordered and split into pages.
+pub struct ListOptions {
+    show_hidden: bool,
+    sort_by: SortField,
+    descending: bool,
+    name_glob: Option<glob::Pattern>,
+    entry_type: EntryType,
+    page_size: usize,
+    offset: usize,
+}
+
+/// A directory entry waiting to be sorted and paged.
+pub struct ListEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+impl ListOptions {
+    pub fn from_request(request: &ListRequest) -> Result<Self, FileError> {
+        let name_glob = match request.name_glob.trim() {
+            "" => None,
+            pattern => Some(glob::Pattern::new(pattern).map_err(|e| {
+                FileError::InvalidRequest(format!("Invalid glob pattern: {}", e))
+            })?),
+        };
+        let offset = match request.page_token.as_str() {
+            "" => 0,
+            token => token
+                .parse()
+                .map_err(|_| FileError::InvalidRequest("Invalid page token".to_string()))?,
+        };
+        let page_size = match request.page_size as usize {
+            0 => usize::MAX,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+
+        Ok(ListOptions {
+            show_hidden: request.show_hidden,
+            sort_by: request.sort_by(),
+            descending: request.descending,
+            name_glob,
+            entry_type: request.entry_type(),
+            page_size,
+            offset,
+        })
+    }
+
+    /// Whether an entry passes the hidden, type and glob filters. Directories
+    /// ignore the glob so the tree can still be browsed.
+    pub fn wants(&self, name: &str, is_dir: bool) -> bool {
+        if name.starts_with('.') && !self.show_hidden {
+            return false;
+        }
+        match self.entry_type {
+            EntryType::File if is_dir => return false,
+            EntryType::Directory if !is_dir => return false,
+            _ => {}
+        }
+        match &self.name_glob {
+            Some(pattern) if !is_dir => pattern.matches(name),
+            _ => true,
+        }
+    }
+
+    /// Order entries with directories first, then by the requested field,
+    /// falling back to the name so pages stay stable.
+    pub fn sort(&self, entries: &mut [ListEntry]) {
+        entries.sort_by(|a, b| {
+            let by_field = match self.sort_by {
+                SortField::Name => Ordering::Equal,
+                SortField::Size => entry_size(&a.metadata).cmp(&entry_size(&b.metadata)),
+                SortField::Time => entry_time(&a.metadata).cmp(&entry_time(&b.metadata)),
+            };
+            let by_field = by_field.then_with(|| a.name.cmp(&b.name));
+            let by_field = if self.descending { by_field.reverse() } else { by_field };
+            b.metadata.is_dir().cmp(&a.metadata.is_dir()).then(by_field)
+        });
+    }
+
+    /// Cut the requested page out of sorted entries, returning it with the
+    /// token for the next page, which is empty after the last one.
+    pub fn page(&self, entries: Vec<ListEntry>) -> (Vec<ListEntry>, String) {
+        let end = self.offset.saturating_add(self.page_size);
+        let next_page_token = if end < entries.len() { end.to_string() } else { String::new() };
+        let page = entries.into_iter().skip(self.offset).take(self.page_size).collect();
+        (page, next_page_token)
+    }
+}
+
+/// The size shown for an entry; directories count as empty.
+pub fn entry_size(metadata: &Metadata) -> u64 {
+    if metadata.is_dir() { 0 } else { metadata.len() }
+}
+
+/// The time shown for an entry: when a file was created, or when a
+/// directory was last modified.
+pub fn entry_time(metadata: &Metadata) -> Option<SystemTime> {
+    if metadata.is_dir() {
+        metadata.modified().ok()
+    } else {
+        metadata.created().ok()
+    }
+}
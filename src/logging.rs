@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+
+use crate::{
+    config::{Config, LogFormat},
+    identity,
+};
+
+/// gRPC metadata key carrying the ID that ties a client action to server logs.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Install the global subscriber. Logs go to `<log_directory>/<component>.log`,
+/// or to stderr if no directory is configured and `default_to_file` is false.
+///
+/// The returned guard flushes buffered log lines on drop and must be kept alive.
+pub fn init(
+    config: &Config,
+    component: &str,
+    default_to_file: bool,
+) -> Result<WorkerGuard, Box<dyn std::error::Error>> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.log_level))
+        .map_err(|e| format!("Invalid log_level '{}': {}", config.log_level, e))?;
+
+    let directory = match &config.log_directory {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None if default_to_file => Some(Config::get_config_dir()?.join("logs")),
+        None => None,
+    };
+
+    let (writer, guard, ansi) = match directory {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)?;
+            let appender = tracing_appender::rolling::never(dir, format!("{}.log", component));
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (writer, guard, false)
+        }
+        None => {
+            let (writer, guard) = tracing_appender::non_blocking(std::io::stderr());
+            (writer, guard, true)
+        }
+    };
+
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    let layer = match config.log_format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .try_init()?;
+
+    Ok(guard)
+}
+
+/// Span wrapping a single server-side RPC, keyed by the client's request ID.
+pub fn rpc_span(request: &http::Request<()>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let peer = identity::peer_identity(request.extensions()).unwrap_or_else(|| "unknown".to_string());
+    let remote_addr = identity::peer_address(request.extensions()).unwrap_or_default();
+
+    tracing::info_span!(
+        "rpc",
+        method = request.uri().path(),
+        request_id = %request_id,
+        peer = %peer,
+        remote_addr = %remote_addr,
+    )
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the hidden file at the storage root that holds tags and attributes.
+pub const METADATA_FILE: &str = ".file_server_meta.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileTags {
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl FileTags {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.attributes.is_empty()
+    }
+
+    /// Check a single filter: either a plain tag or a `key=value` attribute.
+    pub fn matches(&self, filter: &str) -> bool {
+        match filter.split_once('=') {
+            Some((key, value)) => self.attributes.get(key).map(|v| v == value).unwrap_or(false),
+            None => self.tags.contains(filter),
+        }
+    }
+
+    pub fn matches_all(&self, filters: &[String]) -> bool {
+        filters.iter().all(|f| self.matches(f))
+    }
+}
+
+/// Persistent map of storage-relative paths to their tags and attributes.
+///
+/// Entries are keyed by the same relative paths the RPCs use, so moving a
+/// directory moves the metadata of everything beneath it too.
+pub struct MetadataStore {
+    file: PathBuf,
+    entries: Mutex<BTreeMap<String, FileTags>>,
+}
+
+impl MetadataStore {
+    pub fn load(storage_root: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = storage_root.join(METADATA_FILE);
+        let entries = if file.exists() {
+            let content = std::fs::read_to_string(&file)
+                .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", file.display(), e))?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(MetadataStore {
+            file,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn get(&self, path: &str) -> FileTags {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&normalize(path)).cloned().unwrap_or_default()
+    }
+
+    /// Merge tags and attributes into a path's metadata, or replace them entirely.
+    pub fn set(
+        &self,
+        path: &str,
+        tags: impl IntoIterator<Item = String>,
+        attributes: HashMap<String, String>,
+        replace: bool,
+    ) -> std::io::Result<FileTags> {
+        let key = normalize(path);
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key.clone()).or_default();
+        if replace {
+            *entry = FileTags::default();
+        }
+        entry.tags.extend(tags.into_iter().filter(|t| !t.is_empty()));
+        entry.attributes.extend(attributes.into_iter().filter(|(k, _)| !k.is_empty()));
+        let result = entry.clone();
+        if result.is_empty() {
+            entries.remove(&key);
+        }
+        self.save(&entries)?;
+        Ok(result)
+    }
+
+    pub fn remove(
+        &self,
+        path: &str,
+        tags: &[String],
+        attribute_keys: &[String],
+    ) -> std::io::Result<FileTags> {
+        let key = normalize(path);
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&key) else {
+            return Ok(FileTags::default());
+        };
+        for tag in tags {
+            entry.tags.remove(tag);
+        }
+        for attr in attribute_keys {
+            entry.attributes.remove(attr);
+        }
+        let result = entry.clone();
+        if result.is_empty() {
+            entries.remove(&key);
+        }
+        self.save(&entries)?;
+        Ok(result)
+    }
+
+    /// Drop metadata for a path and everything beneath it.
+    pub fn forget(&self, path: &str) -> std::io::Result<()> {
+        let key = normalize(path);
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|k, _| !is_same_or_child(k, &key));
+        if entries.len() != before {
+            self.save(&entries)?;
+        }
+        Ok(())
+    }
+
+    /// Re-key metadata after a path (and anything beneath it) was moved.
+    pub fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        self.transfer(from, to, true)
+    }
+
+    /// Duplicate metadata after a path (and anything beneath it) was copied.
+    pub fn copy(&self, from: &str, to: &str) -> std::io::Result<()> {
+        self.transfer(from, to, false)
+    }
+
+    fn transfer(&self, from: &str, to: &str, remove_source: bool) -> std::io::Result<()> {
+        let from = normalize(from);
+        let to = normalize(to);
+        let mut entries = self.entries.lock().unwrap();
+
+        let moved: Vec<(String, FileTags)> = entries
+            .iter()
+            .filter(|(k, _)| is_same_or_child(k, &from))
+            .map(|(k, v)| (format!("{}{}", to, &k[from.len()..]), v.clone()))
+            .collect();
+        if moved.is_empty() {
+            return Ok(());
+        }
+
+        if remove_source {
+            entries.retain(|k, _| !is_same_or_child(k, &from));
+        }
+        entries.extend(moved);
+        self.save(&entries)
+    }
+
+    fn save(&self, entries: &BTreeMap<String, FileTags>) -> std::io::Result<()> {
+        let content = serde_json::to_string_pretty(entries)?;
+        let temp = self.file.with_extension("json.tmp");
+        std::fs::write(&temp, content)?;
+        std::fs::rename(&temp, &self.file)
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.trim_matches('/').to_string()
+}
+
+fn is_same_or_child(key: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || key == prefix
+        || (key.starts_with(prefix) && key.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
@@ -0,0 +1,353 @@
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use prost::Message;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::Instant;
+use tonic::server::NamedService;
+use tower::{Layer, Service};
+
+use crate::volume::{Volumes, storage_usage};
+
+/// Prometheus metrics for the file service, collected by [`MetricsLayer`].
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    transfer_bytes: IntCounterVec,
+    duration: HistogramVec,
+    active_streams: IntGaugeVec,
+    storage_bytes: IntGaugeVec,
+    storage_files: IntGaugeVec,
+    volumes: Arc<Volumes>,
+    /// Method names by request path, for the methods the services define.
+    /// Anything else is counted as `unknown`, so made-up paths can't add labels.
+    methods: HashMap<String, String>,
+}
+
+impl Metrics {
+    pub fn new(volumes: Arc<Volumes>) -> Result<Arc<Self>, prometheus::Error> {
+        let registry = Registry::new_custom(Some("grpc_files".to_string()), None)?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("requests_total", "RPCs started, by method"),
+            &["method"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new("errors_total", "RPCs that ended with a non-OK status, by method and gRPC code"),
+            &["method", "code"],
+        )?;
+        let transfer_bytes = IntCounterVec::new(
+            Opts::new(
+                "transfer_bytes_total",
+                "Bytes received from clients (upload) and sent to them (download), by method",
+            ),
+            &["method", "direction"],
+        )?;
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "request_duration_seconds",
+                "Time from the start of an RPC until its response stream ends",
+            )
+            .buckets(vec![
+                0.001, 0.005, 0.025, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 1800.0,
+            ]),
+            &["method"],
+        )?;
+        let active_streams = IntGaugeVec::new(
+            Opts::new("active_streams", "RPCs currently in progress, by method"),
+            &["method"],
+        )?;
+        let storage_bytes = IntGaugeVec::new(
+            Opts::new("storage_bytes", "Total size of stored files, by volume"),
+            &["volume"],
+        )?;
+        let storage_files = IntGaugeVec::new(
+            Opts::new("storage_files", "Number of stored files, by volume"),
+            &["volume"],
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(transfer_bytes.clone()))?;
+        registry.register(Box::new(duration.clone()))?;
+        registry.register(Box::new(active_streams.clone()))?;
+        registry.register(Box::new(storage_bytes.clone()))?;
+        registry.register(Box::new(storage_files.clone()))?;
+
+        Ok(Arc::new(Metrics {
+            registry,
+            requests,
+            errors,
+            transfer_bytes,
+            duration,
+            active_streams,
+            storage_bytes,
+            storage_files,
+            volumes,
+            methods: known_methods(),
+        }))
+    }
+
+    pub fn layer(self: &Arc<Self>) -> MetricsLayer {
+        MetricsLayer {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format, refreshing storage usage first.
+    pub async fn render(&self) -> String {
+        for volume in self.volumes.iter() {
+            let root = PathBuf::from(volume.root());
+            if let Ok((bytes, files)) = tokio::task::spawn_blocking(move || storage_usage(&root)).await {
+                self.storage_bytes.with_label_values(&[volume.name()]).set(bytes as i64);
+                self.storage_files.with_label_values(&[volume.name()]).set(files as i64);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Serve `GET /metrics` on its own listener.
+    pub async fn serve(self: Arc<Self>, addr: std::net::SocketAddr) -> std::io::Result<()> {
+        let app = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .with_state(self);
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(listener, app).await
+    }
+}
+
+/// The `/package.Service/Method` paths the file service defines, read from
+/// its compiled descriptors.
+fn known_methods() -> HashMap<String, String> {
+    let mut methods = HashMap::new();
+    let set = prost_types::FileDescriptorSet::decode(crate::fileservice::FILE_DESCRIPTOR_SET)
+        .unwrap_or_default();
+    for file in set.file {
+        for service in &file.service {
+            for method in &service.method {
+                let path = format!("/{}.{}/{}", file.package(), service.name(), method.name());
+                methods.insert(path, method.name().to_string());
+            }
+        }
+    }
+    methods
+}
+
+async fn metrics_handler(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics.render().await,
+    )
+}
+
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// Wraps a gRPC service and records metrics for every call.
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S: NamedService> NamedService for MetricsService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<tonic::body::Body>> for MetricsService<S>
+where
+    S: Service<http::Request<tonic::body::Body>, Response = http::Response<tonic::body::Body>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::Body>) -> Self::Future {
+        let method = self
+            .metrics
+            .methods
+            .get(request.uri().path())
+            .map_or("unknown", String::as_str)
+            .to_string();
+        let call = CallGuard::start(self.metrics.clone(), method);
+
+        let received = self
+            .metrics
+            .transfer_bytes
+            .with_label_values(&[call.method.as_str(), "upload"]);
+        let request = request.map(|body| {
+            tonic::body::Body::new(MeteredBody {
+                inner: body,
+                bytes: received,
+                call: None,
+            })
+        });
+
+        // Take the service that was driven to readiness and leave a fresh clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let response = inner.call(request).await?;
+            let mut call = call;
+            if let Some(code) = grpc_status(response.headers()) {
+                call.record_status(code);
+            }
+
+            let sent = call
+                .metrics
+                .transfer_bytes
+                .with_label_values(&[call.method.as_str(), "download"]);
+            Ok(response.map(|body| {
+                tonic::body::Body::new(MeteredBody {
+                    inner: body,
+                    bytes: sent,
+                    call: Some(call),
+                })
+            }))
+        })
+    }
+}
+
+fn grpc_status(headers: &http::HeaderMap) -> Option<tonic::Code> {
+    let value = headers.get("grpc-status")?.to_str().ok()?;
+    Some(tonic::Code::from_i32(value.parse().ok()?))
+}
+
+/// Tracks one RPC from start until its response stream finishes or is dropped.
+struct CallGuard {
+    metrics: Arc<Metrics>,
+    method: String,
+    started: Instant,
+    status_recorded: bool,
+}
+
+impl CallGuard {
+    fn start(metrics: Arc<Metrics>, method: String) -> Self {
+        metrics.requests.with_label_values(&[method.as_str()]).inc();
+        metrics.active_streams.with_label_values(&[method.as_str()]).inc();
+        CallGuard {
+            metrics,
+            method,
+            started: Instant::now(),
+            status_recorded: false,
+        }
+    }
+
+    fn record_status(&mut self, code: tonic::Code) {
+        if self.status_recorded {
+            return;
+        }
+        self.status_recorded = true;
+        if code != tonic::Code::Ok {
+            self.metrics
+                .errors
+                .with_label_values(&[self.method.as_str(), &format!("{:?}", code)])
+                .inc();
+        }
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        // Dropped without ever seeing a status: the client went away mid-call
+        if !self.status_recorded {
+            self.record_status(tonic::Code::Cancelled);
+        }
+        self.metrics
+            .active_streams
+            .with_label_values(&[self.method.as_str()])
+            .dec();
+        self.metrics
+            .duration
+            .with_label_values(&[self.method.as_str()])
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+/// Body wrapper counting data bytes and, for responses, reading the final status from trailers.
+struct MeteredBody<B> {
+    inner: B,
+    bytes: prometheus::IntCounter,
+    call: Option<CallGuard>,
+}
+
+impl<B> Body for MeteredBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.bytes.inc_by(data.len() as u64);
+                }
+                if let (Some(trailers), Some(call)) = (frame.trailers_ref(), this.call.as_mut())
+                    && let Some(code) = grpc_status(trailers)
+                {
+                    call.record_status(code);
+                }
+            }
+            Some(Err(_)) => {
+                if let Some(call) = this.call.as_mut() {
+                    call.record_status(tonic::Code::Internal);
+                }
+            }
+            None => {
+                // The stream is done, so stop counting it as active
+                this.call.take();
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
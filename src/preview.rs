@@ -0,0 +1,72 @@
+/// Bytes returned by a preview when the request does not ask for a size.
+pub const DEFAULT_PREVIEW_BYTES: u64 = 64 * 1024;
+/// Upper bound on preview size regardless of what the client asks for.
+pub const MAX_PREVIEW_BYTES: u64 = 1024 * 1024;
+
+/// Guess the MIME type of a file from its leading bytes.
+///
+/// Returns the MIME type and whether the content should be shown as text.
+pub fn detect_mime(data: &[u8]) -> (String, bool) {
+    if let Some(kind) = infer::get(data) {
+        let mime = kind.mime_type().to_string();
+        let is_text = mime.starts_with("text/");
+        return (mime, is_text);
+    }
+
+    if looks_like_text(data) {
+        ("text/plain".to_string(), true)
+    } else {
+        ("application/octet-stream".to_string(), false)
+    }
+}
+
+/// Image dimensions from a file header, if it is a format we recognise.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let size = imagesize::blob_size(data).ok()?;
+    Some((size.width as u32, size.height as u32))
+}
+
+/// Cut text down to its first `max_lines` lines. Returns the new length.
+pub fn truncate_lines(data: &[u8], max_lines: usize) -> usize {
+    data.iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(max_lines.saturating_sub(1))
+        .map(|(i, _)| i + 1)
+        .unwrap_or(data.len())
+}
+
+/// Format bytes as a classic hex dump: offset, hex bytes and printable ASCII.
+pub fn hex_dump(data: &[u8], bytes_per_row: usize) -> Vec<String> {
+    let bytes_per_row = bytes_per_row.max(1);
+    data.chunks(bytes_per_row)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            format!(
+                "{:08x}  {:<width$}  |{}|",
+                row * bytes_per_row,
+                hex.join(" "),
+                ascii,
+                width = bytes_per_row * 3 - 1
+            )
+        })
+        .collect()
+}
+
+/// Treat data as text if it is UTF-8 (allowing a character cut off at the end)
+/// and free of NUL and most other control bytes.
+fn looks_like_text(data: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(data) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    valid
+        && !data
+            .iter()
+            .any(|&b| b == 0 || (b < 0x20 && !matches!(b, b'\n' | b'\r' | b'\t' | 0x0c | 0x1b)))
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+/// How long to wait for related changes, such as a certificate and its key
+/// being replaced one after the other, before reloading once for all of them.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// What asked for a reload.
+#[derive(Debug, PartialEq)]
+pub enum Trigger {
+    Changed(PathBuf),
+    Hangup,
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::Changed(path) => write!(f, "{} changed", path.display()),
+            Trigger::Hangup => write!(f, "SIGHUP"),
+        }
+    }
+}
+
+/// Reports changes to watched files and directories, and SIGHUP on Unix.
+pub struct Triggers {
+    _watcher: RecommendedWatcher,
+    rx: mpsc::UnboundedReceiver<Trigger>,
+}
+
+impl Triggers {
+    /// Watch `paths`, each either a directory, for changes to anything in it,
+    /// or a file. Files are watched through their directory so that editors
+    /// and tools that replace a file by renaming over it are noticed too.
+    pub fn watch(paths: &[PathBuf]) -> Result<Self, Box<dyn std::error::Error>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let targets: Vec<(PathBuf, Option<PathBuf>)> = paths
+            .iter()
+            .map(|path| match path.is_dir() {
+                true => (path.clone(), None),
+                false => (
+                    path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+                    Some(path.clone()),
+                ),
+            })
+            .collect();
+
+        let watched = targets.clone();
+        let sender = tx.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            // Reading the files during a reload must not set off another one
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            for path in event.paths {
+                let wanted = watched.iter().any(|(dir, file)| match file {
+                    Some(file) => path.file_name() == file.file_name() && path.parent() == Some(dir),
+                    None => path.parent() == Some(dir),
+                });
+                if wanted {
+                    let _ = sender.send(Trigger::Changed(path));
+                }
+            }
+        })?;
+        for (dir, _) in &targets {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("Failed to watch {}: {}", dir.display(), e))?;
+        }
+
+        #[cfg(unix)]
+        {
+            let mut hangup =
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    if tx.send(Trigger::Hangup).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Ok(Triggers {
+            _watcher: watcher,
+            rx,
+        })
+    }
+
+    /// Wait for the next reload request, gathering every trigger that arrives
+    /// while things settle.
+    pub async fn next(&mut self) -> Option<Vec<Trigger>> {
+        let mut triggers = vec![self.rx.recv().await?];
+        tokio::time::sleep(SETTLE_TIME).await;
+        while let Ok(trigger) = self.rx.try_recv() {
+            if !triggers.contains(&trigger) {
+                triggers.push(trigger);
+            }
+        }
+        Some(triggers)
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Longest single path component, in bytes, matching common filesystem limits.
+const MAX_COMPONENT_LEN: usize = 255;
+/// Longest whole relative path, in bytes.
+const MAX_PATH_LEN: usize = 4096;
+
+/// Why a client-supplied path was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    Absolute,
+    ParentComponent,
+    Nul,
+    InvalidCharacter(char),
+    TooLong,
+    /// A single file or directory name was expected.
+    NotAName,
+    /// The path leads outside the storage root once symlinks are resolved.
+    Escapes,
+    /// The path goes through a symlink and the policy refuses them.
+    Symlink,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Absolute => write!(f, "Absolute paths are not allowed"),
+            PathError::ParentComponent => write!(f, "Path traversal not allowed"),
+            PathError::Nul => write!(f, "Path contains a NUL byte"),
+            PathError::InvalidCharacter(c) => {
+                write!(f, "Path contains a disallowed character U+{:04X}", *c as u32)
+            }
+            PathError::TooLong => write!(f, "Path is too long"),
+            PathError::NotAName => write!(f, "Expected a single file or directory name"),
+            PathError::Escapes => write!(f, "Path leads outside the storage area"),
+            PathError::Symlink => write!(f, "Symbolic links are not allowed"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// A normalised path relative to the storage root.
+///
+/// Components are separated by `/`, with no empty, `.` or `..` components and
+/// no leading or trailing slash. The empty path is the storage root itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RelativePath(String);
+
+impl RelativePath {
+    pub fn root() -> Self {
+        RelativePath(String::new())
+    }
+
+    /// Validate and normalise a path sent by a client.
+    pub fn parse(raw: &str) -> Result<Self, PathError> {
+        if raw.len() > MAX_PATH_LEN {
+            return Err(PathError::TooLong);
+        }
+        if raw.starts_with('/') || has_drive_prefix(raw) {
+            return Err(PathError::Absolute);
+        }
+
+        let mut components = Vec::new();
+        for component in raw.split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => return Err(PathError::ParentComponent),
+                name => {
+                    check_name(name)?;
+                    components.push(name);
+                }
+            }
+        }
+        Ok(RelativePath(components.join("/")))
+    }
+
+    /// Append a single name, such as an uploaded file's name.
+    pub fn join(&self, name: &str) -> Result<Self, PathError> {
+        if matches!(name, "" | "." | "..") || name.contains('/') {
+            return Err(PathError::NotAName);
+        }
+        check_name(name)?;
+        if self.0.is_empty() {
+            Ok(RelativePath(name.to_string()))
+        } else {
+            Ok(RelativePath(format!("{}/{}", self.0, name)))
+        }
+    }
+
+    /// Append another validated path, e.g. to place a client's path inside
+    /// its home directory.
+    pub fn join_path(&self, other: &RelativePath) -> Self {
+        match (self.is_root(), other.is_root()) {
+            (_, true) => self.clone(),
+            (true, false) => other.clone(),
+            (false, false) => RelativePath(format!("{}/{}", self.0, other.0)),
+        }
+    }
+
+    /// The first component and everything after it, or `None` for the root.
+    pub fn split_first(&self) -> Option<(&str, RelativePath)> {
+        if self.is_root() {
+            return None;
+        }
+        Some(match self.0.split_once('/') {
+            Some((first, rest)) => (first, RelativePath(rest.to_string())),
+            None => (self.0.as_str(), RelativePath::root()),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.split('/').filter(|c| !c.is_empty())
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().last()
+    }
+
+    pub fn parent(&self) -> Option<RelativePath> {
+        if self.is_root() {
+            return None;
+        }
+        Some(match self.0.rfind('/') {
+            Some(i) => RelativePath(self.0[..i].to_string()),
+            None => RelativePath::root(),
+        })
+    }
+
+    /// Whether `self` is `other` or lies beneath it, comparing whole components.
+    pub fn starts_with(&self, other: &RelativePath) -> bool {
+        other.is_root()
+            || self.0 == other.0
+            || (self.0.starts_with(&other.0) && self.0.as_bytes().get(other.0.len()) == Some(&b'/'))
+    }
+}
+
+impl fmt::Display for RelativePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for RelativePath {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RelativePath::parse(s)
+    }
+}
+
+/// `C:` style prefixes, which Windows treats as absolute.
+fn has_drive_prefix(raw: &str) -> bool {
+    let bytes = raw.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+fn check_name(name: &str) -> Result<(), PathError> {
+    if name.len() > MAX_COMPONENT_LEN {
+        return Err(PathError::TooLong);
+    }
+    for c in name.chars() {
+        if c == '\0' {
+            return Err(PathError::Nul);
+        }
+        if is_disallowed(c) {
+            return Err(PathError::InvalidCharacter(c));
+        }
+    }
+    Ok(())
+}
+
+/// Characters that have no business in a file name: control characters,
+/// backslashes, invisible formatting and bidi overrides that disguise what a
+/// name really says, and look-alikes of the path separator.
+fn is_disallowed(c: char) -> bool {
+    c.is_control()
+        || c == '\\'
+        || matches!(
+            c,
+            '\u{200B}'..='\u{200F}'
+                | '\u{2028}'..='\u{202E}'
+                | '\u{2060}'..='\u{2069}'
+                | '\u{FEFF}'
+                | '\u{2215}'
+                | '\u{2044}'
+                | '\u{FF0F}'
+                | '\u{FFFD}'
+        )
+}
+
+/// What to do when a path goes through a symbolic link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Follow links, as long as where they lead is still inside the storage root.
+    #[default]
+    Follow,
+    /// Refuse any path that goes through a link.
+    Refuse,
+}
+
+/// Maps validated relative paths onto the filesystem below a storage root.
+#[derive(Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+}
+
+impl Sandbox {
+    pub fn new(root: &Path, symlinks: SymlinkPolicy) -> std::io::Result<Self> {
+        Ok(Sandbox {
+            root: root.canonicalize()?,
+            symlinks,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn symlinks(&self) -> SymlinkPolicy {
+        self.symlinks
+    }
+
+    /// Filesystem location of `path`. The part of it that already exists is
+    /// checked for symlinks and, once canonicalised, must still be inside the
+    /// root. The returned path is not canonicalised, so a link itself can
+    /// still be deleted or renamed rather than its target.
+    pub fn resolve(&self, path: &RelativePath) -> Result<PathBuf, PathError> {
+        let mut full = self.root.clone();
+        let mut existing = self.root.clone();
+        let mut missing = false;
+
+        for component in path.components() {
+            full.push(component);
+            if missing {
+                continue;
+            }
+            match std::fs::symlink_metadata(&full) {
+                Ok(metadata) => {
+                    if metadata.file_type().is_symlink() && self.symlinks == SymlinkPolicy::Refuse {
+                        return Err(PathError::Symlink);
+                    }
+                    existing = full.clone();
+                }
+                Err(_) => missing = true,
+            }
+        }
+
+        if !self.contains(&existing) {
+            return Err(PathError::Escapes);
+        }
+        Ok(full)
+    }
+
+    /// Whether an existing filesystem path, with every symlink resolved, is
+    /// inside the root. Paths that cannot be resolved, such as dangling links,
+    /// are treated as outside.
+    pub fn contains(&self, full: &Path) -> bool {
+        match full.canonicalize() {
+            Ok(canonical) => canonical.starts_with(&self.root),
+            Err(_) => false,
+        }
+    }
+}
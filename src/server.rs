@@ -1,97 +1,500 @@
 use prost_types::Timestamp;
-use std::time::SystemTime;
-use tokio::{fs::File, io::AsyncReadExt};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt}};
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::transport::Server;
+use tonic::service::Routes;
+use tonic::transport::server::TcpIncoming;
+use tonic_health::ServingStatus;
+use tonic_web::GrpcWebLayer;
+use tower::Layer;
+use tracing::Instrument;
 
 use grpc_files::fileservice::{
-    CreateDirectoryRequest, CreateDirectoryResponse, DeleteDirectoryRequest, DeleteDirectoryResponse,
-    DeleteRequest, DeleteResponse, DownloadChunk, DownloadRequest, FileInfo, ListRequest,
-    ListResponse, UploadChunk, UploadResponse,
+    CopyRequest, CopyResponse, CreateDirectoryRequest, CreateDirectoryResponse,
+    DeleteDirectoryRequest, DeleteDirectoryResponse, DeleteRequest, DeleteResponse, DeltaChunk,
+    DeltaDownloadRequest, DeltaHeader, DeltaOp, DownloadChunk, DownloadRequest, SignatureRequest,
+    SignatureResponse, StatRequest, StatResponse, StatusRequest, StatusResponse, FileInfo, ListVolumesRequest, ListVolumesResponse, GetTagsRequest, ListRequest, ListResponse, MoveRequest,
+    MoveResponse, ImageInfo, PreviewRequest, PreviewResponse, RemoveTagsRequest, SearchRequest, SearchResponse, SetTagsRequest, TagsResponse,
+    UploadChunk, UploadResponse, delta_op::Op,
     file_service_server::{FileService, FileServiceServer},
 };
+use grpc_files::access::AccessTokens;
+use grpc_files::admission::Admission;
+use grpc_files::config::{Config, ConfigArgs, check_plaintext};
+use grpc_files::delta::{self, DeltaApplier};
+use grpc_files::error::FileError;
+use grpc_files::gateway;
+use grpc_files::identity;
+use grpc_files::listing::{self, ListEntry, ListOptions};
+use grpc_files::metadata::FileTags;
+use grpc_files::metrics::Metrics;
+use grpc_files::sandbox::{RelativePath, Sandbox, SymlinkPolicy};
+use grpc_files::ssh::{AuthorizedKeys, HostKey, SftpServer};
+use grpc_files::staging::StagedFile;
+use grpc_files::preview::{self, DEFAULT_PREVIEW_BYTES, MAX_PREVIEW_BYTES};
+use grpc_files::reload::{Trigger, Triggers};
+use grpc_files::throttle::{Direction, TransferLimits};
+use grpc_files::tls::ReloadableTls;
+#[cfg(unix)]
+use grpc_files::unix_socket;
+use grpc_files::volume::{Scope, Volume, Volumes, tree_usage};
+use grpc_files::webdav;
+
+/// Who is sending an upload and how much it is allowed to send.
+struct UploadBudget<'a> {
+    volume: &'a Volume,
+    client: &'a str,
+    declared: u64,
+    /// Bytes left under the volume's quota, if it has one.
+    quota_remaining: Option<u64>,
+}
+
+/// Limits a configuration reload replaces as a whole. Running transfers keep
+/// the copy they started with.
+struct Policies {
+    limits: TransferLimits,
+    max_upload_size: Option<u64>,
+    min_free_space: u64,
+}
+
+impl Policies {
+    fn from_config(config: &Config) -> Self {
+        Policies {
+            limits: TransferLimits::from_config(config),
+            max_upload_size: config.max_upload_size_bytes,
+            min_free_space: config.min_free_space_bytes,
+        }
+    }
+}
 
 #[derive(Clone)]
 struct GRPCFileStore {
-    storage_path: String,
+    volumes: Arc<Volumes>,
+    policies: Arc<RwLock<Arc<Policies>>>,
+    admission: Arc<Admission>,
+    started: Instant,
 }
 
 impl GRPCFileStore {
-    pub fn new(storage_path: String) -> Result<Self, Box<dyn std::error::Error>> {
-        std::fs::create_dir_all(&storage_path)?;
-        Ok(GRPCFileStore { storage_path })
+    pub fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(GRPCFileStore {
+            volumes: Volumes::open(config)?,
+            policies: Arc::new(RwLock::new(Arc::new(Policies::from_config(config)))),
+            admission: Admission::from_config(config),
+            started: Instant::now(),
+        })
+    }
+
+    fn policies(&self) -> Arc<Policies> {
+        self.policies.read().unwrap().clone()
     }
 
-    /// Resolve a relative path to an absolute path within storage, preventing directory traversal.
-    fn resolve_path(&self, relative_path: &str) -> Result<String, tonic::Status> {
-        let clean_path = relative_path.trim_start_matches('/').trim_end_matches('/');
+    /// Apply a reloaded configuration to the running service. Returns the
+    /// changes that only take effect after a restart.
+    fn reconfigure(&self, config: &Config) -> Vec<String> {
+        *self.policies.write().unwrap() = Arc::new(Policies::from_config(config));
+        self.admission.reconfigure(config);
+        self.volumes.reconfigure(config)
+    }
 
-        // Block path traversal attempts
-        if clean_path.contains("..") {
-            return Err(tonic::Status::invalid_argument("Path traversal not allowed"));
+    /// Refuse an upload before any data is written if its declared size is over
+    /// the limit, would not fit in the free space on the disk or would take the
+    /// volume over its quota. Replacing the file at `replacing` frees its space.
+    ///
+    /// Returns the budget the rest of the upload is checked against.
+    async fn check_upload_size<'a>(
+        &self,
+        volume: &'a Volume,
+        client: &'a str,
+        directory: &str,
+        replacing: &str,
+        declared: u64,
+    ) -> Result<UploadBudget<'a>, FileError> {
+        let quota_remaining = match volume.quota_remaining().await? {
+            Some(remaining) => Some(remaining.saturating_add(file_size(replacing).await)),
+            None => None,
+        };
+        if let Some(remaining) = quota_remaining
+            && declared > remaining
+        {
+            return Err(quota_error(volume, remaining));
         }
 
-        if clean_path.is_empty() {
-            Ok(self.storage_path.clone())
-        } else {
-            Ok(format!("{}/{}", self.storage_path, clean_path))
+        let policies = self.policies();
+        if let Some(max) = policies.max_upload_size
+            && declared > max
+        {
+            return Err(FileError::TooLarge {
+                size: declared,
+                limit: max,
+            });
         }
+        let budget = UploadBudget {
+            volume,
+            client,
+            declared,
+            quota_remaining,
+        };
+        if declared == 0 {
+            return Ok(budget);
+        }
+
+        let available = fs4::available_space(directory)
+            .map_err(|e| FileError::Internal(format!("Failed to check free space: {}", e)))?;
+        if declared.saturating_add(policies.min_free_space) > available {
+            return Err(FileError::NotEnoughSpace {
+                needed: declared,
+                available: available.saturating_sub(policies.min_free_space),
+            });
+        }
+        Ok(budget)
     }
 
-    /// Check if a path exists and is a directory.
-    async fn ensure_directory_exists(&self, path: &str) -> Result<(), tonic::Status> {
-        let path = std::path::Path::new(path);
-        if path.exists() {
-            if path.is_dir() {
-                Ok(())
-            } else {
-                Err(tonic::Status::failed_precondition("Path exists but is not a directory"))
+    /// Abort an upload as soon as it sends more than it declared or than the
+    /// limit or the volume's quota allows.
+    fn check_received(&self, received: u64, budget: &UploadBudget) -> Result<(), FileError> {
+        if let Some(remaining) = budget.quota_remaining
+            && received > remaining
+        {
+            return Err(quota_error(budget.volume, remaining));
+        }
+        if budget.declared > 0 && received > budget.declared {
+            return Err(FileError::SizeMismatch {
+                declared: budget.declared,
+                received,
+            });
+        }
+        if let Some(max) = self.policies().max_upload_size
+            && received > max
+        {
+            return Err(FileError::TooLarge {
+                size: received,
+                limit: max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rebuild a file from a delta stream into `out`, using the file at
+    /// `basis_path` (if any) for copied blocks. Returns the size of the new file.
+    async fn apply_delta_stream(
+        &self,
+        stream: &mut tonic::Streaming<DeltaChunk>,
+        first_chunk: DeltaChunk,
+        header: &DeltaHeader,
+        basis_path: &str,
+        out: &mut File,
+        budget: &UploadBudget<'_>,
+    ) -> Result<u64, tonic::Status> {
+        let mut basis = File::open(basis_path).await.ok();
+        let mut applier = DeltaApplier::new(header.block_size);
+
+        let mut trailer = None;
+        let mut next = Some(first_chunk);
+        while let Some(chunk) = next {
+            self.policies()
+                .limits
+                .throttle(budget.client, Direction::Upload, literal_bytes(&chunk.ops))
+                .await;
+            for op in chunk.ops {
+                applier
+                    .apply(basis.as_mut(), out, op)
+                    .await
+                    .map_err(|e| delta_error(&header.path, e))?;
             }
+            self.check_received(applier.written(), budget)?;
+            if chunk.trailer.is_some() {
+                trailer = chunk.trailer;
+            }
+            next = stream.message().await?;
+        }
+
+        let trailer = trailer
+            .ok_or_else(|| FileError::InvalidRequest("Delta stream ended without a trailer".to_string()))?;
+        Ok(applier.verify(&trailer).map_err(|e| delta_error(&header.path, e))?)
+    }
+
+    /// Reject a metadata RPC if its client is over the configured request rate.
+    fn check_rate<T>(&self, request: &tonic::Request<T>) -> Result<(), tonic::Status> {
+        let client = identity::client_name(request.extensions());
+        self.policies().limits.allow_request(&client).map_err(|retry_after| {
+            tracing::debug!(client = %client, "request rate limit exceeded");
+            FileError::RateLimited { retry_after }.into()
+        })
+    }
+
+    /// Check that the client path `path`, stored at `full_path`, is a directory.
+    async fn ensure_directory_exists(&self, path: &RelativePath, full_path: &str) -> Result<(), FileError> {
+        let full_path = std::path::Path::new(full_path);
+        if full_path.is_dir() {
+            Ok(())
+        } else if full_path.exists() {
+            Err(FileError::NotADirectory { path: path.to_string() })
         } else {
-            Err(tonic::Status::not_found("Directory does not exist"))
+            Err(FileError::not_found(path))
+        }
+    }
+
+    /// Build a `FileInfo` for a directory entry, including its tags and attributes.
+    fn file_info(
+        &self,
+        scope: &Scope,
+        filename: String,
+        item_path: &RelativePath,
+        metadata: &std::fs::Metadata,
+    ) -> FileInfo {
+        let tags = scope.metadata().get(&scope.key(item_path));
+
+        FileInfo {
+            filename,
+            size: listing::entry_size(metadata),
+            upload_time: listing::entry_time(metadata).map(Timestamp::from),
+            is_directory: metadata.is_dir(),
+            path: item_path.to_string(),
+            tags: tags.tags.into_iter().collect(),
+            attributes: tags.attributes.into_iter().collect(),
+        }
+    }
+
+    /// Resolve the source and destination of a move or copy, checking both ends.
+    fn resolve_transfer(
+        &self,
+        scope: &Scope,
+        source: &RelativePath,
+        destination: &RelativePath,
+    ) -> Result<(String, String), FileError> {
+        if source.is_root() || destination.is_root() {
+            return Err(FileError::InvalidRequest("Source and destination are required".to_string()));
+        }
+        if scope.is_top(source) {
+            return Err(FileError::PermissionDenied {
+                path: Some(source.to_string()),
+                message: "Shared directories cannot be moved".to_string(),
+            });
+        }
+        if destination.starts_with(source) {
+            return Err(FileError::InvalidRequest("Destination is inside the source".to_string()));
+        }
+
+        let source_path = scope.resolve_path(source)?;
+        let destination_path = scope.resolve_path(destination)?;
+
+        if !std::path::Path::new(&source_path).exists() {
+            return Err(FileError::not_found(source));
+        }
+        if std::path::Path::new(&destination_path).exists() {
+            return Err(FileError::AlreadyExists { path: destination.to_string() });
+        }
+        if let Some(parent) = std::path::Path::new(&destination_path).parent()
+            && !parent.is_dir()
+        {
+            return Err(FileError::not_found(destination.parent().unwrap_or_default()));
         }
+
+        Ok((source_path, destination_path))
     }
 }
 
+/// Size of the file at `path`, or 0 if there is none.
+async fn file_size(path: &str) -> u64 {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    }
+}
+
+fn quota_error(volume: &Volume, remaining: u64) -> FileError {
+    FileError::QuotaExceeded {
+        volume: volume.name().to_string(),
+        remaining,
+    }
+}
+
+fn tags_response(path: String, tags: FileTags) -> TagsResponse {
+    TagsResponse {
+        path,
+        tags: tags.tags.into_iter().collect(),
+        attributes: tags.attributes.into_iter().collect(),
+    }
+}
+
+async fn create_staged(staged: &StagedFile, path: &str) -> Result<File, FileError> {
+    File::create(staged.path())
+        .await
+        .map_err(|e| fs_error("stage", path, e))
+}
+
+/// Bytes of new data carried by delta operations, as opposed to block references.
+fn literal_bytes(ops: &[DeltaOp]) -> u64 {
+    ops.iter()
+        .map(|op| match &op.op {
+            Some(Op::Literal(data)) => data.len() as u64,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Check a block size the client picked. 0 is fine and leaves the choice to
+/// the server, or stands for "no blocks" where the client has no copy.
+fn check_block_size(block_size: u32) -> Result<(), FileError> {
+    if block_size == 0 || delta::valid_block_size(block_size) {
+        Ok(())
+    } else {
+        Err(FileError::InvalidRequest(format!("Unsupported block size {}", block_size)))
+    }
+}
+
+fn delta_error(path: &str, e: std::io::Error) -> FileError {
+    match e.kind() {
+        std::io::ErrorKind::InvalidData => FileError::Corrupt(e.to_string()),
+        _ => write_error(path, e),
+    }
+}
+
+/// Map a failed write to a staged upload for `path`.
+fn write_error(path: &str, e: std::io::Error) -> FileError {
+    match e.kind() {
+        // The sweeper removed the staged file while the client was idle
+        std::io::ErrorKind::NotFound => FileError::UploadExpired,
+        _ => fs_error("write", path, e),
+    }
+}
+
+/// Map a failed filesystem call on the client path `path`, telling apart
+/// the failures a client can do something about.
+fn fs_error(action: &'static str, path: impl std::fmt::Display, e: std::io::Error) -> FileError {
+    let path = path.to_string();
+    match e.kind() {
+        std::io::ErrorKind::NotFound => FileError::NotFound { path },
+        std::io::ErrorKind::AlreadyExists => FileError::AlreadyExists { path },
+        std::io::ErrorKind::NotADirectory => FileError::NotADirectory { path },
+        std::io::ErrorKind::IsADirectory => FileError::IsADirectory { path },
+        std::io::ErrorKind::DirectoryNotEmpty => FileError::DirectoryNotEmpty { path },
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => FileError::StorageFull,
+        _ => FileError::io(action, path, e),
+    }
+}
+
+/// Recursively copy a file or directory tree.
+///
+/// Symlinks inside the tree are only followed to files the sandbox allows;
+/// linked directories are skipped so a link cannot make the copy loop.
+fn copy_recursive(
+    source: &std::path::Path,
+    destination: &std::path::Path,
+    sandbox: &Sandbox,
+) -> std::io::Result<()> {
+    if source.is_dir() {
+        std::fs::create_dir(destination)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_symlink()
+                && (sandbox.symlinks() == SymlinkPolicy::Refuse
+                    || !sandbox.contains(&path)
+                    || path.is_dir())
+            {
+                tracing::debug!(path = %path.display(), "skipping symlink while copying");
+                continue;
+            }
+            copy_recursive(&path, &destination.join(entry.file_name()), sandbox)?;
+        }
+    } else {
+        std::fs::copy(source, destination)?;
+    }
+    Ok(())
+}
+
 #[tonic::async_trait]
 impl FileService for GRPCFileStore {
     async fn upload(
         &self,
         request: tonic::Request<tonic::Streaming<UploadChunk>>,
     ) -> Result<tonic::Response<UploadResponse>, tonic::Status> {
+        let client = identity::client_name(request.extensions());
+        let caller = identity::peer_identity(request.extensions());
+        let _permit = self.admission.admit(&client).await?;
         let mut stream = request.into_inner();
-        let first_chunk = stream.message().await?.unwrap();
+        let first_chunk = stream
+            .message()
+            .await?
+            .ok_or_else(|| FileError::InvalidRequest("Empty upload stream".to_string()))?;
         let filename = first_chunk.filename;
         let upload_id = first_chunk.upload_id;
 
-        // Handle target directory from first chunk
-        let target_dir = if !first_chunk.target_directory.is_empty() {
-            self.resolve_path(&first_chunk.target_directory)?
-        } else {
-            self.storage_path.clone()
-        };
+        // Handle volume and target directory from first chunk
+        let scope = self.volumes.scope(&first_chunk.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let target = scope.parse_path(&first_chunk.target_directory)?;
+        let target_dir = scope.resolve_path(&target)?;
+        let child = scope.child_path(&target, &filename)?;
+        let path = child.to_string();
+        let final_path = scope.resolve_path(&child)?;
 
         // Ensure target directory exists
-        self.ensure_directory_exists(&target_dir).await?;
+        self.ensure_directory_exists(&target, &target_dir).await?;
+        let declared_size = first_chunk.file_size;
+        let budget = self
+            .check_upload_size(scope.volume(), &client, &target_dir, &final_path, declared_size)
+            .await?;
 
-        let temp_path = format!("{}/{}.tmp", target_dir, upload_id);
-        let mut file = tokio::fs::File::create(&temp_path)
-            .await
-            .map_err(|e| tonic::Status::internal(format!("Failed to create file: {}", e)))?;
+        let staged = scope.volume().staging().begin();
 
-        let mut total_size = first_chunk.data.len() as u64;
+        let result = async {
+            let mut file = create_staged(&staged, &path).await?;
 
-        // write first chunk
-        tokio::io::AsyncWriteExt::write_all(&mut file, &first_chunk.data).await?;
+            let mut total_size = first_chunk.data.len() as u64;
+            self.check_received(total_size, &budget)?;
 
-        // write the rest of the chunks
-        while let Some(chunk) = stream.message().await? {
-            total_size += chunk.data.len() as u64;
-            tokio::io::AsyncWriteExt::write_all(&mut file, &chunk.data).await?;
-        }
+            // write first chunk
+            self.policies()
+                .limits
+                .throttle(&client, Direction::Upload, total_size)
+                .await;
+            tokio::io::AsyncWriteExt::write_all(&mut file, &first_chunk.data)
+                .await
+                .map_err(|e| write_error(&path, e))?;
+
+            // write the rest of the chunks
+            while let Some(chunk) = stream.message().await? {
+                total_size += chunk.data.len() as u64;
+                self.check_received(total_size, &budget)?;
+                self.policies()
+                    .limits
+                    .throttle(&client, Direction::Upload, chunk.data.len() as u64)
+                    .await;
+                tokio::io::AsyncWriteExt::write_all(&mut file, &chunk.data)
+                    .await
+                    .map_err(|e| write_error(&path, e))?;
+            }
+
+            if declared_size > 0 && total_size < declared_size {
+                return Err(FileError::SizeMismatch {
+                    declared: declared_size,
+                    received: total_size,
+                }
+                .into());
+            }
 
-        let final_path = format!("{}/{}", target_dir, filename);
-        tokio::fs::rename(&temp_path, &final_path).await?;
+            let replaced = file_size(&final_path).await;
+            staged
+                .commit(file, std::path::Path::new(&final_path))
+                .await
+                .map_err(|e| write_error(&path, e))?;
+            scope.volume().record_usage(total_size, replaced);
+            Ok::<_, tonic::Status>(total_size)
+        }
+        .await;
+        let total_size = match result {
+            Ok(size) => size,
+            Err(e) => {
+                tracing::warn!(path = %final_path, error = %e.message(), "upload failed");
+                return Err(e);
+            }
+        };
+        tracing::info!(path = %final_path, bytes = total_size, "upload complete");
 
         Ok(tonic::Response::new(UploadResponse {
             file_id: upload_id,
@@ -107,30 +510,72 @@ impl FileService for GRPCFileStore {
         &self,
         request: tonic::Request<DownloadRequest>,
     ) -> Result<tonic::Response<Self::DownloadStream>, tonic::Status> {
-        let filename = request.into_inner().file_name;
-        let full_path = self.storage_path.clone() + "/" + filename.as_str();
+        let client = identity::client_name(request.extensions());
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let path = scope.parse_path(&req.file_name)?;
+        let full_path = scope.resolve_path(&path)?;
+        let permit = self.admission.admit(&client).await?;
+        let mut file = File::open(&full_path)
+            .await
+            .map_err(|e| fs_error("open", &path, e))?;
+        let metadata = file.metadata().await.map_err(|e| fs_error("read", &path, e))?;
+        if metadata.is_dir() {
+            return Err(FileError::IsADirectory { path: path.to_string() }.into());
+        }
+        if req.offset > metadata.len() {
+            return Err(FileError::OutOfRange {
+                offset: req.offset,
+                size: metadata.len(),
+            }
+            .into());
+        }
+        file.seek(std::io::SeekFrom::Start(req.offset))
+            .await
+            .map_err(|e| fs_error("read", &path, e))?;
+        let mut file = file.take(match req.length {
+            0 => u64::MAX,
+            length => length,
+        });
 
         let (tx, rx) = tokio::sync::mpsc::channel(32);
+        let policies = self.policies();
 
-        tokio::spawn(async move {
-            let mut file = File::open(full_path).await.unwrap();
-            let mut buffer = vec![0u8; 1024 * 1024];
-            loop {
-                match file.read(&mut buffer[..]).await {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        let chunk = DownloadChunk {
-                            data: buffer[..n].to_vec(),
-                        };
-
-                        if tx.send(Ok(chunk)).await.is_err() {
+        tokio::spawn(
+            async move {
+                let _permit = permit;
+                let mut buffer = vec![0u8; 1024 * 1024];
+                let mut sent = 0u64;
+                loop {
+                    match file.read(&mut buffer[..]).await {
+                        Ok(0) => {
+                            tracing::info!(path = %full_path, bytes = sent, "download complete");
+                            break;
+                        }
+                        Ok(n) => {
+                            policies.limits.throttle(&client, Direction::Download, n as u64).await;
+                            let chunk = DownloadChunk {
+                                data: buffer[..n].to_vec(),
+                            };
+
+                            if tx.send(Ok(chunk)).await.is_err() {
+                                tracing::warn!(path = %full_path, bytes = sent, "download cancelled by client");
+                                break;
+                            }
+                            sent += n as u64;
+                        }
+                        Err(e) => {
+                            tracing::warn!(path = %full_path, bytes = sent, error = %e, "download failed");
+                            // Say so rather than let the download look complete
+                            let _ = tx.send(Err(fs_error("read", &path, e).into())).await;
                             break;
                         }
                     }
-                    Err(_e) => break,
                 }
             }
-        });
+            .in_current_span(),
+        );
 
         let res = ReceiverStream::new(rx);
 
@@ -141,12 +586,23 @@ impl FileService for GRPCFileStore {
         &self,
         request: tonic::Request<DeleteRequest>,
     ) -> Result<tonic::Response<DeleteResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
         let req = request.into_inner();
-        let full_path = self.resolve_path(&req.file_name)?;
-        println!("{}", full_path);
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let path = scope.parse_path(&req.file_name)?;
+        let full_path = scope.resolve_path(&path)?;
+        let size = file_size(&full_path).await;
         tokio::fs::remove_file(&full_path)
             .await
-            .map_err(|e| tonic::Status::not_found(e.to_string()))?;
+            .map_err(|e| fs_error("delete", &path, e))?;
+        scope.volume().record_usage(0, size);
+        scope
+            .metadata()
+            .forget(&scope.key(&path))
+            .map_err(|e| FileError::io("update tags of", &path, e))?;
+        tracing::info!(path = %full_path, "file deleted");
         Ok(tonic::Response::new(DeleteResponse {}))
     }
 
@@ -154,71 +610,88 @@ impl FileService for GRPCFileStore {
         &self,
         request: tonic::Request<ListRequest>,
     ) -> Result<tonic::Response<ListResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
         let req = request.into_inner();
-        let request_path = req.path;
-        let full_path = self.resolve_path(&request_path)?;
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let options = ListOptions::from_request(&req)?;
+        let request_path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&request_path)?;
 
         // Ensure the path exists and is a directory
-        self.ensure_directory_exists(&full_path).await?;
+        self.ensure_directory_exists(&request_path, &full_path).await?;
 
         let mut entries = tokio::fs::read_dir(&full_path)
             .await
-            .map_err(|e| tonic::Status::internal(format!("Failed to read directory: {}", e)))?;
+            .map_err(|e| fs_error("list", &request_path, e))?;
 
-        let mut items: Vec<FileInfo> = Vec::new();
+        let mut matching: Vec<ListEntry> = Vec::new();
 
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            let filename = entry.file_name()
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| fs_error("list", &request_path, e))?
+        {
+            let name = entry.file_name()
                 .into_string()
                 .unwrap_or_default();
 
-            // Skip hidden files/directories
-            if filename.starts_with('.') {
+            // The server's own files stay hidden even when hidden files are shown
+            if scope.hides(&request_path, &name) {
                 continue;
             }
 
-            let metadata = entry.metadata().await.unwrap();
-            let is_dir = metadata.is_dir();
-
-            // For directories, use 0 size
-            let size = if is_dir { 0 } else { metadata.len() };
-
-            // Get creation time (use modified for directories as fallback)
-            let created = if is_dir {
-                metadata.modified().ok()
-            } else {
-                metadata.created().ok()
+            let Some(metadata) = scope.entry_metadata(&request_path, &entry).await else {
+                continue;
             };
-            let upload_time = created.map(Timestamp::from);
+            if !options.wants(&name, metadata.is_dir()) {
+                continue;
+            }
 
-            // Build relative path for this item
-            let item_path = if request_path.is_empty() {
-                filename.clone()
-            } else {
-                format!("{}/{}", request_path, filename)
-            };
+            // Tag filters narrow down files; directories stay so they can still be browsed
+            if !metadata.is_dir() && !req.tag_filters.is_empty() {
+                let Ok(item_path) = request_path.join(&name) else {
+                    continue;
+                };
+                if !scope.metadata().get(&scope.key(&item_path)).matches_all(&req.tag_filters) {
+                    continue;
+                }
+            }
 
-            items.push(FileInfo {
-                filename,
-                size,
-                upload_time,
-                is_directory: is_dir,
-                path: item_path,
-            });
+            matching.push(ListEntry { name, metadata });
         }
 
-        // Sort: directories first, then alphabetically
-        items.sort_by(|a, b| {
-            match (a.is_directory, b.is_directory) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.filename.cmp(&b.filename),
+        // Shared directories show up at the top of the client's home
+        if request_path.is_root() {
+            for (name, path) in scope.shared() {
+                if let Ok(metadata) = tokio::fs::metadata(path).await
+                    && options.wants(name, true)
+                {
+                    matching.push(ListEntry { name: name.to_string(), metadata });
+                }
             }
-        });
+        }
+
+        let total_entries = matching.len() as u64;
+        options.sort(&mut matching);
+        let (page, next_page_token) = options.page(matching);
+
+        // Only the returned page is turned into FileInfo, with its tags looked up
+        let mut items: Vec<FileInfo> = Vec::with_capacity(page.len());
+        for entry in page {
+            let Ok(item_path) = request_path.join(&entry.name) else {
+                continue;
+            };
+            items.push(self.file_info(&scope, entry.name, &item_path, &entry.metadata));
+        }
+
+        tracing::debug!(path = %full_path, entries = items.len(), total = total_entries, "listed directory");
 
         Ok(tonic::Response::new(ListResponse {
             files: items,
-            current_path: request_path,
+            current_path: request_path.to_string(),
+            next_page_token,
+            total_entries,
         }))
     }
 
@@ -226,28 +699,29 @@ impl FileService for GRPCFileStore {
         &self,
         request: tonic::Request<CreateDirectoryRequest>,
     ) -> Result<tonic::Response<CreateDirectoryResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
         let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
 
-        // Resolve the parent path
-        let parent_path = self.resolve_path(&req.path)?;
+        // Validate the parent path and the new directory's name
+        let parent = scope.parse_path(&req.path)?;
         let dir_name = req.name.trim().trim_end_matches('/');
-
-        // Validate directory name
-        if dir_name.is_empty() || dir_name.contains('/') {
-            return Err(tonic::Status::invalid_argument("Invalid directory name"));
-        }
-
-        let full_path = format!("{}/{}", parent_path, dir_name);
+        let path = scope.child_path(&parent, dir_name)?;
+        let full_path = scope.resolve_path(&path)?;
 
         // Check if already exists
         if std::path::Path::new(&full_path).exists() {
-            return Err(tonic::Status::already_exists("Directory already exists"));
+            return Err(FileError::AlreadyExists { path: path.to_string() }.into());
         }
 
         // Create the directory
         tokio::fs::create_dir(&full_path)
             .await
-            .map_err(|e| tonic::Status::internal(format!("Failed to create directory: {}", e)))?;
+            .map_err(|e| fs_error("create directory", &path, e))?;
+
+        tracing::info!(path = %full_path, "directory created");
 
         Ok(tonic::Response::new(CreateDirectoryResponse {}))
     }
@@ -256,21 +730,38 @@ impl FileService for GRPCFileStore {
         &self,
         request: tonic::Request<DeleteDirectoryRequest>,
     ) -> Result<tonic::Response<DeleteDirectoryResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
         let req = request.into_inner();
-        let full_path = self.resolve_path(&req.path)?;
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let relative = scope.parse_path(&req.path)?;
+        if relative.is_root() {
+            return Err(FileError::InvalidRequest("Cannot delete the storage root".to_string()).into());
+        }
+        if scope.is_top(&relative) {
+            return Err(FileError::PermissionDenied {
+                path: Some(relative.to_string()),
+                message: "Shared directories cannot be deleted".to_string(),
+            }
+            .into());
+        }
+        let full_path = scope.resolve_path(&relative)?;
         let path = std::path::Path::new(&full_path);
 
         // Verify path exists and is a directory
         if !path.exists() {
-            return Err(tonic::Status::not_found("Directory not found"));
+            return Err(FileError::not_found(&relative).into());
         }
 
         if !path.is_dir() {
-            return Err(tonic::Status::failed_precondition("Path is not a directory"));
+            return Err(FileError::NotADirectory { path: relative.to_string() }.into());
         }
 
         // Check if directory is empty
-        let mut entries = tokio::fs::read_dir(&full_path).await.unwrap();
+        let mut entries = tokio::fs::read_dir(&full_path)
+            .await
+            .map_err(|e| fs_error("list", &relative, e))?;
         let mut is_empty = true;
         while let Ok(Some(entry)) = entries.next_entry().await {
             let name = entry.file_name().to_string_lossy().to_string();
@@ -281,56 +772,847 @@ impl FileService for GRPCFileStore {
         }
 
         if !is_empty && !req.recursive {
-            return Err(tonic::Status::failed_precondition(
-                "Directory is not empty. Use recursive=true to delete."
-            ));
+            return Err(FileError::DirectoryNotEmpty { path: relative.to_string() }.into());
         }
 
         // Delete the directory
         if req.recursive {
-            tokio::fs::remove_dir_all(&full_path)
-                .await
-                .map_err(|e| tonic::Status::internal(format!("Failed to delete directory: {}", e)))?;
+            let removed = tokio::fs::remove_dir_all(&full_path).await;
+            // Even a partly failed delete frees an unknown amount
+            scope.volume().forget_usage();
+            removed.map_err(|e| fs_error("delete", &relative, e))?;
         } else {
             tokio::fs::remove_dir(&full_path)
                 .await
-                .map_err(|e| tonic::Status::internal(format!("Failed to delete directory: {}", e)))?;
+                .map_err(|e| fs_error("delete", &relative, e))?;
         }
 
+        scope
+            .metadata()
+            .forget(&scope.key(&relative))
+            .map_err(|e| FileError::io("update tags of", &relative, e))?;
+
+        tracing::info!(path = %full_path, recursive = req.recursive, "directory deleted");
+
         Ok(tonic::Response::new(DeleteDirectoryResponse {}))
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = grpc_files::config::Config::load()?;
-    let auth_dir = grpc_files::config::Config::get_auth_dir()?;
+    async fn move_file(
+        &self,
+        request: tonic::Request<MoveRequest>,
+    ) -> Result<tonic::Response<MoveResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let (source, destination) = (scope.parse_path(&req.source)?, scope.parse_path(&req.destination)?);
+        let (source_path, destination_path) = self.resolve_transfer(&scope, &source, &destination)?;
+
+        tokio::fs::rename(&source_path, &destination_path)
+            .await
+            .map_err(|e| FileError::io("move", &source, e))?;
+
+        scope
+            .metadata()
+            .rename(&scope.key(&source), &scope.key(&destination))
+            .map_err(|e| FileError::io("update tags of", &destination, e))?;
+
+        tracing::info!(source = %source_path, destination = %destination_path, "moved");
+
+        Ok(tonic::Response::new(MoveResponse {}))
+    }
+
+    async fn copy_file(
+        &self,
+        request: tonic::Request<CopyRequest>,
+    ) -> Result<tonic::Response<CopyResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let (source, destination) = (scope.parse_path(&req.source)?, scope.parse_path(&req.destination)?);
+        let (source_path, destination_path) = self.resolve_transfer(&scope, &source, &destination)?;
+
+        // Only measured under a quota, which is the only time usage is tracked
+        let mut copied = 0;
+        if let Some(remaining) = scope.volume().quota_remaining().await? {
+            let from = std::path::PathBuf::from(&source_path);
+            copied = tokio::task::spawn_blocking(move || match std::fs::metadata(&from) {
+                Ok(metadata) if metadata.is_dir() => tree_usage(&from).0,
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            })
+            .await
+            .map_err(|e| FileError::Internal(e.to_string()))?;
+            if copied > remaining {
+                return Err(quota_error(scope.volume(), remaining).into());
+            }
+        }
+
+        let (from, to) = (source_path.clone(), destination_path.clone());
+        let sandbox = scope.sandbox(&source).clone();
+        tokio::task::spawn_blocking(move || {
+            copy_recursive(std::path::Path::new(&from), std::path::Path::new(&to), &sandbox)
+        })
+        .await
+        .map_err(|e| FileError::Internal(e.to_string()))?
+        .map_err(|e| fs_error("copy", &source, e))?;
+        scope.volume().record_usage(copied, 0);
+
+        scope
+            .metadata()
+            .copy(&scope.key(&source), &scope.key(&destination))
+            .map_err(|e| FileError::io("update tags of", &destination, e))?;
+
+        tracing::info!(source = %source_path, destination = %destination_path, "copied");
+
+        Ok(tonic::Response::new(CopyResponse {}))
+    }
+
+    async fn search_files(
+        &self,
+        request: tonic::Request<SearchRequest>,
+    ) -> Result<tonic::Response<SearchResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let root = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&root)?;
+        self.ensure_directory_exists(&root, &full_path).await?;
+
+        let query = req.query.to_lowercase();
+        let mut results = Vec::new();
+        let mut pending = vec![(full_path, root.clone())];
+        if root.is_root() {
+            for (name, path) in scope.shared() {
+                if let Ok(item_path) = root.join(name) {
+                    pending.push((path.to_string_lossy().to_string(), item_path));
+                }
+            }
+        }
+
+        while let Some((dir, relative)) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&dir)
+                .await
+                .map_err(|e| fs_error("list", &relative, e))?;
+
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let filename = entry.file_name().to_string_lossy().to_string();
+                if filename.starts_with('.') || scope.hides(&relative, &filename) {
+                    continue;
+                }
+                let Some(metadata) = scope.entry_metadata(&relative, &entry).await else {
+                    continue;
+                };
+                let Ok(item_path) = relative.join(&filename) else {
+                    continue;
+                };
+
+                // Linked directories are listed but not searched, so a link
+                // cannot send the search round in circles
+                let is_link = entry.file_type().await.is_ok_and(|t| t.is_symlink());
+                if metadata.is_dir() && !is_link {
+                    pending.push((entry.path().to_string_lossy().to_string(), item_path.clone()));
+                }
+
+                if !filename.to_lowercase().contains(&query)
+                    || !scope.metadata().get(&scope.key(&item_path)).matches_all(&req.tag_filters)
+                {
+                    continue;
+                }
+
+                results.push(self.file_info(&scope, filename, &item_path, &metadata));
+            }
+        }
+
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+        tracing::debug!(query = %req.query, matches = results.len(), "search complete");
+
+        Ok(tonic::Response::new(SearchResponse { files: results }))
+    }
+
+    async fn set_tags(
+        &self,
+        request: tonic::Request<SetTagsRequest>,
+    ) -> Result<tonic::Response<TagsResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+        if !std::path::Path::new(&full_path).exists() {
+            return Err(FileError::not_found(&path).into());
+        }
+
+        let tags = scope
+            .metadata()
+            .set(&scope.key(&path), req.tags, req.attributes, req.replace)
+            .map_err(|e| FileError::io("update tags of", &path, e))?;
+        tracing::info!(path = %path, tags = tags.tags.len(), attributes = tags.attributes.len(), "tags set");
+
+        Ok(tonic::Response::new(tags_response(path.to_string(), tags)))
+    }
+
+    async fn remove_tags(
+        &self,
+        request: tonic::Request<RemoveTagsRequest>,
+    ) -> Result<tonic::Response<TagsResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+        if !std::path::Path::new(&full_path).exists() {
+            return Err(FileError::not_found(&path).into());
+        }
+
+        let tags = scope
+            .metadata()
+            .remove(&scope.key(&path), &req.tags, &req.attribute_keys)
+            .map_err(|e| FileError::io("update tags of", &path, e))?;
+        tracing::info!(path = %path, "tags removed");
+
+        Ok(tonic::Response::new(tags_response(path.to_string(), tags)))
+    }
+
+    async fn get_tags(
+        &self,
+        request: tonic::Request<GetTagsRequest>,
+    ) -> Result<tonic::Response<TagsResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+        if !std::path::Path::new(&full_path).exists() {
+            return Err(FileError::not_found(&path).into());
+        }
+
+        let tags = scope.metadata().get(&scope.key(&path));
+        Ok(tonic::Response::new(tags_response(path.to_string(), tags)))
+    }
+
+    async fn preview(
+        &self,
+        request: tonic::Request<PreviewRequest>,
+    ) -> Result<tonic::Response<PreviewResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+
+        let mut file = File::open(&full_path)
+            .await
+            .map_err(|e| fs_error("open", &path, e))?;
+        let metadata = file.metadata().await.map_err(|e| fs_error("read", &path, e))?;
+        if metadata.is_dir() {
+            return Err(FileError::IsADirectory { path: path.to_string() }.into());
+        }
+
+        let limit = match req.max_bytes {
+            0 => DEFAULT_PREVIEW_BYTES,
+            n => n.min(MAX_PREVIEW_BYTES),
+        };
+        let mut data = Vec::with_capacity(limit.min(metadata.len()) as usize);
+        (&mut file)
+            .take(limit)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| fs_error("read", &path, e))?;
+
+        let (mime_type, is_text) = preview::detect_mime(&data);
+        if is_text && req.max_lines > 0 {
+            data.truncate(preview::truncate_lines(&data, req.max_lines as usize));
+        }
+        let image = if mime_type.starts_with("image/") {
+            preview::image_dimensions(&data).map(|(width, height)| ImageInfo { width, height })
+        } else {
+            None
+        };
+
+        Ok(tonic::Response::new(PreviewResponse {
+            truncated: (data.len() as u64) < metadata.len(),
+            total_size: metadata.len(),
+            data,
+            mime_type,
+            is_text,
+            image,
+        }))
+    }
+
+    async fn get_signatures(
+        &self,
+        request: tonic::Request<SignatureRequest>,
+    ) -> Result<tonic::Response<SignatureResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+
+        let file = std::fs::File::open(&full_path).map_err(|e| fs_error("open", &path, e))?;
+        let metadata = file.metadata().map_err(|e| fs_error("read", &path, e))?;
+        if metadata.is_dir() {
+            return Err(FileError::IsADirectory { path: path.to_string() }.into());
+        }
 
-    let cert = tokio::fs::read_to_string(auth_dir.join("server-cert.pem")).await?;
-    let key = tokio::fs::read_to_string(auth_dir.join("server-key.pem")).await?;
-    let server_identity = Identity::from_pem(cert, key);
+        let file_size = metadata.len();
+        check_block_size(req.block_size)?;
+        let block_size = match req.block_size {
+            0 => delta::block_size_for(file_size),
+            n => n,
+        };
+        let blocks = tokio::task::spawn_blocking(move || delta::signatures(file, block_size))
+            .await
+            .map_err(|e| FileError::Internal(e.to_string()))?
+            .map_err(|e| fs_error("read", &path, e))?;
 
-    let client_ca_cert = tokio::fs::read_to_string(auth_dir.join("ca-cert.pem")).await?;
-    let client_ca_cert = Certificate::from_pem(client_ca_cert);
+        Ok(tonic::Response::new(SignatureResponse {
+            block_size,
+            file_size,
+            blocks,
+        }))
+    }
 
-    let tls = ServerTlsConfig::new()
-        .identity(server_identity)
-        .client_ca_root(client_ca_cert);
+    async fn delta_upload(
+        &self,
+        request: tonic::Request<tonic::Streaming<DeltaChunk>>,
+    ) -> Result<tonic::Response<UploadResponse>, tonic::Status> {
+        let client = identity::client_name(request.extensions());
+        let caller = identity::peer_identity(request.extensions());
+        let _permit = self.admission.admit(&client).await?;
+        let mut stream = request.into_inner();
+        let first_chunk = stream
+            .message()
+            .await?
+            .ok_or_else(|| FileError::InvalidRequest("Empty delta stream".to_string()))?;
+        let header = first_chunk
+            .header
+            .clone()
+            .ok_or_else(|| FileError::InvalidRequest("First chunk must carry a header".to_string()))?;
+        check_block_size(header.block_size)?;
+
+        let scope = self.volumes.scope(&header.volume, caller.as_deref())?;
+        scope.volume().check_writable()?;
+        let path = scope.parse_path(&header.path)?;
+        let (Some(parent), Some(filename)) = (path.parent(), path.file_name()) else {
+            return Err(FileError::InvalidRequest("Give a file name to upload to".to_string()).into());
+        };
+        let filename = filename.to_string();
+        let parent_path = scope.resolve_path(&parent)?;
+        let full_path = scope.resolve_path(&path)?;
+        self.ensure_directory_exists(&parent, &parent_path).await?;
+        let budget = self
+            .check_upload_size(scope.volume(), &client, &parent_path, &full_path, header.file_size)
+            .await?;
+
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let staged = scope.volume().staging().begin();
+
+        let result = async {
+            let mut file = create_staged(&staged, &header.path).await?;
+            let size = self
+                .apply_delta_stream(&mut stream, first_chunk, &header, &full_path, &mut file, &budget)
+                .await?;
+
+            // Swap the rebuilt file in atomically
+            let replaced = file_size(&full_path).await;
+            staged
+                .commit(file, std::path::Path::new(&full_path))
+                .await
+                .map_err(|e| write_error(&header.path, e))?;
+            scope.volume().record_usage(size, replaced);
+            Ok::<_, tonic::Status>(size)
+        }
+        .await;
+        let size = match result {
+            Ok(size) => size,
+            Err(e) => {
+                tracing::warn!(path = %full_path, error = %e.message(), "delta upload failed");
+                return Err(e);
+            }
+        };
+        tracing::info!(path = %full_path, bytes = size, "delta upload complete");
 
-    let addr = config.server_bind_address.parse()?;
-    let service = GRPCFileStore::new(config.upload_directory).unwrap();
+        Ok(tonic::Response::new(UploadResponse {
+            file_id: upload_id,
+            filename,
+            size,
+            upload_time: Some(Timestamp::from(SystemTime::now())),
+        }))
+    }
+
+    type DeltaDownloadStream = ReceiverStream<Result<DeltaChunk, tonic::Status>>;
+
+    async fn delta_download(
+        &self,
+        request: tonic::Request<DeltaDownloadRequest>,
+    ) -> Result<tonic::Response<Self::DeltaDownloadStream>, tonic::Status> {
+        let client = identity::client_name(request.extensions());
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        check_block_size(req.block_size)?;
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+
+        let permit = self.admission.admit(&client).await?;
+        let file = std::fs::File::open(&full_path).map_err(|e| fs_error("open", &path, e))?;
+        let metadata = file.metadata().map_err(|e| fs_error("read", &path, e))?;
+        if metadata.is_dir() {
+            return Err(FileError::IsADirectory { path: path.to_string() }.into());
+        }
+        let file_size = metadata.len();
+
+        let (tx, rx) = tokio::sync::mpsc::channel(32);
+        let policies = self.policies();
+
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let _permit = permit;
+            let header = DeltaChunk {
+                header: Some(DeltaHeader {
+                    path: path.to_string(),
+                    block_size: req.block_size,
+                    file_size,
+                    volume: String::new(),
+                }),
+                ..Default::default()
+            };
+            if tx.blocking_send(Ok(header)).is_err() {
+                return;
+            }
+
+            let result = delta::compute_delta(file, req.block_size, &req.blocks, |ops| {
+                std::thread::sleep(policies.limits.reserve(&client, Direction::Download, literal_bytes(&ops)));
+                tx.blocking_send(Ok(DeltaChunk {
+                    ops,
+                    ..Default::default()
+                }))
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Client went away"))
+            });
+
+            let last = match result {
+                Ok(trailer) => {
+                    tracing::info!(path = %full_path, bytes = trailer.file_size, "delta download complete");
+                    Ok(DeltaChunk {
+                        trailer: Some(trailer),
+                        ..Default::default()
+                    })
+                }
+                Err(e) => {
+                    tracing::warn!(path = %full_path, error = %e, "delta download failed");
+                    Err(FileError::io("compute a delta for", &path, e).into())
+                }
+            };
+            let _ = tx.blocking_send(last);
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_status(
+        &self,
+        request: tonic::Request<StatusRequest>,
+    ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let mut status = self.admission.status();
+        // Who is connected and how busy they are is for admins only
+        if !self.volumes.is_admin(caller.as_deref()) {
+            status.clients.clear();
+        }
+        Ok(tonic::Response::new(StatusResponse {
+            uptime_secs: self.started.elapsed().as_secs(),
+            ..status
+        }))
+    }
+
+    async fn list_volumes(
+        &self,
+        request: tonic::Request<ListVolumesRequest>,
+    ) -> Result<tonic::Response<ListVolumesResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        Ok(tonic::Response::new(ListVolumesResponse {
+            volumes: self.volumes.infos(),
+        }))
+    }
+
+    async fn stat(
+        &self,
+        request: tonic::Request<StatRequest>,
+    ) -> Result<tonic::Response<StatResponse>, tonic::Status> {
+        self.check_rate(&request)?;
+        let caller = identity::peer_identity(request.extensions());
+        let req = request.into_inner();
+        let scope = self.volumes.scope(&req.volume, caller.as_deref())?;
+        let path = scope.parse_path(&req.path)?;
+        let full_path = scope.resolve_path(&path)?;
+        let metadata = tokio::fs::metadata(&full_path)
+            .await
+            .map_err(|e| fs_error("read", &path, e))?;
+
+        let filename = path.file_name().unwrap_or_default().to_string();
+        Ok(tonic::Response::new(StatResponse {
+            info: Some(self.file_info(&scope, filename, &path, &metadata)),
+        }))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (config_args, rest) = ConfigArgs::parse(std::env::args().skip(1).collect())?;
+    let loaded = Config::load_with(&config_args)?;
+    match rest.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => {}
+        ["config", "check"] => {
+            print!("{}", loaded);
+            return Ok(());
+        }
+        _ => {
+            return Err("Usage: server [--config <file>] [--profile <name>] [--<setting> <value>]... [config check]".into());
+        }
+    }
+    let config_file = loaded.file;
+    let config = loaded.config;
+    let _log_guard = grpc_files::logging::init(&config, "server", false)?;
+    if config.server_bind_address.is_empty() && config.server_socket_path.is_none() {
+        return Err("Nothing to listen on: set server_bind_address or server_socket_path".into());
+    }
+    #[cfg(not(unix))]
+    if config.server_socket_path.is_some() {
+        return Err("server_socket_path is not supported on this platform".into());
+    }
+
+    let listener = match config.server_bind_address.as_str() {
+        "" => None,
+        address => {
+            let addr: std::net::SocketAddr = address.parse()?;
+            Some(tokio::net::TcpListener::bind(addr).await?)
+        }
+    };
+    let webdav_listener = match &config.webdav_bind_address {
+        Some(address) => {
+            let addr: std::net::SocketAddr = address.parse()?;
+            Some(tokio::net::TcpListener::bind(addr).await?)
+        }
+        None => None,
+    };
+    let sftp_listener = match &config.sftp_bind_address {
+        Some(address) => {
+            let addr: std::net::SocketAddr = address.parse()?;
+            Some(tokio::net::TcpListener::bind(addr).await?)
+        }
+        None => None,
+    };
+    let tls = if listener.is_none() && webdav_listener.is_none() {
+        None
+    } else if config.plaintext {
+        if listener.is_some() {
+            check_plaintext(&config.server_bind_address)?;
+        }
+        if let Some(address) = &config.webdav_bind_address {
+            check_plaintext(address)?;
+        }
+        tracing::warn!("serving without TLS, which is only meant for local development");
+        None
+    } else {
+        Some(ReloadableTls::load(&config)?)
+    };
+    let service = GRPCFileStore::new(&config)?;
+    let metrics = Metrics::new(service.volumes.clone())?;
+    let tokens = AccessTokens::load(&config)?;
+    let ssh_keys = AuthorizedKeys::load(&config)?;
+    let cors = gateway::cors(&config.web_allowed_origins)?;
+    let gateway = gateway::router(metrics.layer().layer(FileServiceServer::new(service.clone())));
+    let webdav = webdav::router(metrics.layer().layer(FileServiceServer::new(service.clone())));
     let reflection = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(grpc_files::fileservice::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
         .build_v1()?;
 
-    Server::builder()
-        .tls_config(tls)?
-        .initial_connection_window_size(1024 * 1024)
-        .initial_stream_window_size(1024 * 1024)
-        .add_service(FileServiceServer::new(service))
-        .add_service(reflection)
-        .serve(addr)
-        .await?;
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_serving::<FileServiceServer<GRPCFileStore>>()
+        .await;
+
+    if let Some(metrics_addr) = &config.metrics_bind_address {
+        let metrics_addr = metrics_addr.parse()?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(metrics_addr).await {
+                tracing::error!(error = %e, "metrics endpoint failed");
+            }
+        });
+    }
+
+    let sftp = match sftp_listener {
+        Some(listener) => {
+            let host_key = HostKey::load(&config)?;
+            tracing::info!(fingerprint = %host_key.fingerprint(), "sftp host key loaded");
+            let sftp = SftpServer::new(
+                host_key,
+                ssh_keys.clone(),
+                metrics.layer().layer(FileServiceServer::new(service.clone())),
+            );
+            Some((sftp, listener))
+        }
+        None => None,
+    };
+
+    for volume in service.volumes.iter() {
+        volume
+            .staging()
+            .spawn_sweeper(Duration::from_secs(config.staging_idle_timeout_secs));
+    }
+
+    let volume_names: Vec<&str> = service.volumes.iter().map(|v| v.name()).collect();
+    tracing::info!(
+        addr = %config.server_bind_address,
+        socket = %config.server_socket_path.as_deref().unwrap_or_default(),
+        webdav = %config.webdav_bind_address.as_deref().unwrap_or_default(),
+        sftp = %config.sftp_bind_address.as_deref().unwrap_or_default(),
+        volumes = %volume_names.join(","),
+        "server listening"
+    );
+
+    let mut watched = tls.as_ref().map(|tls| tls.files()).unwrap_or_default();
+    watched.extend(config_file);
+    let mut triggers = Triggers::watch(&watched)?;
+    let reloader = Reloader {
+        args: config_args,
+        startup_settings: serde_json::to_value(&config)?,
+        service: service.clone(),
+        tls: tls.clone(),
+        tokens: tokens.clone(),
+        ssh_keys,
+    };
+    tokio::spawn(async move {
+        while let Some(triggers) = triggers.next().await {
+            reloader.reload(&triggers);
+        }
+    });
+
+    // One server per listener, all serving the same services. HTTP/1.1 is
+    // accepted for gRPC-Web and the REST gateway.
+    let router = || {
+        let grpc_web = GrpcWebLayer::new();
+        Server::builder()
+            .accept_http1(true)
+            .initial_connection_window_size(1024 * 1024)
+            .initial_stream_window_size(1024 * 1024)
+            .trace_fn(grpc_files::logging::rpc_span)
+            .layer(cors.clone())
+            .layer(tokens.layer(tls.clone(), config.plaintext))
+            .add_routes(Routes::from(gateway.clone()))
+            .add_service(grpc_web.layer(health_service.clone()))
+            .add_service(grpc_web.layer(metrics.layer().layer(FileServiceServer::new(service.clone()))))
+            .add_service(reflection.clone())
+    };
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let shutdown = || {
+        let mut shutdown_rx = shutdown_rx.clone();
+        async move {
+            let _ = shutdown_rx.wait_for(|stop| *stop).await;
+        }
+    };
+    let mut servers = tokio::task::JoinSet::new();
+    match (listener, &tls) {
+        (Some(listener), Some(tls)) => {
+            servers.spawn(router().serve_with_incoming_shutdown(tls.incoming(listener), shutdown()));
+        }
+        (Some(listener), None) => {
+            let incoming = TcpIncoming::from(listener).with_nodelay(Some(true));
+            servers.spawn(router().serve_with_incoming_shutdown(incoming, shutdown()));
+        }
+        (None, _) => {}
+    }
+    // WebDAV gets a listener of its own, as its methods and paths would
+    // clash with the gateway's
+    if let Some(listener) = webdav_listener {
+        let router = Server::builder()
+            .accept_http1(true)
+            .trace_fn(grpc_files::logging::rpc_span)
+            .layer(tokens.layer(tls.clone(), config.plaintext))
+            .add_routes(Routes::from(webdav.clone()));
+        match &tls {
+            Some(tls) => {
+                servers.spawn(router.serve_with_incoming_shutdown(tls.incoming(listener), shutdown()));
+            }
+            None => {
+                let incoming = TcpIncoming::from(listener).with_nodelay(Some(true));
+                servers.spawn(router.serve_with_incoming_shutdown(incoming, shutdown()));
+            }
+        }
+    }
+    if let Some((sftp, listener)) = sftp {
+        let shutdown_rx = shutdown_rx.clone();
+        servers.spawn(async move {
+            sftp.serve(listener, shutdown_rx).await;
+            Ok(())
+        });
+    }
+    #[cfg(unix)]
+    let _socket_file = match &config.server_socket_path {
+        Some(path) => {
+            let (listener, file) = unix_socket::bind(std::path::Path::new(path))?;
+            let incoming = tokio_stream::wrappers::UnixListenerStream::new(listener);
+            servers.spawn(router().serve_with_incoming_shutdown(incoming, shutdown()));
+            Some(file)
+        }
+        None => None,
+    };
+
+    tokio::select! {
+        Some(result) = servers.join_next() => return Ok(result??),
+        _ = shutdown_signal() => {}
+    }
+
+    // Tell load balancers and health checks we are going away before refusing new RPCs
+    health_reporter
+        .set_not_serving::<FileServiceServer<GRPCFileStore>>()
+        .await;
+    health_reporter
+        .set_service_status("", ServingStatus::NotServing)
+        .await;
+    let _ = shutdown_tx.send(true);
+
+    let grace_period = Duration::from_secs(config.shutdown_grace_period_secs);
+    tracing::info!(
+        grace_period_secs = grace_period.as_secs(),
+        "shutting down, waiting for running transfers"
+    );
+    let drained = tokio::time::timeout(grace_period, async {
+        while let Some(result) = servers.join_next().await {
+            result??;
+        }
+        Ok::<_, Box<dyn std::error::Error>>(())
+    });
+    match drained.await {
+        Ok(result) => {
+            result?;
+            tracing::info!("shutdown complete");
+        }
+        Err(_) => {
+            servers.abort_all();
+            let removed: usize = service.volumes.iter().map(|v| v.staging().abort_all()).sum();
+            tracing::warn!(
+                partial_uploads_removed = removed,
+                "grace period expired, aborted remaining transfers"
+            );
+        }
+    }
 
     Ok(())
 }
+
+/// Settings only read at startup. A reload that changes them reports that a
+/// restart is needed.
+const RESTART_SETTINGS: &[&str] = &[
+    "server_bind_address",
+    "server_socket_path",
+    "auth_directory",
+    "tls_server_cert_file",
+    "tls_server_key_file",
+    "tls_ca_file",
+    "plaintext",
+    "web_allowed_origins",
+    "webdav_bind_address",
+    "sftp_bind_address",
+    "sftp_host_key_file",
+    "metrics_bind_address",
+    "log_level",
+    "log_format",
+    "log_directory",
+    "staging_idle_timeout_secs",
+    "shutdown_grace_period_secs",
+];
+
+/// Applies changes to the configuration and certificates while the server runs.
+struct Reloader {
+    args: ConfigArgs,
+    /// The settings the server started with, to tell when a reload changes
+    /// one that needs a restart.
+    startup_settings: serde_json::Value,
+    service: GRPCFileStore,
+    /// `None` when serving plaintext.
+    tls: Option<Arc<ReloadableTls>>,
+    tokens: Arc<AccessTokens>,
+    ssh_keys: Arc<AuthorizedKeys>,
+}
+
+impl Reloader {
+    /// Reload the certificates and the configuration. Whatever fails to load
+    /// is reported and the settings in use are kept.
+    fn reload(&self, triggers: &[Trigger]) {
+        let reasons: Vec<String> = triggers.iter().map(Trigger::to_string).collect();
+        tracing::info!(reasons = %reasons.join(", "), "reloading");
+
+        let config = Config::load_with(&self.args).map(|loaded| loaded.config);
+        if let Err(e) = &config {
+            tracing::error!(error = %e, "failed to reload configuration, keeping the current one");
+        }
+        // Rotated certificates are picked up even if the config file is broken
+        if let Some(tls) = &self.tls
+            && let Err(e) = tls.reload(config.as_ref().ok())
+        {
+            tracing::error!(error = %e, "failed to reload TLS settings, keeping the current ones");
+        }
+        let Ok(config) = config else {
+            return;
+        };
+        if let Err(e) = self.tokens.reload(&config) {
+            tracing::error!(error = %e, "failed to reload access tokens, keeping the current ones");
+        }
+        if let Err(e) = self.ssh_keys.reload(&config) {
+            tracing::error!(error = %e, "failed to reload SFTP keys, keeping the current ones");
+        }
+        let mut ignored = self.service.reconfigure(&config);
+        let settings = serde_json::to_value(&config).unwrap_or_default();
+        for setting in RESTART_SETTINGS {
+            if settings.get(setting) != self.startup_settings.get(setting) {
+                ignored.push(format!("{} changed", setting));
+            }
+        }
+        for change in &ignored {
+            tracing::warn!(change = %change, "change needs a restart to take effect");
+        }
+        tracing::info!("configuration reloaded");
+    }
+}
+
+/// Resolve when the process receives SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
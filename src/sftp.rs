@@ -0,0 +1,564 @@
+use bytes::Bytes;
+use http::Extensions;
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, FileMode, Handle, Name, OpenFlags, Status, StatusCode,
+};
+use russh_sftp::server::Handler;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Code;
+use tracing::Instrument;
+
+use crate::fileservice::file_service_client::FileServiceClient;
+use crate::fileservice::{
+    CreateDirectoryRequest, DeleteDirectoryRequest, DeleteRequest, DownloadChunk, DownloadRequest,
+    FileInfo, ListRequest, ListVolumesRequest, MoveRequest, StatRequest, UploadChunk,
+    UploadResponse,
+};
+use crate::gateway::{LocalFileService, forward};
+
+/// Entries sent per SSH_FXP_READDIR reply, to stay well under the packet
+/// size clients accept.
+const ENTRIES_PER_READDIR: usize = 100;
+/// Most bytes returned by one read, whatever the client asks for.
+const MAX_READ_BYTES: u32 = 64 * 1024;
+
+/// An SFTP session over the file service, one per SSH channel. Paths are laid
+/// out as over WebDAV: the root lists the volumes and each volume is a
+/// directory below it. Every operation is a call to `service` made as the
+/// signed-in client, so the same homes, permissions and quotas apply.
+pub struct Session {
+    service: LocalFileService,
+    caller: Extensions,
+    handles: HashMap<String, Open>,
+    next_handle: u64,
+    /// Files open across the connection's sessions, which a shutdown waits
+    /// for before hanging up.
+    open_files: Arc<watch::Sender<usize>>,
+}
+
+enum Open {
+    Directory(VecDeque<File>),
+    Reader(Box<Reader>),
+    Writer(Writer),
+}
+
+impl Session {
+    /// `caller` holds the client's [`crate::identity::SshIdentity`].
+    pub fn new(service: LocalFileService, caller: Extensions, open_files: Arc<watch::Sender<usize>>) -> Self {
+        Session {
+            service,
+            caller,
+            handles: HashMap::new(),
+            next_handle: 0,
+            open_files,
+        }
+    }
+
+    fn client(&self) -> FileServiceClient<LocalFileService> {
+        FileServiceClient::new(self.service.clone())
+    }
+
+    fn insert(&mut self, id: u32, open: Open) -> Handle {
+        self.next_handle += 1;
+        let handle = self.next_handle.to_string();
+        if !matches!(open, Open::Directory(_)) {
+            self.open_files.send_modify(|count| *count += 1);
+        }
+        self.handles.insert(handle.clone(), open);
+        Handle { id, handle }
+    }
+
+    fn closed_files(&self, closed: usize) {
+        self.open_files.send_modify(|count| *count = count.saturating_sub(closed));
+    }
+
+    async fn stat_path(&self, path: &str) -> Result<FileAttributes, StatusCode> {
+        match locate(path) {
+            None => Ok(directory_attributes()),
+            Some((volume, path)) => Ok(attributes(&self.info(&volume, &path).await?)),
+        }
+    }
+
+    async fn info(&self, volume: &str, path: &str) -> Result<FileInfo, StatusCode> {
+        let request = StatRequest {
+            path: path.to_string(),
+            volume: volume.to_string(),
+        };
+        let response = self.client().stat(forward(&self.caller, request)).await.map_err(sftp_error)?;
+        response.into_inner().info.ok_or(StatusCode::Failure)
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<File>, StatusCode> {
+        let Some((volume, path)) = locate(path) else {
+            let response = self
+                .client()
+                .list_volumes(forward(&self.caller, ListVolumesRequest {}))
+                .await
+                .map_err(sftp_error)?;
+            let volumes = response.into_inner().volumes;
+            return Ok(volumes
+                .into_iter()
+                .map(|volume| File::new(volume.name, directory_attributes()))
+                .collect());
+        };
+        let request = ListRequest {
+            path,
+            show_hidden: true,
+            volume,
+            ..Default::default()
+        };
+        let response = self.client().list_files(forward(&self.caller, request)).await.map_err(sftp_error)?;
+        Ok(response
+            .into_inner()
+            .files
+            .iter()
+            .map(|info| File::new(info.filename.clone(), attributes(info)))
+            .collect())
+    }
+
+    async fn start_upload(&self, volume: &str, path: &str) -> Result<Writer, StatusCode> {
+        let (directory, name) = split_name(path);
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let first = UploadChunk {
+            upload_id: upload_id.clone(),
+            filename: name.to_string(),
+            chunk_index: 0,
+            data: Vec::new(),
+            target_directory: directory.to_string(),
+            file_size: 0,
+            volume: volume.to_string(),
+        };
+        tx.send(first).await.map_err(|_| StatusCode::Failure)?;
+
+        let mut client = self.client();
+        let request = forward(&self.caller, ReceiverStream::new(rx));
+        let upload = tokio::spawn(
+            async move { client.upload(request).await.map(|response| response.into_inner()) }
+                .in_current_span(),
+        );
+        Ok(Writer {
+            upload_id,
+            chunk_index: 0,
+            written: 0,
+            tx: Some(tx),
+            upload: Some(upload),
+        })
+    }
+}
+
+impl Handler for Session {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let (volume, path) = locate(&filename).ok_or(StatusCode::PermissionDenied)?;
+        let existing = match self.info(&volume, &path).await {
+            Ok(info) => Some(info),
+            Err(StatusCode::NoSuchFile) => None,
+            Err(e) => return Err(e),
+        };
+        if existing.as_ref().is_some_and(|info| info.is_directory) {
+            return Err(StatusCode::Failure);
+        }
+
+        if pflags.contains(OpenFlags::WRITE) {
+            // Uploads replace a file as a whole, so writes have to start
+            // from an empty file and go front to back
+            if pflags.contains(OpenFlags::APPEND) || pflags.contains(OpenFlags::READ) {
+                return Err(StatusCode::OpUnsupported);
+            }
+            match existing {
+                Some(_) if pflags.contains(OpenFlags::EXCLUDE) => return Err(StatusCode::Failure),
+                Some(_) if !pflags.contains(OpenFlags::TRUNCATE) => return Err(StatusCode::OpUnsupported),
+                None if !pflags.contains(OpenFlags::CREATE) => return Err(StatusCode::NoSuchFile),
+                _ => {}
+            }
+            let writer = self.start_upload(&volume, &path).await?;
+            return Ok(self.insert(id, Open::Writer(writer)));
+        }
+
+        let info = existing.ok_or(StatusCode::NoSuchFile)?;
+        let reader = Reader {
+            client: self.client(),
+            caller: self.caller.clone(),
+            volume,
+            path,
+            size: info.size,
+            position: 0,
+            stream: None,
+            buffer: Bytes::new(),
+        };
+        Ok(self.insert(id, Open::Reader(Box::new(reader))))
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        match self.handles.remove(&handle) {
+            Some(Open::Writer(mut writer)) => {
+                let finished = writer.finish().await;
+                self.closed_files(1);
+                finished?
+            }
+            Some(Open::Reader(_)) => self.closed_files(1),
+            Some(Open::Directory(_)) => {}
+            None => return Err(StatusCode::Failure),
+        }
+        Ok(ok(id))
+    }
+
+    async fn read(&mut self, id: u32, handle: String, offset: u64, len: u32) -> Result<Data, Self::Error> {
+        let Some(Open::Reader(reader)) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+        let data = reader.read(offset, len.min(MAX_READ_BYTES) as usize).await?;
+        if data.is_empty() {
+            return Err(StatusCode::Eof);
+        }
+        Ok(Data { id, data })
+    }
+
+    async fn write(&mut self, id: u32, handle: String, offset: u64, data: Vec<u8>) -> Result<Status, Self::Error> {
+        let Some(Open::Writer(writer)) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+        writer.write(offset, data).await?;
+        Ok(ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        // Symbolic links are never shown to clients
+        self.stat(id, path).await
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let attrs = match self.handles.get(&handle) {
+            Some(Open::Directory(_)) => directory_attributes(),
+            Some(Open::Reader(reader)) => {
+                let info = self.info(&reader.volume, &reader.path).await?;
+                attributes(&info)
+            }
+            Some(Open::Writer(writer)) => FileAttributes {
+                size: Some(writer.written),
+                ..file_attributes()
+            },
+            None => return Err(StatusCode::Failure),
+        };
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn setstat(&mut self, id: u32, _path: String, _attrs: FileAttributes) -> Result<Status, Self::Error> {
+        // The store keeps neither owners, permissions nor times set by
+        // clients; accept the request so uploads that preserve them succeed
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(&mut self, id: u32, _handle: String, _attrs: FileAttributes) -> Result<Status, Self::Error> {
+        Ok(ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let entries = self.list(&path).await?;
+        Ok(self.insert(id, Open::Directory(entries.into())))
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        let Some(Open::Directory(entries)) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+        if entries.is_empty() {
+            return Err(StatusCode::Eof);
+        }
+        let count = entries.len().min(ENTRIES_PER_READDIR);
+        Ok(Name {
+            id,
+            files: entries.drain(..count).collect(),
+        })
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        let (volume, path) = locate(&filename).ok_or(StatusCode::PermissionDenied)?;
+        let request = DeleteRequest {
+            file_name: path,
+            volume,
+        };
+        self.client().delete_file(forward(&self.caller, request)).await.map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn mkdir(&mut self, id: u32, path: String, _attrs: FileAttributes) -> Result<Status, Self::Error> {
+        let (volume, path) = locate(&path).ok_or(StatusCode::PermissionDenied)?;
+        if path.is_empty() {
+            // Volumes come from the server's configuration
+            return Err(StatusCode::PermissionDenied);
+        }
+        let (parent, name) = split_name(&path);
+        let request = CreateDirectoryRequest {
+            path: parent.to_string(),
+            name: name.to_string(),
+            volume,
+        };
+        self.client().create_directory(forward(&self.caller, request)).await.map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        let (volume, path) = locate(&path).ok_or(StatusCode::PermissionDenied)?;
+        if path.is_empty() {
+            return Err(StatusCode::PermissionDenied);
+        }
+        let request = DeleteDirectoryRequest {
+            path,
+            recursive: false,
+            volume,
+        };
+        self.client().delete_directory(forward(&self.caller, request)).await.map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        Ok(Name {
+            id,
+            files: vec![File::dummy(normalize(&path))],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let attrs = self.stat_path(&path).await?;
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn rename(&mut self, id: u32, oldpath: String, newpath: String) -> Result<Status, Self::Error> {
+        let (volume, source) = locate(&oldpath).ok_or(StatusCode::PermissionDenied)?;
+        let (to_volume, destination) = locate(&newpath).ok_or(StatusCode::PermissionDenied)?;
+        if volume != to_volume || source.is_empty() || destination.is_empty() {
+            return Err(StatusCode::PermissionDenied);
+        }
+        let request = MoveRequest {
+            source,
+            destination,
+            volume,
+        };
+        self.client().move_file(forward(&self.caller, request)).await.map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+}
+
+/// A file being read, fetched with the Download RPC from wherever the client
+/// last read. Clients read front to back, so one download usually serves the
+/// whole file.
+struct Reader {
+    client: FileServiceClient<LocalFileService>,
+    caller: Extensions,
+    volume: String,
+    path: String,
+    size: u64,
+    position: u64,
+    stream: Option<tonic::Streaming<DownloadChunk>>,
+    buffer: Bytes,
+}
+
+impl Reader {
+    async fn read(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, StatusCode> {
+        if offset != self.position {
+            self.stream = None;
+            self.buffer = Bytes::new();
+            self.position = offset;
+        }
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            if self.buffer.is_empty() {
+                if self.stream.is_none() {
+                    if self.position >= self.size {
+                        break;
+                    }
+                    let request = DownloadRequest {
+                        file_name: self.path.clone(),
+                        volume: self.volume.clone(),
+                        offset: self.position,
+                        length: 0,
+                    };
+                    let response = self
+                        .client
+                        .download(forward(&self.caller, request))
+                        .await
+                        .map_err(sftp_error)?;
+                    self.stream = Some(response.into_inner());
+                }
+                let Some(stream) = self.stream.as_mut() else {
+                    break;
+                };
+                match stream.message().await.map_err(sftp_error)? {
+                    Some(chunk) => self.buffer = Bytes::from(chunk.data),
+                    None => break,
+                }
+            }
+            let chunk = self.buffer.split_to((len - data.len()).min(self.buffer.len()));
+            self.position += chunk.len() as u64;
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+}
+
+/// A file being written, streamed into an Upload RPC. The upload completes
+/// when the handle is closed; a session that ends before that cancels it and
+/// leaves the old file in place.
+struct Writer {
+    upload_id: String,
+    chunk_index: u64,
+    written: u64,
+    tx: Option<tokio::sync::mpsc::Sender<UploadChunk>>,
+    upload: Option<JoinHandle<Result<UploadResponse, tonic::Status>>>,
+}
+
+impl Writer {
+    async fn write(&mut self, offset: u64, data: Vec<u8>) -> Result<(), StatusCode> {
+        if offset != self.written {
+            return Err(StatusCode::OpUnsupported);
+        }
+        let Some(tx) = &self.tx else {
+            return Err(StatusCode::Failure);
+        };
+        self.chunk_index += 1;
+        self.written += data.len() as u64;
+        let chunk = UploadChunk {
+            upload_id: self.upload_id.clone(),
+            chunk_index: self.chunk_index,
+            data,
+            ..Default::default()
+        };
+        if tx.send(chunk).await.is_err() {
+            // The upload ended early; report why
+            self.finish().await?;
+            return Err(StatusCode::Failure);
+        }
+        Ok(())
+    }
+
+    /// End the upload and wait for the server to take the file.
+    async fn finish(&mut self) -> Result<(), StatusCode> {
+        self.tx = None;
+        let Some(upload) = self.upload.take() else {
+            return Ok(());
+        };
+        upload.await.map_err(|_| StatusCode::Failure)?.map_err(sftp_error)?;
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let open = self.handles.values().filter(|open| !matches!(open, Open::Directory(_))).count();
+        self.closed_files(open);
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            upload.abort();
+        }
+    }
+}
+
+/// An absolute path with `.` and `..` resolved. Relative paths start at the
+/// root, which is where sessions begin.
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+/// Split a path into its volume and the path inside the volume. The root,
+/// where the volumes are listed, has neither.
+fn locate(path: &str) -> Option<(String, String)> {
+    let path = normalize(path);
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return None;
+    }
+    let (volume, rest) = path.split_once('/').unwrap_or((path, ""));
+    Some((volume.to_string(), rest.to_string()))
+}
+
+fn split_name(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn file_attributes() -> FileAttributes {
+    let mut attrs = FileAttributes::empty();
+    attrs.uid = Some(0);
+    attrs.gid = Some(0);
+    attrs.permissions = Some(0o644 | FileMode::REG.bits());
+    attrs
+}
+
+fn directory_attributes() -> FileAttributes {
+    FileAttributes {
+        size: Some(0),
+        permissions: Some(0o755 | FileMode::DIR.bits()),
+        atime: Some(0),
+        mtime: Some(0),
+        ..file_attributes()
+    }
+}
+
+fn attributes(info: &FileInfo) -> FileAttributes {
+    let modified = info
+        .upload_time
+        .and_then(|time| u32::try_from(time.seconds).ok())
+        .unwrap_or(0);
+    let base = if info.is_directory {
+        directory_attributes()
+    } else {
+        file_attributes()
+    };
+    FileAttributes {
+        size: Some(info.size),
+        atime: Some(modified),
+        mtime: Some(modified),
+        ..base
+    }
+}
+
+/// SFTP clients only get a status code, so the message is logged here before
+/// it is lost.
+fn sftp_error(status: tonic::Status) -> StatusCode {
+    tracing::debug!(code = ?status.code(), error = %status.message(), "sftp operation failed");
+    match status.code() {
+        Code::NotFound => StatusCode::NoSuchFile,
+        Code::PermissionDenied | Code::Unauthenticated => StatusCode::PermissionDenied,
+        Code::Unimplemented => StatusCode::OpUnsupported,
+        _ => StatusCode::Failure,
+    }
+}
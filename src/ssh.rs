@@ -0,0 +1,321 @@
+use russh::keys::{HashAlg, PrivateKey, PublicKey};
+use russh::keys::ssh_key::public::KeyData;
+use russh::server::{Auth, ChannelOpenHandle, Msg, Session};
+use russh::{Channel, ChannelId, ChannelOpenFailure, Disconnect, MethodKind, MethodSet, SshId};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tower::Service;
+use tower::util::BoxCloneSyncService;
+use tracing::Instrument;
+
+use crate::config::Config;
+use crate::gateway::LocalFileService;
+use crate::identity::{self, SshIdentity};
+use crate::sftp;
+
+const SERVER_VERSION: &str = concat!("SSH-2.0-grpc_files_", env!("CARGO_PKG_VERSION"));
+
+/// How long a client has to finish the key exchange and sign in.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(120);
+/// Rejected keys after which a client is disconnected.
+const MAX_AUTH_FAILURES: usize = 20;
+const MAX_CHANNELS: usize = 8;
+/// How often a quiet client is checked on, and how many checks it may miss
+/// before it is disconnected.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+const KEEPALIVE_MAX: usize = 3;
+
+/// The server's SSH host key, in the OpenSSH format `ssh-keygen` writes and
+/// without a passphrase.
+pub struct HostKey {
+    key: PrivateKey,
+}
+
+impl HostKey {
+    pub fn load(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = config.tls_file(&config.sftp_host_key_file, "ssh_host_ed25519_key")?;
+        let pem = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read SSH host key {}: {}", path.display(), e))?;
+        let key = PrivateKey::from_openssh(&pem)
+            .map_err(|e| format!("Invalid SSH host key {}: {}", path.display(), e))?;
+        if key.is_encrypted() {
+            return Err(format!("Invalid SSH host key {}: the key must not have a passphrase", path.display()).into());
+        }
+        Ok(HostKey { key })
+    }
+
+    /// The fingerprint clients show when they first connect, such as
+    /// `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`.
+    pub fn fingerprint(&self) -> String {
+        self.key.fingerprint(HashAlg::Sha256).to_string()
+    }
+}
+
+/// The SSH keys SFTP clients sign in with.
+pub struct AuthorizedKeys {
+    names: RwLock<HashMap<KeyData, String>>,
+}
+
+impl AuthorizedKeys {
+    pub fn load(config: &Config) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        Ok(Arc::new(AuthorizedKeys {
+            names: RwLock::new(parse_keys(config)?),
+        }))
+    }
+
+    /// Swap in the keys from a reloaded configuration. Clients already signed
+    /// in stay connected.
+    pub fn reload(&self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let names = parse_keys(config)?;
+        *self.names.write().unwrap() = names;
+        Ok(())
+    }
+
+    /// Name of the client a key belongs to.
+    fn name(&self, key: &PublicKey) -> Option<String> {
+        self.names.read().unwrap().get(key.key_data()).cloned()
+    }
+}
+
+fn parse_keys(config: &Config) -> Result<HashMap<KeyData, String>, Box<dyn std::error::Error>> {
+    let mut names = HashMap::new();
+    for key in &config.sftp_authorized_keys {
+        if identity::is_uid_name(&key.name) {
+            return Err(format!("SSH key name '{}' is reserved for local processes", key.name).into());
+        }
+        let public = PublicKey::from_openssh(key.key.trim())
+            .map_err(|e| format!("Invalid SSH key for '{}': {}", key.name, e))?;
+        if names.insert(public.key_data().clone(), key.name.clone()).is_some() {
+            return Err(format!("SSH key for '{}' is the same as another client's", key.name).into());
+        }
+    }
+    Ok(names)
+}
+
+/// An SSH server that offers nothing but the SFTP subsystem, backed by the
+/// file service. Clients sign in with a key from `sftp_authorized_keys` and
+/// act as the client the key is authorized for.
+pub struct SftpServer {
+    config: Arc<russh::server::Config>,
+    keys: Arc<AuthorizedKeys>,
+    service: LocalFileService,
+}
+
+impl SftpServer {
+    pub fn new<S>(host_key: HostKey, keys: Arc<AuthorizedKeys>, service: S) -> Arc<Self>
+    where
+        S: Service<
+                http::Request<tonic::body::Body>,
+                Response = http::Response<tonic::body::Body>,
+                Error = Infallible,
+            > + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let config = russh::server::Config {
+            server_id: SshId::Standard(Cow::Borrowed(SERVER_VERSION)),
+            methods: MethodSet::from(&[MethodKind::PublicKey][..]),
+            // Clients ask which methods there are before they offer a key
+            auth_rejection_time_initial: Some(Duration::ZERO),
+            keys: vec![host_key.key],
+            max_auth_attempts: MAX_AUTH_FAILURES,
+            inactivity_timeout: None,
+            keepalive_interval: Some(KEEPALIVE_INTERVAL),
+            keepalive_max: KEEPALIVE_MAX,
+            nodelay: true,
+            ..Default::default()
+        };
+        Arc::new(SftpServer {
+            config: Arc::new(config),
+            keys,
+            service: BoxCloneSyncService::new(service),
+        })
+    }
+
+    /// Accept clients until `shutdown` turns true, then wait for the ones
+    /// still connected. Each is hung up on once its open files are closed.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, mut shutdown: watch::Receiver<bool>) {
+        let mut connections = JoinSet::new();
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                _ = stopped(&mut shutdown) => break,
+                Some(_) = connections.join_next() => continue,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Usually out of file descriptors; wait for some to free up
+                        tracing::warn!(error = %e, "failed to accept SFTP connection");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+            };
+            let span = tracing::info_span!("ssh", remote = %remote_addr, client = tracing::field::Empty);
+            let connection = self.clone().connection(stream, remote_addr, shutdown.clone(), span.clone());
+            connections.spawn(
+                async move {
+                    match connection.await {
+                        Ok(()) => tracing::debug!("ssh connection closed"),
+                        Err(e) => tracing::info!(error = %e, "ssh connection closed"),
+                    }
+                }
+                .instrument(span),
+            );
+        }
+        drop(listener);
+        while connections.join_next().await.is_some() {}
+    }
+
+    async fn connection(
+        self: Arc<Self>,
+        stream: TcpStream,
+        remote_addr: SocketAddr,
+        mut shutdown: watch::Receiver<bool>,
+        span: tracing::Span,
+    ) -> Result<(), russh::Error> {
+        let deadline = Instant::now() + LOGIN_TIMEOUT;
+        let signed_in = Arc::new(Notify::new());
+        let open_files = Arc::new(watch::channel(0).0);
+        let client = Client {
+            server: self.clone(),
+            remote_addr,
+            span,
+            identity: None,
+            signed_in: signed_in.clone(),
+            channels: HashMap::new(),
+            open_files: open_files.clone(),
+        };
+        let start = russh::server::run_stream(self.config.clone(), stream, client);
+        let mut session = tokio::time::timeout_at(deadline, start)
+            .await
+            .map_err(|_| russh::Error::ConnectionTimeout)??;
+        let handle = session.handle();
+        let hang_up = |reason: &str| {
+            let handle = handle.clone();
+            let reason = reason.to_string();
+            async move {
+                let _ = handle.disconnect(Disconnect::ByApplication, reason, String::new()).await;
+            }
+        };
+
+        tokio::select! {
+            result = &mut session => return result,
+            _ = signed_in.notified() => {}
+            _ = tokio::time::sleep_until(deadline) => {
+                hang_up("sign-in timed out").await;
+                return session.await;
+            }
+        }
+        tokio::select! {
+            result = &mut session => return result,
+            _ = stopped(&mut shutdown) => {}
+        }
+        // Let running transfers finish before hanging up
+        let mut open = open_files.subscribe();
+        let closed = async move {
+            let _ = open.wait_for(|count| *count == 0).await;
+        };
+        tokio::select! {
+            result = &mut session => return result,
+            _ = closed => {}
+        }
+        hang_up("server shutting down").await;
+        session.await
+    }
+}
+
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+/// One client's connection: who it signed in as and the channels it opened.
+struct Client {
+    server: Arc<SftpServer>,
+    remote_addr: SocketAddr,
+    span: tracing::Span,
+    identity: Option<SshIdentity>,
+    signed_in: Arc<Notify>,
+    /// Open channels, holding the ones an SFTP session has not been started on yet.
+    channels: HashMap<ChannelId, Option<Channel<Msg>>>,
+    open_files: Arc<watch::Sender<usize>>,
+}
+
+impl russh::server::Handler for Client {
+    type Error = russh::Error;
+
+    async fn auth_publickey_offered(&mut self, user: &str, key: &PublicKey) -> Result<Auth, Self::Error> {
+        if self.server.keys.name(key).is_some() {
+            return Ok(Auth::Accept);
+        }
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        self.span.in_scope(|| tracing::info!(user = %user, key = %fingerprint, "ssh key not authorized"));
+        Ok(Auth::reject())
+    }
+
+    /// Called once the client has proven it holds the key.
+    async fn auth_publickey(&mut self, user: &str, key: &PublicKey) -> Result<Auth, Self::Error> {
+        // Looked up again as the keys may have been reloaded since the offer
+        let Some(name) = self.server.keys.name(key) else {
+            return Ok(Auth::reject());
+        };
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        self.span.record("client", name.as_str());
+        self.span.in_scope(|| tracing::info!(user = %user, key = %fingerprint, "sftp client signed in"));
+        self.identity = Some(SshIdentity {
+            name,
+            remote_addr: self.remote_addr,
+        });
+        self.signed_in.notify_one();
+        Ok(Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if self.channels.len() >= MAX_CHANNELS {
+            reply.reject(ChannelOpenFailure::AdministrativelyProhibited).await;
+            return Ok(());
+        }
+        self.channels.insert(channel.id(), Some(channel));
+        reply.accept().await;
+        Ok(())
+    }
+
+    async fn subsystem_request(&mut self, id: ChannelId, name: &str, session: &mut Session) -> Result<(), Self::Error> {
+        let channel = self.channels.get_mut(&id).filter(|_| name == "sftp").and_then(Option::take);
+        let (Some(channel), Some(identity)) = (channel, self.identity.clone()) else {
+            session.channel_failure(id)?;
+            return Ok(());
+        };
+        session.channel_success(id)?;
+        let mut caller = http::Extensions::new();
+        caller.insert(identity);
+        let handler = sftp::Session::new(self.server.service.clone(), caller, self.open_files.clone());
+        russh_sftp::server::run(channel.into_stream(), handler).await;
+        Ok(())
+    }
+
+    async fn channel_eof(&mut self, id: ChannelId, session: &mut Session) -> Result<(), Self::Error> {
+        // The client has nothing more to ask, so the session is over
+        session.close(id)?;
+        Ok(())
+    }
+
+    async fn channel_close(&mut self, id: ChannelId, _session: &mut Session) -> Result<(), Self::Error> {
+        self.channels.remove(&id);
+        Ok(())
+    }
+}
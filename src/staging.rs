@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+/// Hidden directory at the storage root where uploads are written until they complete.
+pub const STAGING_DIR: &str = ".staging";
+
+/// Area for in-flight uploads, kept on the same filesystem as the storage
+/// root so a finished upload can be moved into place with a single rename.
+pub struct Staging {
+    dir: PathBuf,
+    active: Mutex<HashSet<PathBuf>>,
+}
+
+/// Files left in the staging area by a previous run.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Leftovers {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl Staging {
+    /// Open the staging area under `storage_root`, clearing out anything a
+    /// previous run left behind. Nothing can be in flight yet, so any
+    /// leftovers are uploads that were cut off.
+    pub fn open(storage_root: &Path) -> io::Result<(Arc<Self>, Leftovers)> {
+        let dir = storage_root.join(STAGING_DIR);
+        std::fs::create_dir_all(&dir)?;
+
+        let mut leftovers = Leftovers::default();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            if std::fs::remove_file(entry.path()).is_ok() {
+                leftovers.files += 1;
+                leftovers.bytes += size;
+            }
+        }
+
+        let staging = Arc::new(Staging {
+            dir,
+            active: Mutex::new(HashSet::new()),
+        });
+        Ok((staging, leftovers))
+    }
+
+    /// Reserve a new staging file. It is removed when dropped unless committed.
+    pub fn begin(self: &Arc<Self>) -> StagedFile {
+        let path = self.dir.join(format!("{}.part", uuid::Uuid::new_v4()));
+        self.active.lock().unwrap().insert(path.clone());
+        StagedFile {
+            path,
+            staging: self.clone(),
+            committed: false,
+        }
+    }
+
+    /// Remove the files of uploads still in flight, e.g. when shutdown cuts
+    /// them off. Returns how many were removed.
+    pub fn abort_all(&self) -> usize {
+        let paths: Vec<PathBuf> = self.active.lock().unwrap().drain().collect();
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        paths.len()
+    }
+
+    /// Remove staging files that have not been written to for `idle`. These
+    /// belong to uploads whose client stalled or whose task was lost.
+    pub fn sweep(&self, idle: Duration) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let Ok(modified) = entry.metadata().and_then(|m| m.modified()) else {
+                continue;
+            };
+            if now.duration_since(modified).unwrap_or_default() < idle {
+                continue;
+            }
+            if std::fs::remove_file(entry.path()).is_ok() {
+                self.active.lock().unwrap().remove(&entry.path());
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Sweep the staging area in the background for as long as the server runs.
+    pub fn spawn_sweeper(self: &Arc<Self>, idle: Duration) -> tokio::task::JoinHandle<()> {
+        let staging = self.clone();
+        let interval = (idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let staging = staging.clone();
+                match tokio::task::spawn_blocking(move || staging.sweep(idle)).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(removed)) => {
+                        tracing::info!(removed, "removed abandoned staging files");
+                    }
+                    Ok(Err(e)) => tracing::warn!(error = %e, "failed to sweep staging area"),
+                    Err(e) => tracing::warn!(error = %e, "staging sweeper panicked"),
+                }
+            }
+        })
+    }
+}
+
+/// An upload being written to the staging area.
+pub struct StagedFile {
+    path: PathBuf,
+    staging: Arc<Staging>,
+    committed: bool,
+}
+
+impl StagedFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flush `file` to disk and move it to `destination`, replacing anything
+    /// already there. The rename is made durable by syncing the directory too.
+    pub async fn commit(mut self, mut file: File, destination: &Path) -> io::Result<()> {
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
+
+        tokio::fs::rename(&self.path, destination).await?;
+        self.committed = true;
+
+        #[cfg(unix)]
+        if let Some(parent) = destination.parent() {
+            File::open(parent).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        self.staging.active.lock().unwrap().remove(&self.path);
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
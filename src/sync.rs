@@ -0,0 +1,492 @@
+use prost_types::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    client::{self, Client, Transfer},
+    delta,
+    fileservice::{SearchRequest, SignatureRequest},
+};
+
+/// File in the local folder that remembers what both sides looked like after the last sync.
+pub const STATE_FILE: &str = ".grpc-files-sync.json";
+
+/// Size and modification time of one side of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub is_dir: bool,
+    pub size: u64,
+    pub secs: i64,
+    pub nanos: u32,
+}
+
+impl Stamp {
+    /// Directories only count as changed if they appear or disappear.
+    fn changed_since(&self, previous: &Stamp) -> bool {
+        if self.is_dir && previous.is_dir {
+            false
+        } else {
+            self != previous
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SyncedEntry {
+    local: Stamp,
+    remote: Stamp,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    #[serde(default)]
+    volume: String,
+    remote_root: String,
+    entries: BTreeMap<String, SyncedEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncAction {
+    CreateLocalDir(String),
+    CreateRemoteDir(String),
+    Upload(String),
+    Download(String),
+    DeleteLocal(String),
+    DeleteRemote(String),
+    DeleteLocalDir(String),
+    DeleteRemoteDir(String),
+    /// Both sides changed: the local copy is kept under `conflict_path` and
+    /// uploaded, then the server's version is downloaded to `path`.
+    Conflict { path: String, conflict_path: String },
+    Skip { path: String, reason: String },
+}
+
+impl SyncAction {
+    fn path(&self) -> &str {
+        match self {
+            SyncAction::CreateLocalDir(p)
+            | SyncAction::CreateRemoteDir(p)
+            | SyncAction::Upload(p)
+            | SyncAction::Download(p)
+            | SyncAction::DeleteLocal(p)
+            | SyncAction::DeleteRemote(p)
+            | SyncAction::DeleteLocalDir(p)
+            | SyncAction::DeleteRemoteDir(p) => p,
+            SyncAction::Conflict { path, .. } | SyncAction::Skip { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::CreateLocalDir(p) => write!(f, "mkdir local   {}", p),
+            SyncAction::CreateRemoteDir(p) => write!(f, "mkdir remote  {}", p),
+            SyncAction::Upload(p) => write!(f, "upload        {}", p),
+            SyncAction::Download(p) => write!(f, "download      {}", p),
+            SyncAction::DeleteLocal(p) => write!(f, "delete local  {}", p),
+            SyncAction::DeleteRemote(p) => write!(f, "delete remote {}", p),
+            SyncAction::DeleteLocalDir(p) => write!(f, "rmdir local   {}", p),
+            SyncAction::DeleteRemoteDir(p) => write!(f, "rmdir remote  {}", p),
+            SyncAction::Conflict {
+                path,
+                conflict_path,
+            } => write!(f, "conflict      {} (local copy kept as {})", path, conflict_path),
+            SyncAction::Skip { path, reason } => write!(f, "skip          {} ({})", path, reason),
+        }
+    }
+}
+
+/// Everything needed to carry out a sync, computed without changing either side.
+pub struct SyncPlan {
+    pub local_root: PathBuf,
+    pub volume: String,
+    pub remote_root: String,
+    pub actions: Vec<SyncAction>,
+    state: SyncState,
+    remote: BTreeMap<String, Stamp>,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub completed: usize,
+    pub failed: Vec<(SyncAction, String)>,
+}
+
+/// Compare the local folder and the server directory against the state of the
+/// last sync and work out what needs to happen on each side.
+pub async fn plan(
+    client: &Client,
+    local_root: &Path,
+    volume: &str,
+    remote_root: &str,
+) -> Result<SyncPlan, Box<dyn std::error::Error>> {
+    let remote_root = remote_root.trim_matches('/').to_string();
+    if !local_root.is_dir() {
+        return Err(format!("{} is not a directory", local_root.display()).into());
+    }
+
+    let state = load_state(local_root, volume, &remote_root)?;
+    let local = scan_local(local_root.to_path_buf()).await?;
+    let remote = scan_remote(client, volume, &remote_root).await?;
+
+    let paths: BTreeSet<&String> = local
+        .keys()
+        .chain(remote.keys())
+        .chain(state.entries.keys())
+        .collect();
+
+    let mut actions = Vec::new();
+    // Directory removals run last and deepest first, once their contents are gone
+    let mut dir_removals = Vec::new();
+
+    for path in paths {
+        let previous = state.entries.get(path);
+        match (local.get(path), remote.get(path)) {
+            (Some(l), Some(r)) if l.is_dir && r.is_dir => {}
+            (Some(l), Some(r)) if l.is_dir != r.is_dir => actions.push(SyncAction::Skip {
+                path: path.clone(),
+                reason: "file on one side, directory on the other".to_string(),
+            }),
+            (Some(l), Some(r)) => {
+                let (local_changed, remote_changed) = match previous {
+                    Some(p) => (l.changed_since(&p.local), r.changed_since(&p.remote)),
+                    None if files_identical(client, local_root, volume, &remote_root, path, l, r).await? => {
+                        (false, false)
+                    }
+                    None => (true, true),
+                };
+                match (local_changed, remote_changed) {
+                    (false, false) => {}
+                    (true, false) => actions.push(SyncAction::Upload(path.clone())),
+                    (false, true) => actions.push(SyncAction::Download(path.clone())),
+                    (true, true) => actions.push(SyncAction::Conflict {
+                        path: path.clone(),
+                        conflict_path: conflict_name(path),
+                    }),
+                }
+            }
+            (Some(l), None) => match previous {
+                Some(_) if l.is_dir => dir_removals.push(SyncAction::DeleteLocalDir(path.clone())),
+                Some(p) if !l.changed_since(&p.local) => {
+                    actions.push(SyncAction::DeleteLocal(path.clone()))
+                }
+                _ if l.is_dir => actions.push(SyncAction::CreateRemoteDir(path.clone())),
+                _ => actions.push(SyncAction::Upload(path.clone())),
+            },
+            (None, Some(r)) => match previous {
+                Some(_) if r.is_dir => dir_removals.push(SyncAction::DeleteRemoteDir(path.clone())),
+                Some(p) if !r.changed_since(&p.remote) => {
+                    actions.push(SyncAction::DeleteRemote(path.clone()))
+                }
+                _ if r.is_dir => actions.push(SyncAction::CreateLocalDir(path.clone())),
+                _ => actions.push(SyncAction::Download(path.clone())),
+            },
+            (None, None) => {}
+        }
+    }
+
+    dir_removals.reverse();
+    actions.extend(dir_removals);
+
+    Ok(SyncPlan {
+        local_root: local_root.to_path_buf(),
+        volume: volume.to_string(),
+        remote_root,
+        actions,
+        state,
+        remote,
+    })
+}
+
+/// Carry out a plan, continuing past individual failures, then record the new state.
+pub async fn execute(
+    client: &Client,
+    plan: SyncPlan,
+) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    let mut report = SyncReport::default();
+
+    for action in &plan.actions {
+        if let SyncAction::Skip { .. } = action {
+            continue;
+        }
+        match execute_action(client, &plan, action).await {
+            Ok(()) => report.completed += 1,
+            Err(e) => report.failed.push((action.clone(), e.to_string())),
+        }
+    }
+
+    // Record both sides as they are now, except for paths that could not be synced
+    let local = scan_local(plan.local_root.clone()).await?;
+    let remote = scan_remote(client, &plan.volume, &plan.remote_root).await?;
+    let unsettled: BTreeSet<&str> = report
+        .failed
+        .iter()
+        .map(|(a, _)| a.path())
+        .chain(plan.actions.iter().filter_map(|a| match a {
+            SyncAction::Skip { path, .. } => Some(path.as_str()),
+            _ => None,
+        }))
+        .collect();
+
+    let mut entries = BTreeMap::new();
+    for (path, l) in &local {
+        if unsettled.contains(path.as_str()) {
+            if let Some(previous) = plan.state.entries.get(path) {
+                entries.insert(path.clone(), *previous);
+            }
+            continue;
+        }
+        if let Some(r) = remote.get(path) {
+            entries.insert(path.clone(), SyncedEntry { local: *l, remote: *r });
+        }
+    }
+
+    save_state(
+        &plan.local_root,
+        &SyncState {
+            volume: plan.volume.clone(),
+            remote_root: plan.remote_root.clone(),
+            entries,
+        },
+    )?;
+
+    Ok(report)
+}
+
+async fn execute_action(
+    client: &Client,
+    plan: &SyncPlan,
+    action: &SyncAction,
+) -> Result<(), client::Error> {
+    let local = |path: &str| plan.local_root.join(path);
+    let remote = |path: &str| join_remote(&plan.remote_root, path);
+
+    match action {
+        SyncAction::CreateLocalDir(path) => tokio::fs::create_dir_all(local(path)).await?,
+        SyncAction::CreateRemoteDir(path) => {
+            client.create_directories(&plan.volume, &remote(path)).await?
+        }
+        SyncAction::Upload(path) => upload(client, plan, path).await?,
+        SyncAction::Download(path) => download(client, plan, path).await?,
+        SyncAction::DeleteLocal(path) => tokio::fs::remove_file(local(path)).await?,
+        SyncAction::DeleteRemote(path) => client.delete_file(&plan.volume, &remote(path)).await?,
+        SyncAction::DeleteLocalDir(path) => tokio::fs::remove_dir(local(path)).await?,
+        SyncAction::DeleteRemoteDir(path) => {
+            client.delete_directory(&plan.volume, &remote(path), false).await?
+        }
+        SyncAction::Conflict {
+            path,
+            conflict_path,
+        } => {
+            tokio::fs::rename(local(path), local(conflict_path)).await?;
+            upload(client, plan, conflict_path).await?;
+            download(client, plan, path).await?;
+        }
+        SyncAction::Skip { .. } => {}
+    }
+    Ok(())
+}
+
+async fn upload(
+    client: &Client,
+    plan: &SyncPlan,
+    path: &str,
+) -> Result<(), client::Error> {
+    let local_path = plan.local_root.join(path);
+    let remote_path = join_remote(&plan.remote_root, path);
+
+    if plan.remote.get(path).map(|r| !r.is_dir).unwrap_or(false) {
+        return client.delta_upload_file(&local_path, &plan.volume, &remote_path).await;
+    }
+
+    if let Some((remote_parent, _)) = remote_path.rsplit_once('/') {
+        client.create_directories(&plan.volume, remote_parent).await?;
+    }
+    client
+        .upload_file(&local_path, &plan.volume, &remote_path, &Transfer::default())
+        .await?;
+    Ok(())
+}
+
+async fn download(
+    client: &Client,
+    plan: &SyncPlan,
+    path: &str,
+) -> Result<(), client::Error> {
+    let local_path = plan.local_root.join(path);
+    if let Some(parent) = local_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let remote_path = join_remote(&plan.remote_root, path);
+    client.delta_download_file(&plan.volume, &remote_path, &local_path).await?;
+    Ok(())
+}
+
+/// Without sync history, two files only count as the same if every block matches.
+async fn files_identical(
+    client: &Client,
+    local_root: &Path,
+    volume: &str,
+    remote_root: &str,
+    path: &str,
+    local: &Stamp,
+    remote: &Stamp,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if local.size != remote.size {
+        return Ok(false);
+    }
+
+    let signature = client
+        .rpc()
+        .get_signatures(SignatureRequest {
+            path: join_remote(remote_root, path),
+            block_size: 0,
+            volume: volume.to_string(),
+        })
+        .await?
+        .into_inner();
+
+    let file = std::fs::File::open(local_root.join(path))?;
+    let block_size = signature.block_size;
+    let local_blocks =
+        tokio::task::spawn_blocking(move || delta::signatures(file, block_size)).await??;
+
+    Ok(local_blocks == signature.blocks)
+}
+
+async fn scan_remote(
+    client: &Client,
+    volume: &str,
+    remote_root: &str,
+) -> Result<BTreeMap<String, Stamp>, Box<dyn std::error::Error>> {
+    let files = client
+        .rpc()
+        .search_files(SearchRequest {
+            path: remote_root.to_string(),
+            query: String::new(),
+            tag_filters: Vec::new(),
+            volume: volume.to_string(),
+        })
+        .await?
+        .into_inner()
+        .files;
+
+    let prefix = if remote_root.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", remote_root)
+    };
+
+    Ok(files
+        .into_iter()
+        .filter_map(|f| {
+            let path = f.path.strip_prefix(&prefix)?.to_string();
+            let time = f.upload_time.unwrap_or_default();
+            Some((
+                path,
+                Stamp {
+                    is_dir: f.is_directory,
+                    size: f.size,
+                    secs: time.seconds,
+                    nanos: time.nanos as u32,
+                },
+            ))
+        })
+        .collect())
+}
+
+async fn scan_local(root: PathBuf) -> Result<BTreeMap<String, Stamp>, Box<dyn std::error::Error>> {
+    let entries = tokio::task::spawn_blocking(move || {
+        let mut entries = BTreeMap::new();
+        let mut pending = vec![(root, String::new())];
+        while let Some((dir, relative)) = pending.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Dot-files are not synced, which also keeps the sync state
+                // file out of the plan
+                if name.starts_with('.') {
+                    continue;
+                }
+                let path = join_remote(&relative, &name);
+                let metadata = entry.metadata()?;
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                if metadata.is_dir() {
+                    pending.push((entry.path(), path.clone()));
+                }
+                entries.insert(
+                    path,
+                    Stamp {
+                        is_dir: metadata.is_dir(),
+                        size: if metadata.is_dir() { 0 } else { metadata.len() },
+                        secs: modified.as_secs() as i64,
+                        nanos: modified.subsec_nanos(),
+                    },
+                );
+            }
+        }
+        Ok::<_, std::io::Error>(entries)
+    })
+    .await??;
+    Ok(entries)
+}
+
+fn load_state(
+    local_root: &Path,
+    volume: &str,
+    remote_root: &str,
+) -> Result<SyncState, Box<dyn std::error::Error>> {
+    let path = local_root.join(STATE_FILE);
+    if !path.exists() {
+        return Ok(SyncState::default());
+    }
+    let content = std::fs::read_to_string(&path)?;
+    let state: SyncState = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    // History for a different server directory says nothing about this one
+    if state.volume != volume || state.remote_root != remote_root {
+        return Ok(SyncState::default());
+    }
+    Ok(state)
+}
+
+fn save_state(local_root: &Path, state: &SyncState) -> std::io::Result<()> {
+    let path = local_root.join(STATE_FILE);
+    let temp = local_root.join(format!("{}.tmp", STATE_FILE));
+    std::fs::write(&temp, serde_json::to_string_pretty(state)?)?;
+    std::fs::rename(temp, path)
+}
+
+fn join_remote(root: &str, path: &str) -> String {
+    if root.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", root, path)
+    }
+}
+
+/// `dir/report.txt` becomes `dir/report (conflict 2026-01-31 120000).txt`.
+fn conflict_name(path: &str) -> String {
+    let timestamp = Timestamp::from(SystemTime::now()).to_string();
+    let (date, time) = timestamp.split_once('T').unwrap_or((&timestamp, ""));
+    let time: String = time.chars().take(8).filter(|c| *c != ':').collect();
+
+    let (dir, name) = match path.rfind('/') {
+        Some(i) => (&path[..=i], &path[i + 1..]),
+        None => ("", path),
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    };
+    format!("{}{} (conflict {} {}){}", dir, stem, date, time, ext)
+}
@@ -0,0 +1,266 @@
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+use tower::{Layer, Service};
+
+use crate::config::Config;
+
+/// Token bucket that spreads a flow of bytes or requests out to a steady rate.
+///
+/// Up to one second's worth of the rate may be used at once; anything beyond
+/// that has to wait until the bucket refills.
+pub struct RateLimiter {
+    per_unit: Duration,
+    burst: Duration,
+    /// When the bucket will next be completely full.
+    full_at: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_per_sec: u64) -> Self {
+        let rate = rate_per_sec.max(1);
+        RateLimiter {
+            per_unit: Duration::from_secs_f64(1.0 / rate as f64),
+            burst: Duration::from_secs(1),
+            full_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Take `amount` from the bucket, returning how long the caller must wait
+    /// before using it.
+    pub fn reserve(&self, amount: u64) -> Duration {
+        let now = Instant::now();
+        let mut full_at = self.full_at.lock().unwrap();
+        *full_at = (*full_at).max(now) + self.per_unit.mul_f64(amount as f64);
+        full_at.saturating_duration_since(now + self.burst)
+    }
+
+    /// Take `amount` only if it is available right away. Otherwise returns
+    /// how long until it will be.
+    pub fn try_acquire(&self, amount: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut full_at = self.full_at.lock().unwrap();
+        let next = (*full_at).max(now) + self.per_unit.mul_f64(amount as f64);
+        if next > now + self.burst {
+            return Err(next - (now + self.burst));
+        }
+        *full_at = next;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// Server-side bandwidth and request rate limits, applied both to all
+/// transfers together and to each client identity on its own.
+#[derive(Default)]
+pub struct TransferLimits {
+    upload: Option<RateLimiter>,
+    download: Option<RateLimiter>,
+    client_upload: Option<u64>,
+    client_download: Option<u64>,
+    client_requests: Option<u64>,
+    clients: Mutex<HashMap<String, Arc<ClientLimits>>>,
+}
+
+#[derive(Default)]
+struct ClientLimits {
+    upload: Option<RateLimiter>,
+    download: Option<RateLimiter>,
+    requests: Option<RateLimiter>,
+}
+
+impl TransferLimits {
+    pub fn from_config(config: &Config) -> Self {
+        TransferLimits {
+            upload: config.upload_limit_bytes_per_sec.map(RateLimiter::new),
+            download: config.download_limit_bytes_per_sec.map(RateLimiter::new),
+            client_upload: config.client_upload_limit_bytes_per_sec,
+            client_download: config.client_download_limit_bytes_per_sec,
+            client_requests: config.metadata_requests_per_sec,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn client(&self, client: &str) -> Arc<ClientLimits> {
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(client.to_string())
+            .or_insert_with(|| {
+                Arc::new(ClientLimits {
+                    upload: self.client_upload.map(RateLimiter::new),
+                    download: self.client_download.map(RateLimiter::new),
+                    requests: self.client_requests.map(RateLimiter::new),
+                })
+            })
+            .clone()
+    }
+
+    /// Account for `bytes` moved for `client`, returning how long to wait before
+    /// moving more. Blocking code can sleep on this; async code should use [`Self::throttle`].
+    pub fn reserve(&self, client: &str, direction: Direction, bytes: u64) -> Duration {
+        let client = self.client(client);
+        let (global, own) = match direction {
+            Direction::Upload => (&self.upload, &client.upload),
+            Direction::Download => (&self.download, &client.download),
+        };
+        let global_wait = global.as_ref().map(|l| l.reserve(bytes)).unwrap_or_default();
+        let own_wait = own.as_ref().map(|l| l.reserve(bytes)).unwrap_or_default();
+        global_wait.max(own_wait)
+    }
+
+    pub async fn throttle(&self, client: &str, direction: Direction, bytes: u64) {
+        let wait = self.reserve(client, direction, bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Count one metadata request for `client`. If it is over its rate,
+    /// returns how long until it may make another.
+    pub fn allow_request(&self, client: &str) -> Result<(), Duration> {
+        match &self.client(client).requests {
+            Some(limiter) => limiter.try_acquire(1),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Client-side speed cap for everything sent and received over a channel.
+#[derive(Clone, Default)]
+pub struct ThrottleLayer {
+    upload: Option<Arc<RateLimiter>>,
+    download: Option<Arc<RateLimiter>>,
+}
+
+impl ThrottleLayer {
+    pub fn new(upload_bytes_per_sec: Option<u64>, download_bytes_per_sec: Option<u64>) -> Self {
+        ThrottleLayer {
+            upload: upload_bytes_per_sec.map(|r| Arc::new(RateLimiter::new(r))),
+            download: download_bytes_per_sec.map(|r| Arc::new(RateLimiter::new(r))),
+        }
+    }
+}
+
+impl<S> Layer<S> for ThrottleLayer {
+    type Service = Throttled<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Throttled {
+            inner,
+            limits: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Throttled<S> {
+    inner: S,
+    limits: ThrottleLayer,
+}
+
+impl<S> Service<http::Request<tonic::body::Body>> for Throttled<S>
+where
+    S: Service<http::Request<tonic::body::Body>, Response = http::Response<tonic::body::Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::Body>) -> Self::Future {
+        let upload = self.limits.upload.clone();
+        let download = self.limits.download.clone();
+        let request = request.map(|body| tonic::body::Body::new(ThrottledBody::new(body, upload)));
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(response.map(|body| tonic::body::Body::new(ThrottledBody::new(body, download))))
+        })
+    }
+}
+
+/// Body wrapper that holds back each data frame until the limiter allows it.
+struct ThrottledBody<B> {
+    inner: B,
+    limiter: Option<Arc<RateLimiter>>,
+    delay: Option<Pin<Box<Sleep>>>,
+    held: Option<Frame<Bytes>>,
+}
+
+impl<B> ThrottledBody<B> {
+    fn new(inner: B, limiter: Option<Arc<RateLimiter>>) -> Self {
+        ThrottledBody {
+            inner,
+            limiter,
+            delay: None,
+            held: None,
+        }
+    }
+}
+
+impl<B> Body for ThrottledBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+            return Poll::Ready(this.held.take().map(Ok));
+        }
+
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        let (Some(limiter), Some(Ok(data))) = (&this.limiter, &frame) else {
+            return Poll::Ready(frame);
+        };
+        let Some(len) = data.data_ref().map(|d| d.len() as u64) else {
+            return Poll::Ready(frame);
+        };
+
+        let wait = limiter.reserve(len);
+        if wait.is_zero() {
+            return Poll::Ready(frame);
+        }
+        let mut delay = Box::pin(tokio::time::sleep(wait));
+        if delay.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(frame);
+        }
+        this.delay = Some(delay);
+        this.held = frame.and_then(Result::ok);
+        Poll::Pending
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.held.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.held.is_some() {
+            return SizeHint::default();
+        }
+        self.inner.size_hint()
+    }
+}
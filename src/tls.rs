@@ -0,0 +1,413 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{WebPkiSupportedAlgorithms, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::ReceiverStream;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::Config;
+use crate::identity;
+
+/// How long a new connection may take to complete its TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server's TLS settings, built from its certificate, key and client CA
+/// and replaced when those files change. Each handshake uses whatever is
+/// current, so established connections are left alone.
+pub struct ReloadableTls {
+    files: ServerFiles,
+    clients: RwLock<ClientCheck>,
+    current: RwLock<Loaded>,
+}
+
+struct ServerFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    /// Not needed while clients are checked against pinned keys.
+    ca: Option<PathBuf>,
+}
+
+/// How the server checks client certificates.
+#[derive(Clone)]
+struct ClientCheck {
+    /// Names of the pinned client keys, by the SHA-256 hash of the key.
+    pins: BTreeMap<[u8; 32], String>,
+    required: bool,
+}
+
+struct Loaded {
+    /// Hash of everything the configuration was built from.
+    fingerprint: blake3::Hash,
+    config: Arc<ServerConfig>,
+    /// CN and expiry of the server certificate, for the logs.
+    subject: String,
+    expires: String,
+}
+
+impl ReloadableTls {
+    pub fn load(config: &Config) -> Result<Arc<Self>, Box<dyn Error>> {
+        let clients = ClientCheck::from_config(config)?;
+        let ca = config.tls_file(&config.tls_ca_file, "ca-cert.pem");
+        let files = ServerFiles {
+            cert: config.tls_file(&config.tls_server_cert_file, "server-cert.pem")?,
+            key: config.tls_file(&config.tls_server_key_file, "server-key.pem")?,
+            ca: if clients.pins.is_empty() { Some(ca?) } else { ca.ok() },
+        };
+        let loaded = files.build(&clients)?;
+        tracing::info!(subject = %loaded.subject, expires = %loaded.expires, "loaded server certificate");
+        Ok(Arc::new(ReloadableTls {
+            files,
+            clients: RwLock::new(clients),
+            current: RwLock::new(loaded),
+        }))
+    }
+
+    /// The files to watch for rotations.
+    pub fn files(&self) -> Vec<PathBuf> {
+        [&self.files.cert, &self.files.key]
+            .into_iter()
+            .chain(&self.files.ca)
+            .cloned()
+            .collect()
+    }
+
+    /// Read the files again, with the client checks from `config` if given,
+    /// and switch to them if anything changed. Returns whether it did. On
+    /// error the current settings stay in use, so a rotation caught halfway
+    /// through is picked up by the next reload.
+    pub fn reload(&self, config: Option<&Config>) -> Result<bool, Box<dyn Error>> {
+        let clients = match config {
+            Some(config) => ClientCheck::from_config(config)?,
+            None => self.clients.read().unwrap().clone(),
+        };
+        let loaded = self.files.build(&clients)?;
+        *self.clients.write().unwrap() = clients;
+        let mut current = self.current.write().unwrap();
+        if loaded.fingerprint == current.fingerprint {
+            return Ok(false);
+        }
+        tracing::info!(subject = %loaded.subject, expires = %loaded.expires, "reloaded server certificate");
+        *current = loaded;
+        Ok(true)
+    }
+
+    /// Whether clients are checked against pinned keys rather than the CA.
+    pub fn pins_clients(&self) -> bool {
+        !self.clients.read().unwrap().pins.is_empty()
+    }
+
+    /// Name of the client a certificate's key is pinned for, if it still is.
+    pub fn pinned_name(&self, cert: &[u8]) -> Option<String> {
+        let hash = spki_hash(cert)?;
+        self.clients.read().unwrap().pins.get(&hash).cloned()
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().config.clone())
+    }
+
+    /// Accept connections on `listener` and hand them over once their
+    /// handshake completes. Stops when the returned stream is dropped.
+    pub fn incoming(
+        self: &Arc<Self>,
+        listener: TcpListener,
+    ) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(64);
+        let tls = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = tokio::select! {
+                    _ = tx.closed() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            // Usually out of file descriptors; give connections time to close
+                            tracing::warn!(error = %e, "failed to accept connection");
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+                let _ = stream.set_nodelay(true);
+                let acceptor = tls.acceptor();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!(%addr, error = %e, "TLS handshake failed"),
+                        Err(_) => tracing::debug!(%addr, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
+
+impl ServerFiles {
+    fn build(&self, clients: &ClientCheck) -> Result<Loaded, Box<dyn Error>> {
+        let cert_pem = read(&self.cert)?;
+        let key_pem = read(&self.key)?;
+        let certs = CertificateDer::pem_slice_iter(&cert_pem).collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(&key_pem)?;
+
+        let mut hasher = blake3::Hasher::new();
+        for pem in [&cert_pem, &key_pem] {
+            hasher.update(blake3::hash(pem).as_bytes());
+        }
+        hasher.update(&[clients.required as u8]);
+        let verifier: Arc<dyn ClientCertVerifier> = if clients.pins.is_empty() {
+            let ca = self
+                .ca
+                .as_ref()
+                .ok_or("tls_ca_file is needed unless tls_client_public_key_pins is set")?;
+            let ca_pem = read(ca)?;
+            hasher.update(blake3::hash(&ca_pem).as_bytes());
+            let builder = WebPkiClientVerifier::builder(Arc::new(root_store(&ca_pem)?));
+            match clients.required {
+                true => builder.build()?,
+                false => builder.allow_unauthenticated().build()?,
+            }
+        } else {
+            let pins: Vec<[u8; 32]> = clients.pins.keys().copied().collect();
+            for pin in &pins {
+                hasher.update(pin);
+            }
+            Arc::new(PinnedKeys::new(pins, clients.required))
+        };
+
+        let first = certs.first().ok_or("The server certificate file holds no certificate")?;
+        let (subject, expires) = describe(first);
+        let mut config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)?;
+        // HTTP/1.1 is for gRPC-Web and the REST gateway
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Loaded {
+            fingerprint: hasher.finalize(),
+            config: Arc::new(config),
+            subject,
+            expires,
+        })
+    }
+}
+
+impl ClientCheck {
+    fn from_config(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let mut pins = BTreeMap::new();
+        for pin in &config.tls_client_public_key_pins {
+            if identity::is_uid_name(&pin.name) {
+                return Err(format!("Client key pin name '{}' is reserved for local processes", pin.name).into());
+            }
+            if pins.insert(parse_pin(&pin.pin)?, pin.name.clone()).is_some() {
+                return Err(format!("Client key pin '{}' has the same key as another pin", pin.name).into());
+            }
+        }
+        Ok(ClientCheck {
+            pins,
+            required: config.tls_require_client_cert,
+        })
+    }
+}
+
+/// TLS settings for the client: its certificate, if it has one, and the CA
+/// or pinned keys it checks the server against.
+pub fn client_config(config: &Config) -> Result<ClientConfig, Box<dyn Error>> {
+    let builder = if config.tls_server_public_key_pins.is_empty() {
+        let ca_pem = read(&config.tls_file(&config.tls_ca_file, "ca-cert.pem")?)?;
+        ClientConfig::builder().with_root_certificates(root_store(&ca_pem)?)
+    } else {
+        let pins = config
+            .tls_server_public_key_pins
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<_, _>>()?;
+        let verifier = PinnedKeys::new(pins, true);
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+    };
+
+    // Without a certificate the client can only use servers that do not require one
+    let cert_file = config.tls_file(&config.tls_client_cert_file, "client-cert.pem")?;
+    let mut tls = if config.tls_client_cert_file.is_none() && !cert_file.exists() {
+        tracing::debug!(path = %cert_file.display(), "no client certificate, connecting without one");
+        builder.with_no_client_auth()
+    } else {
+        let key_file = config.tls_file(&config.tls_client_key_file, "client-key.pem")?;
+        let certs = CertificateDer::pem_slice_iter(&read(&cert_file)?).collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(&read(&key_file)?)?;
+        builder.with_client_auth_cert(certs, key)?
+    };
+    tls.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(tls)
+}
+
+/// Base64 SHA-256 hash of a certificate's SubjectPublicKeyInfo: the form
+/// public key pins are written in.
+fn public_key_pin(cert: &[u8]) -> Option<String> {
+    spki_hash(cert).map(|hash| BASE64.encode(hash))
+}
+
+fn parse_pin(pin: &str) -> Result<[u8; 32], Box<dyn Error>> {
+    BASE64
+        .decode(pin.trim())
+        .ok()
+        .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+        .ok_or_else(|| format!("Invalid public key pin '{}': expected a base64 SHA-256 hash", pin).into())
+}
+
+fn spki_hash(cert: &[u8]) -> Option<[u8; 32]> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, cert.public_key().raw);
+    digest.as_ref().try_into().ok()
+}
+
+/// Accepts a peer by the hash of its public key instead of a CA signature.
+/// The peer still proves it holds the key during the handshake, but names and
+/// expiry dates are not checked: replacing the key is what retires it. A
+/// client is known by the name its key is pinned under, never by its CN.
+#[derive(Debug)]
+struct PinnedKeys {
+    pins: Vec<[u8; 32]>,
+    mandatory: bool,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedKeys {
+    fn new(pins: Vec<[u8; 32]>, mandatory: bool) -> Self {
+        PinnedKeys {
+            pins,
+            mandatory,
+            algorithms: rustls::crypto::aws_lc_rs::default_provider().signature_verification_algorithms,
+        }
+    }
+
+    fn check(&self, cert: &CertificateDer) -> Result<(), rustls::Error> {
+        match spki_hash(cert) {
+            Some(hash) if self.pins.contains(&hash) => Ok(()),
+            _ => Err(rustls::Error::General(format!(
+                "public key {} is not pinned",
+                public_key_pin(cert).unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedKeys {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.check(end_entity).map(|_| ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+impl ClientCertVerifier for PinnedKeys {
+    fn client_auth_mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.check(end_entity).map(|_| ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e).into())
+}
+
+fn root_store(ca_pem: &[u8]) -> Result<RootCertStore, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    for ca in CertificateDer::pem_slice_iter(ca_pem) {
+        roots.add(ca?)?;
+    }
+    Ok(roots)
+}
+
+/// Subject CN and expiry of a certificate, for the logs.
+fn describe(cert: &CertificateDer) -> (String, String) {
+    let subject = identity::common_name(cert).unwrap_or_else(|| "unknown".to_string());
+    let expires = X509Certificate::from_der(cert)
+        .map(|(_, cert)| cert.validity().not_after.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    (subject, expires)
+}
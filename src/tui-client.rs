@@ -1,6 +1,66 @@
-use grpc_files::tui::run;
+use grpc_files::{
+    client::Client,
+    config::{Config, ConfigArgs},
+    logging, sync,
+    tui::run,
+};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: tui-client [--config <file>] [--profile <name>] [--<setting> <value>]... [sync ... | config check]";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    run().await
+    let (config_args, args) = ConfigArgs::parse(std::env::args().skip(1).collect())?;
+    let loaded = Config::load_with(&config_args)?;
+    match args.first().map(String::as_str) {
+        Some("sync") => sync_command(&args[1..], loaded.config).await,
+        Some("config") if args[1..] == ["check"] => {
+            print!("{}", loaded);
+            Ok(())
+        }
+        None => run(loaded.config).await,
+        Some(_) => Err(USAGE.into()),
+    }
+}
+
+/// `tui-client sync <local-dir> [[volume:]remote-dir] [--dry-run]`
+async fn sync_command(args: &[String], config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let dry_run = args.iter().any(|a| a == "--dry-run" || a == "-n");
+    let positional: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+    let local = positional
+        .first()
+        .ok_or("Usage: tui-client sync <local-dir> [[volume:]remote-dir] [--dry-run]")?;
+    let remote = positional.get(1).map(|s| s.as_str()).unwrap_or("");
+    // Without a `volume:` prefix the server's default volume is used
+    let (volume, remote) = remote.split_once(':').unwrap_or(("", remote));
+
+    let _log_guard = logging::init(&config, "tui-client", true)?;
+    let client = Client::connect(&config).await?;
+    let plan = sync::plan(&client, &PathBuf::from(local), volume, remote).await?;
+
+    if plan.actions.is_empty() {
+        println!("Already in sync");
+        return Ok(());
+    }
+    for action in &plan.actions {
+        println!("{}", action);
+    }
+    if dry_run {
+        println!("Dry run: {} actions planned, nothing changed", plan.actions.len());
+        return Ok(());
+    }
+
+    let report = sync::execute(&client, plan).await?;
+    for (action, error) in &report.failed {
+        eprintln!("failed: {}: {}", action, error);
+    }
+    println!(
+        "Sync finished: {} completed, {} failed",
+        report.completed,
+        report.failed.len()
+    );
+    if !report.failed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
@@ -1,10 +1,19 @@
-use crate::fileservice::FileInfo;
+use crate::fileservice::{EntryType, FileInfo, ListResponse, PreviewResponse, SortField, VolumeInfo};
+
+/// Entries fetched per listing request, so large directories show up quickly.
+pub const PAGE_SIZE: u32 = 200;
+/// Fetch the next page once the selection is this close to the end of what is loaded.
+const PREFETCH_MARGIN: usize = 20;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AppMode {
     Normal,
     Uploading,
     CreatingDirectory,
+    EditingTags,
+    FilteringByTag,
+    FilteringByName,
+    Syncing,
 }
 
 pub struct App {
@@ -13,7 +22,26 @@ pub struct App {
     status_message: Option<String>,
     mode: AppMode,
     selected_file_path: Option<String>,
+    /// The volume being browsed, or `None` while choosing one.
+    volume: Option<String>,
+    volumes: Vec<VolumeInfo>,
     current_directory: String,
+    tag_filters: Vec<String>,
+    name_glob: String,
+    entry_type: EntryType,
+    show_hidden: bool,
+    sort_by: SortField,
+    descending: bool,
+    next_page_token: String,
+    total_entries: u64,
+    show_preview: bool,
+    preview: Option<(String, PreviewResponse)>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
@@ -24,10 +52,68 @@ impl App {
             status_message: Some("Press r to refresh".to_string()),
             mode: AppMode::Normal,
             selected_file_path: None,
+            volume: None,
+            volumes: Vec::new(),
             current_directory: String::new(),
+            tag_filters: Vec::new(),
+            name_glob: String::new(),
+            entry_type: EntryType::Any,
+            show_hidden: false,
+            sort_by: SortField::Name,
+            descending: false,
+            next_page_token: String::new(),
+            total_entries: 0,
+            show_preview: true,
+            preview: None,
+        }
+    }
+
+    /// Name of the volume being browsed, empty while choosing one.
+    pub fn volume(&self) -> &str {
+        self.volume.as_deref().unwrap_or("")
+    }
+
+    pub fn at_volume_list(&self) -> bool {
+        self.volume.is_none()
+    }
+
+    pub fn volumes(&self) -> &[VolumeInfo] {
+        &self.volumes
+    }
+
+    /// Start browsing `name` from its root.
+    pub fn open_volume(&mut self, name: String) {
+        self.volume = Some(name);
+        self.current_directory = String::new();
+        self.selected_index = 0;
+        self.preview = None;
+    }
+
+    /// Go back to choosing a volume.
+    pub fn close_volume(&mut self) {
+        self.volume = None;
+        self.current_directory = String::new();
+        self.selected_index = 0;
+        self.preview = None;
+    }
+
+    pub fn update_volumes(&mut self, volumes: Vec<VolumeInfo>) {
+        self.volumes = volumes;
+        self.files.clear();
+        self.next_page_token.clear();
+        self.total_entries = 0;
+        if self.selected_index >= self.volumes.len() {
+            self.selected_index = self.volumes.len().saturating_sub(1);
         }
     }
 
+    pub fn selected_volume(&self) -> Option<&VolumeInfo> {
+        if !self.at_volume_list() {
+            return None;
+        }
+        self.volumes.get(self.selected_index)
+    }
+
     pub fn set_current_directory(&mut self, path: String) {
         self.current_directory = path;
     }
@@ -53,23 +139,39 @@ impl App {
         }
     }
 
+    /// Number of rows on screen: volumes while choosing one, otherwise files.
+    fn row_count(&self) -> usize {
+        if self.at_volume_list() { self.volumes.len() } else { self.files.len() }
+    }
+
     pub fn select_next(&mut self) {
-        if !self.files.is_empty() {
-            self.selected_index = (self.selected_index + 1) % self.files.len();
+        let rows = self.row_count();
+        if rows > 0 {
+            self.selected_index = (self.selected_index + 1) % rows;
         }
     }
 
     pub fn select_prev(&mut self) {
-        if !self.files.is_empty() {
+        let rows = self.row_count();
+        if rows > 0 {
             match self.selected_index {
-                0 => self.selected_index = self.files.len() - 1,
+                0 => self.selected_index = rows - 1,
                 _ => self.selected_index -= 1,
             }
         }
     }
 
-    pub fn update_files(&mut self, new_files: Vec<FileInfo>, current_path: String) {
+    /// Replace the listing with the first page of a directory.
+    pub fn update_files(&mut self, response: ListResponse) {
+        let ListResponse {
+            files: new_files,
+            current_path,
+            next_page_token,
+            total_entries,
+        } = response;
         self.current_directory = current_path.clone();
+        self.next_page_token = next_page_token;
+        self.total_entries = total_entries;
 
         // Add parent directory entry if not at root
         let mut files = new_files;
@@ -85,6 +187,8 @@ impl App {
                 upload_time: None,
                 is_directory: true,
                 path: parent_path,
+                tags: Vec::new(),
+                attributes: Default::default(),
             });
         }
 
@@ -94,13 +198,33 @@ impl App {
         }
     }
 
+    /// Add a further page to the listing.
+    pub fn append_files(&mut self, response: ListResponse) {
+        self.files.extend(response.files);
+        self.next_page_token = response.next_page_token;
+        self.total_entries = response.total_entries;
+    }
+
+    pub fn next_page_token(&self) -> &str {
+        &self.next_page_token
+    }
+
+    /// Whether the selection is near the end of what is loaded and more is available.
+    pub fn wants_more_files(&self) -> bool {
+        !self.next_page_token.is_empty()
+            && self.selected_index + PREFETCH_MARGIN >= self.files.len()
+    }
+
+    /// Entries in the directory that match the current filters, loaded or not.
+    pub fn total_entries(&self) -> u64 {
+        self.total_entries
+    }
+
     pub fn enter_directory(&mut self) -> Option<String> {
-        if let Some(file) = self.selected_file() {
-            if file.is_directory {
-                return Some(file.path.clone());
-            }
+        match self.selected_file() {
+            Some(file) if file.is_directory => Some(file.path.clone()),
+            _ => None,
         }
-        None
     }
 
     pub fn selected_is_directory(&self) -> bool {
@@ -153,6 +277,93 @@ impl App {
         self.mode = mode;
     }
 
+    pub fn tag_filters(&self) -> &Vec<String> {
+        &self.tag_filters
+    }
+
+    pub fn set_tag_filters(&mut self, filters: Vec<String>) {
+        self.tag_filters = filters;
+    }
+
+    pub fn name_glob(&self) -> &str {
+        &self.name_glob
+    }
+
+    pub fn set_name_glob(&mut self, glob: String) {
+        self.name_glob = glob;
+    }
+
+    pub fn entry_type(&self) -> EntryType {
+        self.entry_type
+    }
+
+    /// Switch between showing everything, only files and only directories.
+    pub fn cycle_entry_type(&mut self) {
+        self.entry_type = match self.entry_type {
+            EntryType::Any => EntryType::File,
+            EntryType::File => EntryType::Directory,
+            EntryType::Directory => EntryType::Any,
+        };
+    }
+
+    pub fn show_hidden(&self) -> bool {
+        self.show_hidden
+    }
+
+    pub fn toggle_hidden(&mut self) {
+        self.show_hidden = !self.show_hidden;
+    }
+
+    pub fn sort_by(&self) -> SortField {
+        self.sort_by
+    }
+
+    /// Switch between sorting by name, size and time.
+    pub fn cycle_sort(&mut self) {
+        self.sort_by = match self.sort_by {
+            SortField::Name => SortField::Size,
+            SortField::Size => SortField::Time,
+            SortField::Time => SortField::Name,
+        };
+    }
+
+    pub fn descending(&self) -> bool {
+        self.descending
+    }
+
+    pub fn toggle_descending(&mut self) {
+        self.descending = !self.descending;
+    }
+
+    pub fn show_preview(&self) -> bool {
+        self.show_preview
+    }
+
+    pub fn toggle_preview(&mut self) {
+        self.show_preview = !self.show_preview;
+    }
+
+    /// The preview currently shown, along with the path it belongs to.
+    pub fn preview(&self) -> Option<&(String, PreviewResponse)> {
+        self.preview.as_ref()
+    }
+
+    pub fn set_preview(&mut self, preview: Option<(String, PreviewResponse)>) {
+        self.preview = preview;
+    }
+
+    /// Path of the selected file if its preview needs to be fetched.
+    pub fn preview_needed(&self) -> Option<String> {
+        if !self.show_preview {
+            return None;
+        }
+        let file = self.selected_file().filter(|f| !f.is_directory)?;
+        match &self.preview {
+            Some((path, _)) if *path == file.path => None,
+            _ => Some(file.path.clone()),
+        }
+    }
+
     pub fn is_uploading(&self) -> bool {
         matches!(self.mode, AppMode::Uploading)
     }
@@ -10,32 +10,27 @@ use ratatui::{
     },
     prelude::{Backend, CrosstermBackend},
 };
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::path::Path;
 use std::process::Command;
 use std::time::Duration;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio_stream::StreamExt;
-use tokio_stream::wrappers::ReceiverStream;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
-use uuid;
 
 use crate::{
+    client::{self, Client, Transfer},
+    logging,
     config::Config,
-    fileservice::{
-        CreateDirectoryRequest, DeleteDirectoryRequest, DeleteRequest, DownloadRequest,
-        ListRequest, UploadChunk,
-        file_service_client::FileServiceClient,
-    },
+    sync,
+    fileservice::{ListRequest, PreviewRequest, SetTagsRequest},
     tui::{
-        app::{App, AppMode},
-        ui::ui,
+        app::{App, AppMode, PAGE_SIZE},
+        ui::{entry_type_label, format_tags, sort_label, ui},
     },
 };
-pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let config = crate::config::Config::load()?;
-    let auth_dir = crate::config::Config::get_auth_dir()?;
+pub async fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    // The terminal belongs to the UI, so logs always go to a file
+    let _log_guard = logging::init(&config, "tui-client", true)?;
+    let client = Client::connect(&config).await?;
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -43,28 +38,7 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     let mut app = App::new();
-    let client_cert = tokio::fs::read_to_string(auth_dir.join("client-cert.pem")).await?;
-    let client_key = tokio::fs::read_to_string(auth_dir.join("client-key.pem")).await?;
-    let client_identity = Identity::from_pem(client_cert, client_key);
-
-    let server_ca_cert = tokio::fs::read_to_string(auth_dir.join("ca-cert.pem")).await?;
-    let server_ca_cert = Certificate::from_pem(server_ca_cert);
-
-    let tls = ClientTlsConfig::new()
-        .domain_name("localhost")
-        .ca_certificate(server_ca_cert)
-        .identity(client_identity);
-
-    let server_url = format!("https://{}", config.server_connect_address);
-    let channel = Channel::from_shared(server_url)?
-        .tls_config(tls)?
-        .initial_connection_window_size(1024 * 1024)
-        .initial_stream_window_size(1024 * 1024)
-        .connect()
-        .await?;
-
-    let mut client = FileServiceClient::new(channel);
-    let res = run_app(&mut terminal, &mut app, &mut client, &config).await;
+    let res = run_app(&mut terminal, &mut app, &client, &config).await;
 
     disable_raw_mode()?;
     execute!(
@@ -80,26 +54,57 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
 async fn run_app<B: Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App,
-    client: &mut FileServiceClient<Channel>,
+    client: &Client,
     config: &Config,
 ) -> io::Result<()> {
-    // Initial refresh
+    // Initial refresh, going straight into the only volume if there is just one
     if let Err(e) = refresh_files(app, client).await {
-        app.set_status(format!("Error loading files: {}", e));
+        app.set_status(format!("Error loading volumes: {}", e));
+    } else if let [volume] = app.volumes() {
+        let name = volume.name.clone();
+        app.open_volume(name);
+        if let Err(e) = refresh_files(app, client).await {
+            app.set_status(format!("Error loading files: {}", e));
+        }
     }
 
     loop {
+        update_preview(app, client).await;
         terminal.draw(|f| ui(f, app))?;
         if let Event::Key(key) = event::read()? {
             if key.kind == KeyEventKind::Release {
                 continue;
             }
+            // Everything that works on files needs a volume to work in
+            if app.at_volume_list()
+                && matches!(
+                    key.code,
+                    KeyCode::Char('n' | 't' | 'f' | 'g' | 'T' | '.' | 's' | 'o' | 'X' | 'd' | 'D' | 'S' | 'U')
+                )
+            {
+                app.set_status("Open a volume first (l)".to_string());
+                continue;
+            }
             match key.code {
-                KeyCode::Char('j') => app.select_next(),
+                KeyCode::Char('j') => {
+                    // Large directories arrive a page at a time as the selection moves down
+                    if app.wants_more_files()
+                        && let Err(e) = load_more_files(app, client).await
+                    {
+                        app.set_status(format!("Error loading more files: {}", e));
+                    }
+                    app.select_next();
+                }
                 KeyCode::Char('k') => app.select_prev(),
                 KeyCode::Char('l') => {
-                    // Enter selected directory
-                    if let Some(dir_path) = app.enter_directory() {
+                    // Open the selected volume or enter the selected directory
+                    if let Some(volume) = app.selected_volume().map(|v| v.name.clone()) {
+                        app.open_volume(volume.clone());
+                        app.set_status(format!("Opening volume: {}", volume));
+                        if let Err(e) = refresh_files(app, client).await {
+                            app.set_status(format!("Error: {}", e));
+                        }
+                    } else if let Some(dir_path) = app.enter_directory() {
                         app.set_current_directory(dir_path.clone());
                         app.set_status(format!("Entering directory: {}", dir_path));
                         if let Err(e) = refresh_files(app, client).await {
@@ -117,8 +122,14 @@ async fn run_app<B: Backend>(
                         if let Err(e) = refresh_files(app, client).await {
                             app.set_status(format!("Error: {}", e));
                         }
+                    } else if !app.at_volume_list() {
+                        // Leaving a volume's root goes back to the list of volumes
+                        app.close_volume();
+                        if let Err(e) = refresh_files(app, client).await {
+                            app.set_status(format!("Error: {}", e));
+                        }
                     } else {
-                        app.set_status("Already at root directory".to_string());
+                        app.set_status("Already at the list of volumes".to_string());
                     }
                 }
                 KeyCode::Char('r') => {
@@ -134,7 +145,7 @@ async fn run_app<B: Backend>(
                     app.set_mode(AppMode::CreatingDirectory);
                     terminal.draw(|f| ui(f, app))?;
 
-                    prepare_terminal_for_input();
+                    prepare_terminal_for_input("Enter directory name:");
 
                     match prompt_for_directory_name().await {
                         Some(name) => {
@@ -144,7 +155,7 @@ async fn run_app<B: Backend>(
                             let current_dir = app.current_directory().to_string();
                             app.set_status(format!("Creating directory '{}'...", name));
 
-                            if let Err(e) = create_directory(client, &current_dir, &name).await {
+                            if let Err(e) = client.create_directory(app.volume(), &current_dir, &name).await {
                                 app.set_status(format!("Error creating directory: {}", e));
                             } else {
                                 app.set_status(format!("Created directory '{}'", name));
@@ -158,6 +169,142 @@ async fn run_app<B: Backend>(
                         }
                     }
                 }
+                KeyCode::Char('t') => {
+                    // Edit tags of the selected entry
+                    let Some(file) = app.selected_file() else {
+                        continue;
+                    };
+                    if file.filename == ".." {
+                        app.set_status("Cannot tag parent entry".to_string());
+                        continue;
+                    }
+                    let path = file.path.clone();
+                    let name = file.filename.clone();
+                    let current = format_tags(&file.tags, &file.attributes);
+
+                    app.set_mode(AppMode::EditingTags);
+                    terminal.draw(|f| ui(f, app))?;
+
+                    prepare_terminal_for_input(&format!(
+                        "Tags for {} (space separated, key=value for attributes, empty to clear)\nCurrent: {}",
+                        name,
+                        if current.is_empty() { "none" } else { &current }
+                    ));
+
+                    let input = prompt_for_line().await;
+                    restore_terminal_after_input();
+                    app.set_mode(AppMode::Normal);
+
+                    match input {
+                        Some(input) => {
+                            if let Err(e) = set_tags(client, app.volume(), &path, &input).await {
+                                app.set_status(format!("Error updating tags: {}", e));
+                            } else {
+                                let _ = refresh_files(app, client).await;
+                                app.set_status(format!("Updated tags for {}", name));
+                            }
+                        }
+                        None => app.set_status("Tag editing cancelled".to_string()),
+                    }
+                }
+                KeyCode::Char('f') => {
+                    // Filter the listing by tags
+                    app.set_mode(AppMode::FilteringByTag);
+                    terminal.draw(|f| ui(f, app))?;
+
+                    prepare_terminal_for_input(
+                        "Filter by tags (space separated, key=value for attributes, empty to clear):",
+                    );
+
+                    let input = prompt_for_line().await;
+                    restore_terminal_after_input();
+                    app.set_mode(AppMode::Normal);
+
+                    match input {
+                        Some(input) => {
+                            let filters: Vec<String> =
+                                input.split_whitespace().map(str::to_string).collect();
+                            let message = if filters.is_empty() {
+                                "Tag filter cleared".to_string()
+                            } else {
+                                format!("Filtering by: {}", filters.join(" "))
+                            };
+                            app.set_tag_filters(filters);
+                            if let Err(e) = refresh_files(app, client).await {
+                                app.set_status(format!("Error: {}", e));
+                            } else {
+                                app.set_status(message);
+                            }
+                        }
+                        None => app.set_status("Filter unchanged".to_string()),
+                    }
+                }
+                KeyCode::Char('g') => {
+                    // Filter the listing by file name
+                    app.set_mode(AppMode::FilteringByName);
+                    terminal.draw(|f| ui(f, app))?;
+
+                    prepare_terminal_for_input(
+                        "Filter file names by glob (e.g. *.rs, report-??.pdf, empty to clear):",
+                    );
+
+                    let input = prompt_for_line().await;
+                    restore_terminal_after_input();
+                    app.set_mode(AppMode::Normal);
+
+                    match input {
+                        Some(input) => {
+                            let glob = input.trim().to_string();
+                            let message = if glob.is_empty() {
+                                "Name filter cleared".to_string()
+                            } else {
+                                format!("Showing files matching {}", glob)
+                            };
+                            app.set_name_glob(glob);
+                            if let Err(e) = refresh_files(app, client).await {
+                                app.set_status(format!("Error: {}", e));
+                            } else {
+                                app.set_status(message);
+                            }
+                        }
+                        None => app.set_status("Filter unchanged".to_string()),
+                    }
+                }
+                KeyCode::Char('T') => {
+                    app.cycle_entry_type();
+                    let message = format!("Showing {}", entry_type_label(app.entry_type()));
+                    if let Err(e) = refresh_files(app, client).await {
+                        app.set_status(format!("Error: {}", e));
+                    } else {
+                        app.set_status(message);
+                    }
+                }
+                KeyCode::Char('.') => {
+                    app.toggle_hidden();
+                    let message = if app.show_hidden() {
+                        "Showing hidden files"
+                    } else {
+                        "Hiding hidden files"
+                    };
+                    if let Err(e) = refresh_files(app, client).await {
+                        app.set_status(format!("Error: {}", e));
+                    } else {
+                        app.set_status(message.to_string());
+                    }
+                }
+                KeyCode::Char('s') | KeyCode::Char('o') => {
+                    if key.code == KeyCode::Char('s') {
+                        app.cycle_sort();
+                    } else {
+                        app.toggle_descending();
+                    }
+                    let message = format!("Sorted by {}", sort_label(app.sort_by(), app.descending()));
+                    if let Err(e) = refresh_files(app, client).await {
+                        app.set_status(format!("Error: {}", e));
+                    } else {
+                        app.set_status(message);
+                    }
+                }
                 KeyCode::Char('X') => {
                     if let Some(file) = app.selected_file() {
                         let name = file.filename.clone();
@@ -173,7 +320,7 @@ async fn run_app<B: Backend>(
                             // For directories, use recursive delete
                             app.set_status(format!("Deleting directory {}...", name));
 
-                            if let Err(e) = delete_directory(client, &path, true).await {
+                            if let Err(e) = client.delete_directory(app.volume(), &path, true).await {
                                 app.set_status(format!("Error deleting directory: {}", e));
                             } else {
                                 app.set_status(format!("Deleted directory {}", name));
@@ -182,7 +329,7 @@ async fn run_app<B: Backend>(
                         } else {
                             // Existing file delete logic
                             app.set_status(format!("Deleting {}...", name));
-                            if let Err(e) = delete_file(client, &path).await {
+                            if let Err(e) = client.delete_file(app.volume(), &path).await {
                                 app.set_status(format!("Error: {}", e));
                             } else {
                                 app.set_status(format!("Deleted {}", name));
@@ -199,14 +346,94 @@ async fn run_app<B: Backend>(
                         }
 
                         let filename = file.filename.clone();
+                        let path = file.path.clone();
                         app.set_status(format!("Downloading {}...", filename));
-                        if let Err(e) = download_file(client, &filename, config).await {
+                        if let Err(e) = download_file(client, app.volume(), &path, &filename, config).await {
                             app.set_status(format!("Error downloading {}: {}", filename, e));
                         } else {
                             app.set_status(format!("Downloaded {}", filename));
                         }
                     }
                 }
+                KeyCode::Char('D') => {
+                    if let Some(file) = app.selected_file() {
+                        if file.is_directory {
+                            app.set_status("Cannot download directories".to_string());
+                            continue;
+                        }
+
+                        let filename = file.filename.clone();
+                        let path = file.path.clone();
+                        app.set_status(format!("Syncing {}...", filename));
+                        let local_path = Path::new(&config.download_directory).join(&filename);
+                        match client.delta_download_file(app.volume(), &path, &local_path).await {
+                            Ok(reused) => app.set_status(format!(
+                                "Synced {} ({} reused from local copy)",
+                                filename,
+                                crate::tui::ui::format_bytes(reused)
+                            )),
+                            Err(e) => {
+                                app.set_status(format!("Error syncing {}: {}", filename, e))
+                            }
+                        }
+                    }
+                }
+                KeyCode::Char('S') => {
+                    // Two-way sync of the current directory with a local folder
+                    let current_dir = app.current_directory().to_string();
+                    let default_local = Path::new(&config.download_directory).join(&current_dir);
+
+                    app.set_mode(AppMode::Syncing);
+                    terminal.draw(|f| ui(f, app))?;
+
+                    prepare_terminal_for_input(&format!(
+                        "Local folder to sync with /{} (empty for {}):",
+                        current_dir,
+                        default_local.display()
+                    ));
+
+                    let input = prompt_for_line().await;
+                    restore_terminal_after_input();
+                    app.set_mode(AppMode::Normal);
+
+                    let Some(input) = input else {
+                        app.set_status("Sync cancelled".to_string());
+                        continue;
+                    };
+                    let local = if input.is_empty() {
+                        default_local
+                    } else {
+                        Path::new(&input).to_path_buf()
+                    };
+
+                    app.set_status(format!("Syncing with {}...", local.display()));
+                    terminal.draw(|f| ui(f, app))?;
+
+                    if let Err(e) = tokio::fs::create_dir_all(&local).await {
+                        app.set_status(format!("Sync failed: {}", e));
+                        continue;
+                    }
+                    let result = match sync::plan(client, &local, app.volume(), &current_dir).await {
+                        Ok(plan) => sync::execute(client, plan).await,
+                        Err(e) => Err(e),
+                    };
+                    let _ = refresh_files(app, client).await;
+                    match result {
+                        Ok(report) if report.failed.is_empty() => app.set_status(format!(
+                            "Synced with {}: {} changes",
+                            local.display(),
+                            report.completed
+                        )),
+                        Ok(report) => app.set_status(format!(
+                            "Synced with {}: {} changes, {} failed ({})",
+                            local.display(),
+                            report.completed,
+                            report.failed.len(),
+                            report.failed[0].1
+                        )),
+                        Err(e) => app.set_status(format!("Sync failed: {}", e)),
+                    }
+                }
                 KeyCode::Char('U') => {
                     app.set_mode(AppMode::Uploading);
                     terminal.draw(|f| ui(f, app))?;
@@ -222,7 +449,35 @@ async fn run_app<B: Backend>(
                             app.set_status(format!("Uploading {}...", path));
 
                             let current_dir = app.current_directory().to_string();
-                            if let Err(e) = upload_selected_file(client, &path, &current_dir).await {
+
+                            // Send only the changes if the file already exists on the server
+                            let local_name = Path::new(&path)
+                                .file_name()
+                                .map(|n| n.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                            let existing = app
+                                .files()
+                                .iter()
+                                .find(|f| !f.is_directory && f.filename == local_name)
+                                .map(|f| f.path.clone());
+                            let local_path = Path::new(&path);
+                            let result = match existing {
+                                Some(remote_path) => {
+                                    client.delta_upload_file(local_path, app.volume(), &remote_path).await
+                                }
+                                None => {
+                                    let remote_path = match current_dir.as_str() {
+                                        "" => local_name,
+                                        dir => format!("{}/{}", dir, local_name),
+                                    };
+                                    client
+                                        .upload_file(local_path, app.volume(), &remote_path, &Transfer::default())
+                                        .await
+                                        .map(|_| ())
+                                }
+                            };
+
+                            if let Err(e) = result {
                                 app.set_status(format!("Upload failed: {}", e));
                             } else {
                                 app.set_status("Upload completed".to_string());
@@ -239,6 +494,9 @@ async fn run_app<B: Backend>(
                         }
                     }
                 }
+                KeyCode::Char('p') => {
+                    app.toggle_preview();
+                }
                 KeyCode::Char('q') => {
                     return Ok(());
                 }
@@ -248,61 +506,92 @@ async fn run_app<B: Backend>(
     }
 }
 
+/// Listing request for the current directory with the app's filters and sort order.
+fn list_request(app: &App, page_token: String) -> ListRequest {
+    ListRequest {
+        path: app.current_directory().to_string(),
+        tag_filters: app.tag_filters().clone(),
+        show_hidden: app.show_hidden(),
+        sort_by: app.sort_by().into(),
+        descending: app.descending(),
+        name_glob: app.name_glob().to_string(),
+        entry_type: app.entry_type().into(),
+        page_size: PAGE_SIZE,
+        page_token,
+        volume: app.volume().to_string(),
+    }
+}
+
+/// Reload what is on screen: the volume list at the top level, otherwise the
+/// first page of the current directory.
 async fn refresh_files(
     app: &mut App,
-    client: &mut FileServiceClient<Channel>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let response = client.list_files(ListRequest { path: app.current_directory().to_string() }).await?;
-    let inner = response.into_inner();
-    app.update_files(inner.files, inner.current_path);
+    client: &Client,
+) -> Result<(), client::Error> {
+    if app.at_volume_list() {
+        app.update_volumes(client.volumes().await?);
+    } else {
+        app.update_files(client.list(list_request(app, String::new())).await?);
+    }
     app.clear_status();
     Ok(())
 }
 
-async fn delete_file(
-    client: &mut FileServiceClient<Channel>,
-    filename: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    client
-        .delete_file(DeleteRequest {
-            file_name: filename.to_string(),
-        })
-        .await?;
+/// Fetch the next page of the current directory, if there is one.
+async fn load_more_files(
+    app: &mut App,
+    client: &Client,
+) -> Result<(), client::Error> {
+    let token = app.next_page_token().to_string();
+    if token.is_empty() {
+        return Ok(());
+    }
+    app.append_files(client.list(list_request(app, token)).await?);
     Ok(())
 }
 
-async fn download_file(
-    client: &mut FileServiceClient<Channel>,
-    filename: &str,
-    config: &Config,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut stream = client
-        .download(DownloadRequest {
-            file_name: filename.to_string(),
-        })
-        .await?
-        .into_inner();
-
-    let full_path = format!("{}/{}", config.download_directory, filename);
-    let path = Path::new(&full_path);
-    let file_name = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or(filename);
-
-    // Check if file already exists
-    if Path::new(file_name).exists() {
-        return Err(format!("File '{}' already exists", file_name).into());
+/// Fetch a preview for the selected file if it changed since the last one.
+async fn update_preview(app: &mut App, client: &Client) {
+    let is_file = app.selected_file().map(|f| !f.is_directory).unwrap_or(false);
+    if !is_file {
+        app.set_preview(None);
+        return;
     }
+    let Some(path) = app.preview_needed() else {
+        return;
+    };
 
-    let mut file = File::create(path).await?;
+    let preview = client
+        .rpc()
+        .preview(PreviewRequest {
+            path: path.clone(),
+            max_bytes: 16 * 1024,
+            max_lines: 500,
+            volume: app.volume().to_string(),
+        })
+        .await
+        .ok()
+        .map(|response| (path, response.into_inner()));
+    app.set_preview(preview);
+}
 
-    while let Some(chunk) = stream.next().await {
-        let data = chunk?.data;
-        file.write_all(&data).await?;
+/// Download a file into the download directory, refusing to replace a file
+/// that is already there.
+async fn download_file(
+    client: &Client,
+    volume: &str,
+    path: &str,
+    filename: &str,
+    config: &Config,
+) -> Result<(), client::Error> {
+    let local_path = Path::new(&config.download_directory).join(filename);
+    if local_path.exists() {
+        let message = format!("File '{}' already exists", local_path.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
     }
-
-    file.flush().await?;
+    client
+        .download_file(volume, path, &local_path, &Transfer::default())
+        .await?;
     Ok(())
 }
 
@@ -329,10 +618,10 @@ fn restore_terminal_after_file_selection() {
 }
 
 async fn select_file_with_picker() -> Option<String> {
-    let result = if Command::new("zenity").output().is_ok() {
+    if Command::new("zenity").output().is_ok() {
         // Try zenity (GTK)
         Command::new("zenity")
-            .args(&["--file-selection", "--title=Select file to upload"])
+            .args(["--file-selection", "--title=Select file to upload"])
             .output()
             .map(|output| {
                 if output.status.success() {
@@ -345,7 +634,7 @@ async fn select_file_with_picker() -> Option<String> {
     } else if Command::new("kdialog").output().is_ok() {
         // Try kdialog (KDE)
         Command::new("kdialog")
-            .args(&["--getopenfilename", "."])
+            .args(["--getopenfilename", "."])
             .output()
             .map(|output| {
                 if output.status.success() {
@@ -367,125 +656,15 @@ async fn select_file_with_picker() -> Option<String> {
         } else {
             None
         }
-    };
-
-    result
-}
-
-async fn upload_selected_file(
-    client: &mut FileServiceClient<Channel>,
-    file_path: &str,
-    target_directory: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let path = Path::new(file_path);
-    if !path.exists() {
-        return Err("File does not exist".into());
-    }
-
-    if !path.is_file() {
-        return Err("Path is a directory, not a file".into());
     }
-    let mut file = File::open(path).await?;
-    let filename = path
-        .file_name()
-        .unwrap()
-        .to_str()
-        .ok_or("Filename contains invalid UTF-8")?
-        .to_string();
-    let upload_id = uuid::Uuid::new_v4().to_string();
-    let target_dir = target_directory.to_string();
-
-    let (tx, rx) = tokio::sync::mpsc::channel(256);
-
-    tokio::spawn(async move {
-        let mut buffer = vec![0u8; 1024 * 1024];
-        let mut chunk_index: u64 = 0;
-        loop {
-            match file.read(&mut buffer).await {
-                Ok(0) => break, // EOF
-                Ok(n) => {
-                    let chunk = UploadChunk {
-                        upload_id: upload_id.clone(),
-                        filename: filename.to_string(),
-                        chunk_index,
-                        data: buffer[..n].to_vec(),
-                        target_directory: if chunk_index == 0 {
-                            target_dir.clone()
-                        } else {
-                            String::new()
-                        },
-                    };
-
-                    if tx.send(chunk).await.is_err() {
-                        break;
-                    }
-                    chunk_index += 1;
-                }
-                Err(_) => break,
-            }
-        }
-    });
-
-    // Send stream to server
-    let stream = ReceiverStream::new(rx);
-    let network_start = std::time::Instant::now();
-    let response = client.upload(tonic::Request::new(stream)).await?;
-    let network_elapsed = network_start.elapsed();
-    let result = response.into_inner();
-    //let total_elapsed = start.elapsed();
-    let log_msg = format!(
-        "Network transfer: {:?} ({:.2} MB/s)",
-        network_elapsed,
-        (result.size as f64 / 1024.0 / 1024.0) / network_elapsed.as_secs_f64()
-    );
-    Command::new("sh")
-        .arg("-c")
-        .arg(format!("echo '{}' > log.txt", log_msg))
-        .output()
-        .ok();
-    /*println!(
-        "Total upload: {:?} ({:.2} MB/s)",
-        total_elapsed,
-        (result.size as f64 / 1024.0 / 1024.0) / total_elapsed.as_secs_f64()
-    );*/
-
-    Ok(())
-}
-
-async fn delete_directory(
-    client: &mut FileServiceClient<Channel>,
-    path: &str,
-    recursive: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    client
-        .delete_directory(DeleteDirectoryRequest {
-            path: path.to_string(),
-            recursive,
-        })
-        .await?;
-    Ok(())
-}
-
-async fn create_directory(
-    client: &mut FileServiceClient<Channel>,
-    parent_path: &str,
-    name: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    client
-        .create_directory(CreateDirectoryRequest {
-            path: parent_path.to_string(),
-            name: name.to_string(),
-        })
-        .await?;
-    Ok(())
 }
 
-fn prepare_terminal_for_input() {
+fn prepare_terminal_for_input(prompt: &str) {
     execute!(io::stdout(), DisableMouseCapture).ok();
     execute!(io::stdout(), LeaveAlternateScreen).ok();
     disable_raw_mode().ok();
     execute!(io::stdout(), Clear(ClearType::All)).ok();
-    println!("Enter directory name:");
+    println!("{}", prompt);
     io::stdout().flush().ok();
 }
 
@@ -511,3 +690,40 @@ async fn prompt_for_directory_name() -> Option<String> {
         Some(name)
     }
 }
+
+/// Read a single line of input, allowing it to be empty.
+async fn prompt_for_line() -> Option<String> {
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).ok()?;
+    Some(input.trim().to_string())
+}
+
+async fn set_tags(
+    client: &Client,
+    volume: &str,
+    path: &str,
+    input: &str,
+) -> Result<(), client::Error> {
+    let mut tags = Vec::new();
+    let mut attributes = HashMap::new();
+    for token in input.split_whitespace() {
+        match token.split_once('=') {
+            Some((key, value)) => {
+                attributes.insert(key.to_string(), value.to_string());
+            }
+            None => tags.push(token.to_string()),
+        }
+    }
+
+    client
+        .rpc()
+        .set_tags(SetTagsRequest {
+            path: path.to_string(),
+            tags,
+            attributes,
+            replace: true,
+            volume: volume.to_string(),
+        })
+        .await?;
+    Ok(())
+}
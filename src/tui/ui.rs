@@ -7,7 +7,11 @@ use ratatui::{
     widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
 };
 
-use crate::{fileservice::FileInfo, tui::app::{App, AppMode}};
+use crate::{
+    fileservice::{EntryType, FileInfo, PreviewResponse, SortField, VolumeInfo},
+    preview::hex_dump,
+    tui::app::{App, AppMode},
+};
 
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -57,21 +61,46 @@ pub fn ui(frame: &mut Frame, app: &App) {
         return;
     }
 
-    // If we're in creating directory mode, show a full-screen message
-    if matches!(app.mode(), AppMode::CreatingDirectory) {
+    // Modes that read input from the terminal show a full-screen message
+    let prompt = match app.mode() {
+        AppMode::CreatingDirectory => Some((
+            "Create Directory",
+            "Creating Directory",
+            "Enter directory name in terminal.",
+        )),
+        AppMode::EditingTags => Some((
+            "Edit Tags",
+            "Editing Tags",
+            "Enter tags and key=value attributes in terminal.",
+        )),
+        AppMode::FilteringByTag => Some((
+            "Filter by Tag",
+            "Filtering by Tag",
+            "Enter tags to filter by in terminal.",
+        )),
+        AppMode::FilteringByName => Some((
+            "Filter by Name",
+            "Filtering by Name",
+            "Enter a glob to filter file names in terminal.",
+        )),
+        AppMode::Syncing => Some((
+            "Sync Folder",
+            "Syncing Folder",
+            "Enter local folder to sync in terminal.",
+        )),
+        _ => None,
+    };
+    if let Some((title, heading, hint)) = prompt {
         let area = centered_rect(60, 20, frame.area());
         let text = vec![
             Line::from(""),
-            Line::from(Span::styled(
-                "Creating Directory",
-                Style::default().fg(Color::Cyan).bold(),
-            )),
+            Line::from(Span::styled(heading, Style::default().fg(Color::Cyan).bold())),
             Line::from(""),
-            Line::from("Enter directory name in terminal."),
+            Line::from(hint),
             Line::from(""),
         ];
         let paragraph = Paragraph::new(text)
-            .block(Block::default().borders(Borders::ALL).title("Create Directory"))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .style(Style::default().fg(Color::White))
             .wrap(Wrap { trim: true })
             .alignment(Alignment::Center);
@@ -85,11 +114,29 @@ pub fn ui(frame: &mut Frame, app: &App) {
         .constraints([Constraint::Min(1), Constraint::Length(3)])
         .split(frame.area());
 
+    // Split the browser into the file list and, if enabled, a preview pane
+    let list_area = if app.show_preview() {
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(chunks[0]);
+        render_preview(frame, app, panes[1]);
+        panes[0]
+    } else {
+        chunks[0]
+    };
+
+    if app.at_volume_list() {
+        render_volumes(frame, app, list_area);
+        render_status(frame, app, chunks[1]);
+        return;
+    }
+
     // Calculate column widths based on terminal width
-    let list_width = chunks[0].width;
+    let list_width = list_area.width;
     // Reserve space for arrows and borders: 2 for arrows/bullets + 2 for borders + 4 for spacing = 8
-    // Size column gets 10 chars, timestamp gets 19 chars (YYYY-MM-DD HH:MM:SS)
-    let filename_width = (list_width as usize).saturating_sub(8 + 10 + 19);
+    // Size column gets 10 chars, timestamp gets 19 chars (YYYY-MM-DD HH:MM:SS), tags get 20 + 1 spacing
+    let filename_width = (list_width as usize).saturating_sub(8 + 10 + 19 + 21);
     let filename_width = filename_width.max(10); // Minimum filename width
 
     let items: Vec<ListItem> = app
@@ -98,6 +145,7 @@ pub fn ui(frame: &mut Frame, app: &App) {
         .enumerate()
         .map(|(i, file_info)| {
             let (filename, size, upload_time, is_dir) = format_file_info(file_info);
+            let tags = format_tags(&file_info.tags, &file_info.attributes);
 
             // Truncate filename if too long and add ellipsis
             let display_filename = if filename.len() > filename_width {
@@ -115,6 +163,13 @@ pub fn ui(frame: &mut Frame, app: &App) {
             // Fixed width for timestamp (19 chars for YYYY-MM-DD HH:MM:SS)
             let padded_time = format!("{:<19}", upload_time);
 
+            // Fixed width for tags (20 chars), truncated like filenames
+            let padded_tags = if tags.chars().count() > 20 {
+                format!("{}...", tags.chars().take(17).collect::<String>())
+            } else {
+                format!("{:<20}", tags)
+            };
+
             // Different style for directories
             let base_color = if is_dir { Color::Cyan } else { Color::White };
 
@@ -126,6 +181,8 @@ pub fn ui(frame: &mut Frame, app: &App) {
                     Span::styled(padded_size, Style::default().fg(Color::Yellow)),
                     Span::raw(" "),
                     Span::styled(padded_time, Style::default().fg(Color::Yellow)),
+                    Span::raw(" "),
+                    Span::styled(padded_tags, Style::default().fg(Color::Yellow)),
                 ])
             } else {
                 Line::from(vec![
@@ -135,6 +192,8 @@ pub fn ui(frame: &mut Frame, app: &App) {
                     Span::styled(padded_size, Style::default().fg(Color::DarkGray)),
                     Span::raw(" "),
                     Span::styled(padded_time, Style::default().fg(Color::DarkGray)),
+                    Span::raw(" "),
+                    Span::styled(padded_tags, Style::default().fg(Color::Magenta)),
                 ])
             };
             ListItem::new(line)
@@ -147,10 +206,32 @@ pub fn ui(frame: &mut Frame, app: &App) {
     } else {
         format!("/{}", app.current_directory())
     };
-    let title = format!(" File Server Browser - {} ", display_path);
+    let mut title = format!(
+        " File Server Browser - {}:{} [{}] ",
+        app.volume(),
+        display_path,
+        sort_label(app.sort_by(), app.descending())
+    );
+    if !app.tag_filters().is_empty() {
+        title.push_str(&format!("[tags: {}] ", app.tag_filters().join(" ")));
+    }
+    if !app.name_glob().is_empty() {
+        title.push_str(&format!("[name: {}] ", app.name_glob()));
+    }
+    if app.entry_type() != EntryType::Any {
+        title.push_str(&format!("[{}] ", entry_type_label(app.entry_type())));
+    }
+    if app.show_hidden() {
+        title.push_str("[hidden] ");
+    }
+    // Show how much of a large directory has been fetched so far
+    if !app.next_page_token().is_empty() {
+        let loaded = app.files().iter().filter(|f| f.filename != "..").count();
+        title.push_str(&format!("[{} of {}] ", loaded, app.total_entries()));
+    }
 
     // Updated help text with new commands
-    let help_text = " r: refresh | l: enter dir | h: parent | n: new dir | d: download | X: delete | U: upload | q: quit ";
+    let help_text = " r: refresh | l: enter dir | h: parent/volumes | n: new dir | t: tags | f: filter | g: name filter | T: type | s: sort | o: order | .: hidden | p: preview | d: download | D: sync file | S: sync dir | X: delete | U: upload | q: quit ";
 
     let list = List::new(items)
         .block(
@@ -161,10 +242,18 @@ pub fn ui(frame: &mut Frame, app: &App) {
         )
         .highlight_style(Style::default());
 
-    frame.render_widget(list, chunks[0]);
-    // Status bar
+    frame.render_widget(list, list_area);
+    render_status(frame, app, chunks[1]);
+}
+
+fn render_status(frame: &mut Frame, app: &App, area: Rect) {
     let status_text = if let Some(msg) = app.status_message() {
         msg.clone()
+    } else if app.at_volume_list() {
+        match app.selected_volume() {
+            Some(v) => format!("Volume: {} (Press 'l' to open)", v.name),
+            None => "No volumes. Press r to refresh".to_string(),
+        }
     } else if app.files().is_empty() {
         "No files. Press r to refresh".to_string()
     } else {
@@ -182,7 +271,124 @@ pub fn ui(frame: &mut Frame, app: &App) {
         .block(Block::default().borders(Borders::ALL).title("Status"))
         .wrap(Wrap { trim: true });
 
-    frame.render_widget(status, chunks[1]);
+    frame.render_widget(status, area);
+}
+
+/// The list of volumes shown before one is opened.
+fn render_volumes(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .volumes()
+        .iter()
+        .enumerate()
+        .map(|(i, volume)| {
+            let text = format!(
+                "{:<24} {:>10} free  {:<16} {}",
+                volume.name,
+                format_bytes(volume.free_bytes),
+                volume_quota(volume),
+                volume_flags(volume)
+            );
+            let line = if i == app.selected_index() {
+                Line::from(vec![
+                    Span::styled("→ ", Style::default().fg(Color::Yellow)),
+                    Span::styled(text, Style::default().fg(Color::Yellow).bold()),
+                ])
+            } else {
+                Line::from(vec![Span::raw("  "), Span::styled(text, Style::default().fg(Color::Cyan))])
+            };
+            ListItem::new(line)
+        })
+        .collect();
+
+    let help_text = " r: refresh | l: open volume | q: quit ";
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" File Server Browser - Volumes ")
+            .title_bottom(help_text),
+    );
+    frame.render_widget(list, area);
+}
+
+fn volume_quota(volume: &VolumeInfo) -> String {
+    match volume.quota_bytes {
+        0 => "no quota".to_string(),
+        quota => format!("quota {}", format_bytes(quota)),
+    }
+}
+
+fn volume_flags(volume: &VolumeInfo) -> String {
+    let mut flags = Vec::new();
+    if volume.is_default {
+        flags.push("default");
+    }
+    if volume.read_only {
+        flags.push("read-only");
+    }
+    flags.join(", ")
+}
+
+fn render_preview(frame: &mut Frame, app: &App, area: Rect) {
+    let selected_is_file = app.selected_file().map(|f| !f.is_directory).unwrap_or(false);
+    let preview = app.preview().filter(|_| selected_is_file);
+
+    let Some((_, preview)) = preview else {
+        let paragraph = Paragraph::new("No preview available")
+            .style(Style::default().fg(Color::DarkGray))
+            .block(Block::default().borders(Borders::ALL).title(" Preview "));
+        frame.render_widget(paragraph, area);
+        return;
+    };
+
+    let title = match &preview.image {
+        Some(image) => format!(
+            " Preview - {} {}x{} ({}) ",
+            preview.mime_type,
+            image.width,
+            image.height,
+            format_bytes(preview.total_size)
+        ),
+        None => format!(
+            " Preview - {} ({}) ",
+            preview.mime_type,
+            format_bytes(preview.total_size)
+        ),
+    };
+
+    let mut lines = preview_lines(preview, area.width.saturating_sub(2) as usize);
+    if preview.truncated {
+        lines.push(Line::from(Span::styled(
+            "... (truncated)",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_widget(paragraph, area);
+}
+
+/// Text is shown with line numbers, anything else as a hex dump.
+fn preview_lines(preview: &PreviewResponse, width: usize) -> Vec<Line<'static>> {
+    if preview.is_text {
+        let text = String::from_utf8_lossy(&preview.data);
+        text.lines()
+            .enumerate()
+            .map(|(i, line)| {
+                Line::from(vec![
+                    Span::styled(format!("{:>4} ", i + 1), Style::default().fg(Color::DarkGray)),
+                    Span::raw(line.replace('\t', "    ")),
+                ])
+            })
+            .collect()
+    } else {
+        // A row needs 10 chars of offset, 4 per byte and 4 for separators
+        let bytes_per_row = if width >= 10 + 16 * 4 + 4 { 16 } else { 8 };
+        hex_dump(&preview.data, bytes_per_row)
+            .into_iter()
+            .map(Line::from)
+            .collect()
+    }
 }
 
 fn format_file_info(file_info: &FileInfo) -> (String, String, String, bool) {
@@ -219,7 +425,25 @@ fn format_file_info(file_info: &FileInfo) -> (String, String, String, bool) {
     (filename, size, upload_time, is_dir)
 }
 
-fn format_bytes(bytes: u64) -> String {
+pub(crate) fn sort_label(sort_by: SortField, descending: bool) -> String {
+    let field = match sort_by {
+        SortField::Name => "name",
+        SortField::Size => "size",
+        SortField::Time => "time",
+    };
+    let arrow = if descending { "↓" } else { "↑" };
+    format!("{} {}", field, arrow)
+}
+
+pub(crate) fn entry_type_label(entry_type: EntryType) -> &'static str {
+    match entry_type {
+        EntryType::Any => "files and directories",
+        EntryType::File => "files only",
+        EntryType::Directory => "directories only",
+    }
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
     let mut size = bytes as f64;
     let mut unit_index = 0;
@@ -235,3 +459,17 @@ fn format_bytes(bytes: u64) -> String {
         format!("{:.1} {}", size, UNITS[unit_index])
     }
 }
+
+/// Render tags and attributes as the space separated form used by the tag editor.
+pub(crate) fn format_tags(tags: &[String], attributes: &std::collections::HashMap<String, String>) -> String {
+    let mut attributes: Vec<String> = attributes
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    attributes.sort();
+    tags.iter()
+        .cloned()
+        .chain(attributes)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,44 @@
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tokio::net::UnixListener;
+
+/// Permissions of the socket file: the server's user and group may connect.
+const SOCKET_MODE: u32 = 0o660;
+
+/// The socket file a server listens on. It is removed when this is dropped.
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Listen on a Unix domain socket at `path`, replacing a socket file left
+/// behind by a server that did not shut down cleanly.
+pub fn bind(path: &Path) -> Result<(UnixListener, SocketFile), Box<dyn std::error::Error>> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()).into());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(format!("{} is in use by another server", path.display()).into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+    let file = SocketFile {
+        path: path.to_path_buf(),
+    };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(SOCKET_MODE))?;
+    Ok((listener, file))
+}
+
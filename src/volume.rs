@@ -0,0 +1,548 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, HomesConfig, VolumeConfig};
+use crate::error::FileError;
+use crate::fileservice::VolumeInfo;
+use crate::metadata::{METADATA_FILE, MetadataStore};
+use crate::sandbox::{PathError, RelativePath, Sandbox, SymlinkPolicy};
+use crate::staging::{STAGING_DIR, Staging};
+
+/// Name of the volume served from `upload_directory` when no volumes are configured.
+pub const DEFAULT_VOLUME: &str = "default";
+
+/// How long a measured usage is trusted before the volume is walked again,
+/// to pick up changes made on disk behind the server's back.
+const USAGE_REFRESH: Duration = Duration::from_secs(300);
+
+/// A named storage root with its own tags, staging area and options.
+pub struct Volume {
+    name: String,
+    root: String,
+    options: RwLock<VolumeOptions>,
+    metadata: MetadataStore,
+    staging: Arc<Staging>,
+    /// Bytes stored when last measured, kept up to date as the server changes
+    /// the volume, so quota checks don't walk it on every upload.
+    usage: Mutex<Option<Usage>>,
+}
+
+struct Usage {
+    bytes: u64,
+    measured: Instant,
+}
+
+/// The parts of a volume a configuration reload can change.
+struct VolumeOptions {
+    read_only: bool,
+    quota_bytes: Option<u64>,
+    sandbox: Sandbox,
+}
+
+impl Volume {
+    fn open(config: &VolumeConfig, symlinks: SymlinkPolicy) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = Path::new(&config.path);
+        std::fs::create_dir_all(root)?;
+        let metadata = MetadataStore::load(root)?;
+        let (staging, leftovers) = Staging::open(root)?;
+        let sandbox = Sandbox::new(root, symlinks)?;
+        if leftovers.files > 0 {
+            tracing::warn!(
+                volume = %config.name,
+                files = leftovers.files,
+                bytes = leftovers.bytes,
+                "removed unfinished uploads left over from a previous run"
+            );
+        }
+
+        Ok(Volume {
+            name: config.name.clone(),
+            root: config.path.clone(),
+            options: RwLock::new(VolumeOptions {
+                read_only: config.read_only,
+                quota_bytes: config.quota_bytes,
+                sandbox,
+            }),
+            metadata,
+            staging,
+            usage: Mutex::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    pub fn sandbox(&self) -> Sandbox {
+        self.options.read().unwrap().sandbox.clone()
+    }
+
+    /// Apply reloaded options. The path stays the same.
+    fn reconfigure(&self, config: &VolumeConfig, symlinks: SymlinkPolicy) -> Result<(), Box<dyn std::error::Error>> {
+        let sandbox = Sandbox::new(Path::new(&self.root), symlinks)?;
+        *self.options.write().unwrap() = VolumeOptions {
+            read_only: config.read_only,
+            quota_bytes: config.quota_bytes,
+            sandbox,
+        };
+        // Measure afresh should a quota be turned on
+        *self.usage.lock().unwrap() = None;
+        Ok(())
+    }
+
+    pub fn metadata(&self) -> &MetadataStore {
+        &self.metadata
+    }
+
+    pub fn staging(&self) -> &Arc<Staging> {
+        &self.staging
+    }
+
+    /// Refuse a change to a read-only volume.
+    pub fn check_writable(&self) -> Result<(), FileError> {
+        if self.options.read().unwrap().read_only {
+            return Err(FileError::ReadOnly {
+                volume: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Bytes that may still be stored before the quota is reached, or `None`
+    /// if the volume has no quota.
+    pub async fn quota_remaining(&self) -> Result<Option<u64>, FileError> {
+        let Some(quota) = self.options.read().unwrap().quota_bytes else {
+            return Ok(None);
+        };
+        Ok(Some(quota.saturating_sub(self.used_bytes().await?)))
+    }
+
+    /// Bytes stored, walking the volume only when the last measurement is
+    /// missing or old.
+    async fn used_bytes(&self) -> Result<u64, FileError> {
+        if let Some(usage) = &*self.usage.lock().unwrap()
+            && usage.measured.elapsed() < USAGE_REFRESH
+        {
+            return Ok(usage.bytes);
+        }
+        let root = PathBuf::from(&self.root);
+        let measured = Instant::now();
+        let (bytes, _) = tokio::task::spawn_blocking(move || storage_usage(&root))
+            .await
+            .map_err(|e| FileError::Internal(e.to_string()))?;
+        *self.usage.lock().unwrap() = Some(Usage { bytes, measured });
+        Ok(bytes)
+    }
+
+    /// Account for a change the server made: `added` bytes written and
+    /// `removed` bytes deleted or replaced.
+    pub fn record_usage(&self, added: u64, removed: u64) {
+        if let Some(usage) = &mut *self.usage.lock().unwrap() {
+            usage.bytes = usage.bytes.saturating_add(added).saturating_sub(removed);
+        }
+    }
+
+    /// Forget the measured usage after a change too large to account for,
+    /// such as deleting a directory tree.
+    pub fn forget_usage(&self) {
+        *self.usage.lock().unwrap() = None;
+    }
+
+    /// Create the directory at `path` if needed and confine a sandbox to it.
+    fn mount(&self, name: &str, path: &RelativePath) -> Result<Mount, FileError> {
+        let sandbox = self.sandbox();
+        let full = sandbox.resolve(path).map_err(|e| path_error(path, e))?;
+        std::fs::create_dir_all(&full)
+            .and_then(|_| Sandbox::new(&full, sandbox.symlinks()))
+            .map(|sandbox| Mount {
+                name: name.to_string(),
+                prefix: path.clone(),
+                sandbox,
+            })
+            .map_err(|e| FileError::io("open", path, e))
+    }
+
+    pub fn info(&self, is_default: bool) -> VolumeInfo {
+        let options = self.options.read().unwrap();
+        VolumeInfo {
+            name: self.name.clone(),
+            read_only: options.read_only,
+            quota_bytes: options.quota_bytes.unwrap_or(0),
+            free_bytes: fs4::available_space(&self.root).unwrap_or(0),
+            is_default,
+        }
+    }
+}
+
+/// Every volume the server serves. The first one is the default.
+pub struct Volumes {
+    volumes: Vec<Arc<Volume>>,
+    homes: RwLock<Option<Arc<Homes>>>,
+    /// Whether callers without an identity are served, which only plaintext
+    /// development servers do.
+    anonymous: bool,
+}
+
+/// Where home and shared directories live, with paths checked up front.
+struct Homes {
+    directory: RelativePath,
+    shared_directory: RelativePath,
+    config: HomesConfig,
+}
+
+impl Homes {
+    fn new(config: &HomesConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let directory = RelativePath::parse(&config.directory)?;
+        let shared_directory = RelativePath::parse(&config.shared_directory)?;
+        if directory.is_root() || shared_directory.is_root() || directory == shared_directory {
+            return Err("homes.directory and homes.shared_directory must be two different subdirectories".into());
+        }
+        for shared in &config.shared {
+            if shared_directory.join(&shared.name).is_err() {
+                return Err(format!("Invalid shared directory name '{}'", shared.name).into());
+            }
+        }
+        Ok(Homes {
+            directory,
+            shared_directory,
+            config: config.clone(),
+        })
+    }
+}
+
+impl Volumes {
+    pub fn open(config: &Config) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        let mut volumes: Vec<Arc<Volume>> = Vec::new();
+        for volume in config.volumes() {
+            if RelativePath::root().join(&volume.name).is_err() {
+                return Err(format!("Invalid volume name '{}'", volume.name).into());
+            }
+            if volumes.iter().any(|v| v.name == volume.name) {
+                return Err(format!("Volume '{}' is configured twice", volume.name).into());
+            }
+            volumes.push(Arc::new(Volume::open(&volume, config.symlink_policy)?));
+        }
+        let homes = config.homes.as_ref().map(Homes::new).transpose()?.map(Arc::new);
+        Ok(Arc::new(Volumes {
+            volumes,
+            homes: RwLock::new(homes),
+            anonymous: config.plaintext,
+        }))
+    }
+
+    /// Apply a reloaded configuration: volume options, the symlink policy and
+    /// home directories. Volumes cannot be added, removed or moved while the
+    /// server runs, so such changes are returned to be reported instead.
+    pub fn reconfigure(&self, config: &Config) -> Vec<String> {
+        let mut ignored = Vec::new();
+        let configured = config.volumes();
+        for volume_config in &configured {
+            match self.volumes.iter().find(|v| v.name == volume_config.name) {
+                None => ignored.push(format!("volume '{}' was added", volume_config.name)),
+                Some(volume) if volume.root != volume_config.path => {
+                    ignored.push(format!("volume '{}' changed path", volume.name))
+                }
+                Some(volume) => {
+                    if let Err(e) = volume.reconfigure(volume_config, config.symlink_policy) {
+                        ignored.push(format!("volume '{}': {}", volume.name, e));
+                    }
+                }
+            }
+        }
+        for volume in &self.volumes {
+            if !configured.iter().any(|v| v.name == volume.name) {
+                ignored.push(format!("volume '{}' was removed", volume.name));
+            }
+        }
+
+        match config.homes.as_ref().map(Homes::new).transpose() {
+            Ok(homes) => *self.homes.write().unwrap() = homes.map(Arc::new),
+            Err(e) => ignored.push(format!("homes: {}", e)),
+        }
+        ignored
+    }
+
+    /// Whether a client is listed in `homes.admins`.
+    pub fn is_admin(&self, client: Option<&str>) -> bool {
+        let Some(client) = client else {
+            return false;
+        };
+        self.homes
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|homes| homes.config.admins.iter().any(|admin| admin == client))
+    }
+
+    /// The part of a volume a client may use: its home directory and the
+    /// shared directories it is a member of, or the whole volume for admins
+    /// and when home directories are not configured. The home directory is
+    /// created the first time it is needed. Callers without an identity are
+    /// refused, except by plaintext servers without home directories.
+    pub fn scope(&self, name: &str, client: Option<&str>) -> Result<Scope, FileError> {
+        let volume = self.get(name)?;
+        let homes = self.homes.read().unwrap().clone();
+        let Some(client) = client else {
+            if self.anonymous && homes.is_none() {
+                return Ok(Scope::whole(volume));
+            }
+            return Err(FileError::Unauthenticated);
+        };
+        let Some(homes) = homes else {
+            return Ok(Scope::whole(volume));
+        };
+        if homes.config.admins.iter().any(|admin| admin == client) {
+            return Ok(Scope::whole(volume));
+        }
+
+        let home_path = homes.directory.join(client).map_err(|_| FileError::PermissionDenied {
+            path: None,
+            message: format!("Certificate name '{}' cannot be used as a home directory", client),
+        })?;
+        let home = volume.mount(client, &home_path)?;
+        let mut shared = Vec::new();
+        for directory in &homes.config.shared {
+            if !directory.members.iter().any(|member| member == client) {
+                continue;
+            }
+            // Validated when the configuration was loaded
+            let Ok(path) = homes.shared_directory.join(&directory.name) else {
+                continue;
+            };
+            shared.push(volume.mount(&directory.name, &path)?);
+        }
+
+        Ok(Scope {
+            root: volume.sandbox(),
+            volume,
+            home: Some(home),
+            shared,
+        })
+    }
+
+    /// Look up a volume by the name in a request; empty means the default.
+    pub fn get(&self, name: &str) -> Result<Arc<Volume>, FileError> {
+        if name.is_empty() {
+            return Ok(self.volumes[0].clone());
+        }
+        self.volumes
+            .iter()
+            .find(|v| v.name == name)
+            .cloned()
+            .ok_or_else(|| FileError::NoSuchVolume {
+                volume: name.to_string(),
+            })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Volume>> {
+        self.volumes.iter()
+    }
+
+    pub fn infos(&self) -> Vec<VolumeInfo> {
+        self.volumes
+            .iter()
+            .enumerate()
+            .map(|(i, volume)| volume.info(i == 0))
+            .collect()
+    }
+}
+
+/// A directory of the volume that appears at a client's root.
+struct Mount {
+    name: String,
+    /// Location inside the volume.
+    prefix: RelativePath,
+    sandbox: Sandbox,
+}
+
+/// A volume as one client sees it. Paths in requests and responses are
+/// relative to the client's root, which is its home directory when it has
+/// one; shared directories appear as extra entries at the top of it.
+pub struct Scope {
+    volume: Arc<Volume>,
+    /// The whole volume, used when there is no home directory.
+    root: Sandbox,
+    home: Option<Mount>,
+    shared: Vec<Mount>,
+}
+
+impl Scope {
+    fn whole(volume: Arc<Volume>) -> Self {
+        Scope {
+            root: volume.sandbox(),
+            volume,
+            home: None,
+            shared: Vec::new(),
+        }
+    }
+
+    pub fn volume(&self) -> &Arc<Volume> {
+        &self.volume
+    }
+
+    pub fn metadata(&self) -> &MetadataStore {
+        self.volume.metadata()
+    }
+
+    /// The mount a client path falls in and the rest of the path inside it.
+    /// `None` means the whole volume.
+    fn locate(&self, path: &RelativePath) -> (Option<&Mount>, RelativePath) {
+        let Some(home) = &self.home else {
+            return (None, path.clone());
+        };
+        if let Some((first, rest)) = path.split_first()
+            && let Some(shared) = self.shared.iter().find(|m| m.name == first)
+        {
+            return (Some(shared), rest);
+        }
+        (Some(home), path.clone())
+    }
+
+    /// Validate a path sent by a client. Every RPC goes through this before
+    /// touching the filesystem.
+    pub fn parse_path(&self, raw: &str) -> Result<RelativePath, FileError> {
+        RelativePath::parse(raw).map_err(|e| path_error(raw, e))
+    }
+
+    /// Validate a single name inside `parent`, such as an uploaded file.
+    pub fn child_path(&self, parent: &RelativePath, name: &str) -> Result<RelativePath, FileError> {
+        parent.join(name).map_err(|e| path_error(format!("{}/{}", parent, name), e))
+    }
+
+    /// Where a client path is inside the volume. Tags are stored under this.
+    pub fn key(&self, path: &RelativePath) -> String {
+        match self.locate(path) {
+            (Some(mount), rest) => mount.prefix.join_path(&rest).to_string(),
+            (None, path) => path.to_string(),
+        }
+    }
+
+    /// The sandbox a client path is resolved in.
+    pub fn sandbox(&self, path: &RelativePath) -> &Sandbox {
+        match self.locate(path) {
+            (Some(mount), _) => &mount.sandbox,
+            (None, _) => &self.root,
+        }
+    }
+
+    /// Map a validated client path to its location in storage, keeping the
+    /// server's own files out of reach and making sure symlinks do not lead
+    /// outside the client's part of the volume.
+    pub fn resolve_path(&self, path: &RelativePath) -> Result<String, FileError> {
+        let (mount, rest) = self.locate(path);
+        let full = match mount {
+            Some(mount) => mount.sandbox.resolve(&rest),
+            None => {
+                check_reserved(&rest)?;
+                self.root.resolve(&rest)
+            }
+        }
+        .map_err(|e| path_error(path, e))?;
+        Ok(full.to_string_lossy().to_string())
+    }
+
+    /// Whether a path is the client's root or a shared directory, neither of
+    /// which can be deleted or moved.
+    pub fn is_top(&self, path: &RelativePath) -> bool {
+        path.is_root() || (path.parent().is_some_and(|p| p.is_root()) && self.is_shared(path.as_str()))
+    }
+
+    fn is_shared(&self, name: &str) -> bool {
+        self.shared.iter().any(|m| m.name == name)
+    }
+
+    /// The shared directories at the client's root, by name and location.
+    pub fn shared(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.shared.iter().map(|m| (m.name.as_str(), m.sandbox.root()))
+    }
+
+    /// Whether an entry found in directory `dir` is kept out of listings: the
+    /// server's own files, and names a shared directory stands in for.
+    pub fn hides(&self, dir: &RelativePath, name: &str) -> bool {
+        dir.is_root() && if self.home.is_some() { self.is_shared(name) } else { is_reserved(name) }
+    }
+
+    /// Metadata for an entry found in directory `dir` while listing or
+    /// searching, or `None` if it is a symlink the sandbox does not allow.
+    /// Allowed symlinks report what they point to.
+    pub async fn entry_metadata(
+        &self,
+        dir: &RelativePath,
+        entry: &tokio::fs::DirEntry,
+    ) -> Option<std::fs::Metadata> {
+        let metadata = entry.metadata().await.ok()?;
+        if !metadata.file_type().is_symlink() {
+            return Some(metadata);
+        }
+        let sandbox = self.sandbox(dir);
+        if sandbox.symlinks() == SymlinkPolicy::Refuse || !sandbox.contains(&entry.path()) {
+            return None;
+        }
+        tokio::fs::metadata(entry.path()).await.ok()
+    }
+}
+
+/// Names at a volume root that hold the server's own bookkeeping.
+pub fn is_reserved(name: &str) -> bool {
+    name == STAGING_DIR || name.starts_with(METADATA_FILE)
+}
+
+/// Keep clients away from the server's own bookkeeping at the volume root.
+fn check_reserved(path: &RelativePath) -> Result<(), FileError> {
+    match path.components().next() {
+        Some(first) if is_reserved(first) => Err(FileError::PermissionDenied {
+            path: Some(path.to_string()),
+            message: "Path is reserved by the server".to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn path_error(path: impl std::fmt::Display, error: PathError) -> FileError {
+    FileError::InvalidPath {
+        path: path.to_string(),
+        error,
+    }
+}
+
+/// Sum the size and count of the files in a volume, leaving out the
+/// server's own bookkeeping at its root.
+pub fn storage_usage(root: &Path) -> (u64, u64) {
+    usage(root, true)
+}
+
+/// Sum the size and count of every regular file below `dir`.
+pub fn tree_usage(dir: &Path) -> (u64, u64) {
+    usage(dir, false)
+}
+
+fn usage(root: &Path, skip_reserved: bool) -> (u64, u64) {
+    let mut bytes = 0;
+    let mut files = 0;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if skip_reserved && dir == root && is_reserved(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                bytes += metadata.len();
+                files += 1;
+            }
+        }
+    }
+    (bytes, files)
+}
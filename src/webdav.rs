@@ -0,0 +1,634 @@
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{Extensions, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use bytes::{Buf, Bytes};
+use dav_server::davpath::DavPath;
+use dav_server::fakels::FakeLs;
+use dav_server::fs::{
+    DavDirEntry, DavFile, DavMetaData, FsError, FsFuture, FsResult, FsStream, GuardedFileSystem,
+    OpenOptions, ReadDirMeta,
+};
+use dav_server::DavHandler;
+use std::convert::Infallible;
+use std::io::SeekFrom;
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Code;
+use tower::Service;
+use tower::util::BoxCloneSyncService;
+use tracing::Instrument;
+
+use crate::fileservice::file_service_client::FileServiceClient;
+use crate::fileservice::{
+    CopyRequest, CreateDirectoryRequest, DeleteDirectoryRequest, DeleteRequest, DownloadChunk,
+    DownloadRequest, FileInfo, ListRequest, ListVolumesRequest, MoveRequest, StatRequest,
+    UploadChunk, UploadResponse,
+};
+use crate::gateway::{LocalFileService, forward};
+use crate::identity;
+
+/// A WebDAV server over the file service, for mounting it in file managers.
+/// The root lists the volumes; each volume is a directory below it. Every
+/// operation is a call to `service` made as the WebDAV client, so paths,
+/// homes, read-only volumes, quotas and tags work as they do over gRPC.
+/// Locks are accepted but not enforced, which is all most clients need.
+pub fn router<S>(service: S) -> Router
+where
+    S: Service<
+            http::Request<tonic::body::Body>,
+            Response = http::Response<tonic::body::Body>,
+            Error = Infallible,
+        > + Clone
+        + Send
+        + Sync
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let store = Store {
+        service: BoxCloneSyncService::new(service),
+    };
+    let handler = DavHandler::builder()
+        .filesystem(Box::new(store))
+        .locksystem(FakeLs::new())
+        .build_handler();
+    Router::new().fallback(handle).with_state(handler)
+}
+
+/// Hand a request to the WebDAV handler with the caller's connection and
+/// token as its credentials. Callers without an identity are asked for one,
+/// which file managers answer with a token as the password.
+async fn handle(State(handler): State<DavHandler<Extensions>>, request: Request) -> Response {
+    let caller = request.extensions().clone();
+    if identity::peer_identity(&caller).is_none() {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"grpc-files\"")],
+        )
+            .into_response();
+    }
+    handler.handle_guarded(request, caller).await.map(Body::new)
+}
+
+#[derive(Clone)]
+struct Store {
+    service: LocalFileService,
+}
+
+impl Store {
+    fn client(&self) -> FileServiceClient<LocalFileService> {
+        FileServiceClient::new(self.service.clone())
+    }
+
+    async fn stat(&self, caller: &Extensions, volume: &str, path: &str) -> FsResult<FileInfo> {
+        let request = StatRequest {
+            path: path.to_string(),
+            volume: volume.to_string(),
+        };
+        let response = self.client().stat(forward(caller, request)).await.map_err(fs_error)?;
+        response.into_inner().info.ok_or(FsError::GeneralFailure)
+    }
+
+    /// Remove a file at `path` so a move or copy can take its place, as
+    /// WebDAV expects when the client asked to overwrite it.
+    async fn replace(&self, caller: &Extensions, volume: &str, path: &str) -> FsResult<()> {
+        match self.stat(caller, volume, path).await {
+            Ok(info) if !info.is_directory => {
+                let request = DeleteRequest {
+                    file_name: path.to_string(),
+                    volume: volume.to_string(),
+                };
+                self.client().delete_file(forward(caller, request)).await.map_err(fs_error)?;
+                Ok(())
+            }
+            Ok(_) | Err(FsError::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Split a WebDAV path into its volume and the path inside the volume. The
+/// root, where the volumes are listed, has neither.
+fn locate(path: &DavPath) -> FsResult<Option<(String, String)>> {
+    let path = std::str::from_utf8(path.as_bytes()).map_err(|_| FsError::NotFound)?;
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return Ok(None);
+    }
+    let (volume, rest) = path.split_once('/').unwrap_or((path, ""));
+    Ok(Some((volume.to_string(), rest.to_string())))
+}
+
+/// Like [`locate`], for operations that cannot apply to the root.
+fn locate_inside(path: &DavPath) -> FsResult<(String, String)> {
+    locate(path)?.ok_or(FsError::Forbidden)
+}
+
+fn split_name(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// WebDAV only has a handful of outcomes, so the message is logged here
+/// before it is lost.
+fn fs_error(status: tonic::Status) -> FsError {
+    tracing::debug!(code = ?status.code(), error = %status.message(), "webdav operation failed");
+    match status.code() {
+        Code::NotFound => FsError::NotFound,
+        Code::AlreadyExists => FsError::Exists,
+        Code::PermissionDenied
+        | Code::Unauthenticated
+        | Code::InvalidArgument
+        | Code::FailedPrecondition => FsError::Forbidden,
+        Code::ResourceExhausted => FsError::InsufficientStorage,
+        Code::Unimplemented => FsError::NotImplemented,
+        _ => FsError::GeneralFailure,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Meta {
+    len: u64,
+    modified: SystemTime,
+    is_dir: bool,
+}
+
+impl Meta {
+    fn directory() -> Self {
+        Meta {
+            len: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            is_dir: true,
+        }
+    }
+}
+
+impl From<&FileInfo> for Meta {
+    fn from(info: &FileInfo) -> Self {
+        Meta {
+            len: info.size,
+            modified: info
+                .upload_time
+                .and_then(|time| SystemTime::try_from(time).ok())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            is_dir: info.is_directory,
+        }
+    }
+}
+
+impl DavMetaData for Meta {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.modified)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+}
+
+struct Entry {
+    name: String,
+    meta: Meta,
+}
+
+impl DavDirEntry for Entry {
+    fn name(&self) -> Vec<u8> {
+        self.name.as_bytes().to_vec()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        Box::pin(async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) })
+    }
+}
+
+impl GuardedFileSystem<Extensions> for Store {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        caller: &'a Extensions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        Box::pin(async move {
+            let (volume, path) = locate_inside(path)?;
+            if options.write {
+                let (directory, name) = split_name(&path);
+                // Report a missing directory or a directory in the way now
+                // rather than after the body has been sent
+                if !self.stat(caller, &volume, directory).await?.is_directory {
+                    return Err(FsError::Forbidden);
+                }
+                match self.stat(caller, &volume, &path).await {
+                    Ok(info) if info.is_directory => return Err(FsError::Forbidden),
+                    Ok(_) if options.create_new => return Err(FsError::Exists),
+                    Ok(_) | Err(FsError::NotFound) => {}
+                    Err(e) => return Err(e),
+                }
+                let writer = Writer::start(self, caller, &volume, directory, name, options.size).await?;
+                return Ok(Box::new(writer) as Box<dyn DavFile>);
+            }
+
+            let info = self.stat(caller, &volume, &path).await?;
+            if info.is_directory {
+                return Err(FsError::Forbidden);
+            }
+            Ok(Box::new(Reader {
+                client: self.client(),
+                caller: caller.clone(),
+                meta: Meta::from(&info),
+                volume,
+                path,
+                position: 0,
+                stream: None,
+                buffer: Bytes::new(),
+            }) as Box<dyn DavFile>)
+        })
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        _meta: ReadDirMeta,
+        caller: &'a Extensions,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        Box::pin(async move {
+            let entries: Vec<FsResult<Box<dyn DavDirEntry>>> = match locate(path)? {
+                None => {
+                    let response = self
+                        .client()
+                        .list_volumes(forward(caller, ListVolumesRequest {}))
+                        .await
+                        .map_err(fs_error)?;
+                    response
+                        .into_inner()
+                        .volumes
+                        .into_iter()
+                        .map(|volume| {
+                            Ok(Box::new(Entry {
+                                name: volume.name,
+                                meta: Meta::directory(),
+                            }) as Box<dyn DavDirEntry>)
+                        })
+                        .collect()
+                }
+                Some((volume, path)) => {
+                    let request = ListRequest {
+                        path,
+                        show_hidden: true,
+                        volume,
+                        ..Default::default()
+                    };
+                    let response = self
+                        .client()
+                        .list_files(forward(caller, request))
+                        .await
+                        .map_err(fs_error)?;
+                    response
+                        .into_inner()
+                        .files
+                        .iter()
+                        .map(|info| {
+                            Ok(Box::new(Entry {
+                                name: info.filename.clone(),
+                                meta: Meta::from(info),
+                            }) as Box<dyn DavDirEntry>)
+                        })
+                        .collect()
+                }
+            };
+            Ok(Box::pin(tokio_stream::iter(entries)) as FsStream<Box<dyn DavDirEntry>>)
+        })
+    }
+
+    fn metadata<'a>(
+        &'a self,
+        path: &'a DavPath,
+        caller: &'a Extensions,
+    ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        Box::pin(async move {
+            let meta = match locate(path)? {
+                None => Meta::directory(),
+                Some((volume, path)) => Meta::from(&self.stat(caller, &volume, &path).await?),
+            };
+            Ok(Box::new(meta) as Box<dyn DavMetaData>)
+        })
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath, caller: &'a Extensions) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            let (volume, path) = locate_inside(path)?;
+            if path.is_empty() {
+                // Volumes come from the server's configuration
+                return Err(FsError::Forbidden);
+            }
+            let (parent, name) = split_name(&path);
+            let request = CreateDirectoryRequest {
+                path: parent.to_string(),
+                name: name.to_string(),
+                volume,
+            };
+            self.client().create_directory(forward(caller, request)).await.map_err(fs_error)?;
+            Ok(())
+        })
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath, caller: &'a Extensions) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            let (volume, path) = locate_inside(path)?;
+            let request = DeleteDirectoryRequest {
+                path,
+                recursive: false,
+                volume,
+            };
+            self.client().delete_directory(forward(caller, request)).await.map_err(fs_error)?;
+            Ok(())
+        })
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath, caller: &'a Extensions) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            let (volume, path) = locate_inside(path)?;
+            let request = DeleteRequest {
+                file_name: path,
+                volume,
+            };
+            self.client().delete_file(forward(caller, request)).await.map_err(fs_error)?;
+            Ok(())
+        })
+    }
+
+    fn rename<'a>(
+        &'a self,
+        from: &'a DavPath,
+        to: &'a DavPath,
+        caller: &'a Extensions,
+    ) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            let (volume, source) = locate_inside(from)?;
+            let (to_volume, destination) = locate_inside(to)?;
+            if volume != to_volume {
+                return Err(FsError::Forbidden);
+            }
+            self.replace(caller, &volume, &destination).await?;
+            let request = MoveRequest {
+                source,
+                destination,
+                volume,
+            };
+            self.client().move_file(forward(caller, request)).await.map_err(fs_error)?;
+            Ok(())
+        })
+    }
+
+    fn copy<'a>(
+        &'a self,
+        from: &'a DavPath,
+        to: &'a DavPath,
+        caller: &'a Extensions,
+    ) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            let (volume, source) = locate_inside(from)?;
+            let (to_volume, destination) = locate_inside(to)?;
+            if volume != to_volume {
+                return Err(FsError::Forbidden);
+            }
+            self.replace(caller, &volume, &destination).await?;
+            let request = CopyRequest {
+                source,
+                destination,
+                volume,
+            };
+            self.client().copy_file(forward(caller, request)).await.map_err(fs_error)?;
+            Ok(())
+        })
+    }
+}
+
+/// A file being read, fetched with the Download RPC from wherever the client
+/// last sought to.
+struct Reader {
+    client: FileServiceClient<LocalFileService>,
+    caller: Extensions,
+    meta: Meta,
+    volume: String,
+    path: String,
+    position: u64,
+    // Behind a mutex only because the stream is not `Sync`
+    stream: Option<tokio::sync::Mutex<tonic::Streaming<DownloadChunk>>>,
+    buffer: Bytes,
+}
+
+impl std::fmt::Debug for Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reader")
+            .field("volume", &self.volume)
+            .field("path", &self.path)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl DavFile for Reader {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = self.meta.clone();
+        Box::pin(async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) })
+    }
+
+    fn write_buf(&mut self, _buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        Box::pin(async { Err(FsError::Forbidden) })
+    }
+
+    fn write_bytes(&mut self, _buf: Bytes) -> FsFuture<'_, ()> {
+        Box::pin(async { Err(FsError::Forbidden) })
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        Box::pin(async move {
+            while self.buffer.is_empty() {
+                if self.stream.is_none() {
+                    if self.position >= self.meta.len {
+                        return Ok(Bytes::new());
+                    }
+                    let request = DownloadRequest {
+                        file_name: self.path.clone(),
+                        volume: self.volume.clone(),
+                        offset: self.position,
+                        length: 0,
+                    };
+                    let response = self
+                        .client
+                        .download(forward(&self.caller, request))
+                        .await
+                        .map_err(fs_error)?;
+                    self.stream = Some(tokio::sync::Mutex::new(response.into_inner()));
+                }
+                let Some(stream) = self.stream.as_mut() else {
+                    return Ok(Bytes::new());
+                };
+                match stream.get_mut().message().await.map_err(fs_error)? {
+                    Some(chunk) => self.buffer = Bytes::from(chunk.data),
+                    None => return Ok(Bytes::new()),
+                }
+            }
+            let data = self.buffer.split_to(count.min(self.buffer.len()));
+            self.position += data.len() as u64;
+            Ok(data)
+        })
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        Box::pin(async move {
+            let position = match pos {
+                SeekFrom::Start(offset) => Some(offset),
+                SeekFrom::End(offset) => self.meta.len.checked_add_signed(offset),
+                SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            }
+            .ok_or(FsError::GeneralFailure)?;
+            if position != self.position {
+                // Start a new download from the new position on the next read
+                self.stream = None;
+                self.buffer = Bytes::new();
+                self.position = position;
+            }
+            Ok(position)
+        })
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A file being written, streamed into an Upload RPC as the body arrives.
+/// The upload only completes on flush; a writer dropped before that cancels
+/// it, so an interrupted PUT leaves the old file in place.
+#[derive(Debug)]
+struct Writer {
+    upload_id: String,
+    chunk_index: u64,
+    written: u64,
+    tx: Option<tokio::sync::mpsc::Sender<UploadChunk>>,
+    upload: Option<JoinHandle<Result<UploadResponse, tonic::Status>>>,
+}
+
+impl Writer {
+    async fn start(
+        store: &Store,
+        caller: &Extensions,
+        volume: &str,
+        directory: &str,
+        name: &str,
+        size: Option<u64>,
+    ) -> FsResult<Self> {
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let first = UploadChunk {
+            upload_id: upload_id.clone(),
+            filename: name.to_string(),
+            chunk_index: 0,
+            data: Vec::new(),
+            target_directory: directory.to_string(),
+            file_size: size.unwrap_or(0),
+            volume: volume.to_string(),
+        };
+        tx.send(first).await.map_err(|_| FsError::GeneralFailure)?;
+
+        let mut client = store.client();
+        let request = forward(caller, ReceiverStream::new(rx));
+        let upload = tokio::spawn(
+            async move { client.upload(request).await.map(|response| response.into_inner()) }
+                .in_current_span(),
+        );
+        Ok(Writer {
+            upload_id,
+            chunk_index: 0,
+            written: 0,
+            tx: Some(tx),
+            upload: Some(upload),
+        })
+    }
+
+    /// End the upload and wait for the server to take the file.
+    async fn finish(&mut self) -> FsResult<()> {
+        self.tx = None;
+        let Some(upload) = self.upload.take() else {
+            return Ok(());
+        };
+        upload
+            .await
+            .map_err(|_| FsError::GeneralFailure)?
+            .map_err(fs_error)?;
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            upload.abort();
+        }
+    }
+}
+
+impl DavFile for Writer {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = Meta {
+            len: self.written,
+            modified: SystemTime::now(),
+            is_dir: false,
+        };
+        Box::pin(async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) })
+    }
+
+    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        let bytes = buf.copy_to_bytes(buf.remaining());
+        self.write_bytes(bytes)
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            let Some(tx) = &self.tx else {
+                return Err(FsError::GeneralFailure);
+            };
+            self.chunk_index += 1;
+            self.written += buf.len() as u64;
+            let chunk = UploadChunk {
+                upload_id: self.upload_id.clone(),
+                chunk_index: self.chunk_index,
+                data: buf.to_vec(),
+                ..Default::default()
+            };
+            if tx.send(chunk).await.is_err() {
+                // The upload ended early; report why
+                self.finish().await?;
+                return Err(FsError::GeneralFailure);
+            }
+            Ok(())
+        })
+    }
+
+    fn read_bytes(&mut self, _count: usize) -> FsFuture<'_, Bytes> {
+        Box::pin(async { Err(FsError::Forbidden) })
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        // Uploads are written front to back, so partial PUTs are not supported
+        let written = self.written;
+        Box::pin(async move {
+            match pos {
+                SeekFrom::Start(offset) if offset == written => Ok(written),
+                SeekFrom::Current(0) => Ok(written),
+                _ => Err(FsError::NotImplemented),
+            }
+        })
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        Box::pin(self.finish())
+    }
+}
@@ -0,0 +1,39 @@
+use grpc_files::config::{Config, ConfigArgs};
+
+fn load(flags: &[&str]) -> Config {
+    let dir = std::env::temp_dir().join(format!("grpc-files-config-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("config.json");
+    std::fs::write(&file, "{}").unwrap();
+
+    let mut args = vec!["--config".to_string(), file.to_string_lossy().into_owned()];
+    args.extend(flags.iter().map(|flag| flag.to_string()));
+    let (args, rest) = ConfigArgs::parse(args).unwrap();
+    assert!(rest.is_empty());
+    let config = Config::load_with(&args).unwrap().config;
+    std::fs::remove_dir_all(&dir).unwrap();
+    config
+}
+
+#[test]
+fn text_settings_keep_values_that_look_like_json() {
+    let config = load(&["--tls-server-name", "123", "--tls-ca-file", "null", "--log-directory=true"]);
+    assert_eq!(config.tls_server_name, "123");
+    assert_eq!(config.tls_ca_file.as_deref(), Some("null"));
+    assert_eq!(config.log_directory.as_deref(), Some("true"));
+}
+
+#[test]
+fn other_settings_are_read_as_json() {
+    let config = load(&[
+        "--max-concurrent-transfers",
+        "4",
+        "--plaintext",
+        "true",
+        "--web-allowed-origins",
+        r#"["https://files.example.com"]"#,
+    ]);
+    assert_eq!(config.max_concurrent_transfers, Some(4));
+    assert!(config.plaintext);
+    assert_eq!(config.web_allowed_origins, ["https://files.example.com"]);
+}
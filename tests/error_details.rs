@@ -0,0 +1,82 @@
+use grpc_files::error::{FileError, Reason, ServerError};
+use grpc_files::sandbox::PathError;
+use std::collections::HashMap;
+use std::time::Duration;
+use tonic::Code;
+use tonic_types::{ErrorDetails, StatusExt};
+
+fn round_trip(error: FileError) -> ServerError {
+    ServerError::from(&tonic::Status::from(error))
+}
+
+#[test]
+fn carries_reason_and_path() {
+    let error = round_trip(FileError::not_found("docs/readme.txt"));
+    assert_eq!(error.code, Code::NotFound);
+    assert_eq!(error.reason, Some(Reason::NotFound));
+    assert_eq!(error.path.as_deref(), Some("docs/readme.txt"));
+    assert_eq!(error.to_string(), "'docs/readme.txt' does not exist");
+}
+
+#[test]
+fn carries_volume() {
+    let error = round_trip(FileError::ReadOnly { volume: "archive".to_string() });
+    assert_eq!(error.code, Code::PermissionDenied);
+    assert_eq!(error.reason, Some(Reason::ReadOnly));
+    assert_eq!(error.volume.as_deref(), Some("archive"));
+    assert_eq!(error.path, None);
+}
+
+#[test]
+fn carries_retry_after() {
+    let error = round_trip(FileError::RateLimited { retry_after: Duration::from_millis(1500) });
+    assert_eq!(error.code, Code::ResourceExhausted);
+    assert_eq!(error.reason, Some(Reason::RateLimited));
+    assert_eq!(error.retry_after, Some(Duration::from_millis(1500)));
+    assert_eq!(error.to_string(), "Request rate limit exceeded, try again in 2s");
+}
+
+#[test]
+fn escaping_paths_are_refused() {
+    let error = round_trip(FileError::InvalidPath {
+        path: "link/etc".to_string(),
+        error: PathError::Escapes,
+    });
+    assert_eq!(error.code, Code::PermissionDenied);
+    assert_eq!(error.reason, Some(Reason::PermissionDenied));
+}
+
+#[test]
+fn callers_without_identity_are_unauthenticated() {
+    let error = round_trip(FileError::Unauthenticated);
+    assert_eq!(error.code, Code::Unauthenticated);
+    assert_eq!(error.reason, Some(Reason::Unauthenticated));
+}
+
+#[test]
+fn storage_failures_name_the_client_path() {
+    let source = std::io::Error::other("disk on fire");
+    let error = round_trip(FileError::io("read", "notes.txt", source));
+    assert_eq!(error.code, Code::Internal);
+    assert_eq!(error.reason, Some(Reason::Internal));
+    assert_eq!(error.path.as_deref(), Some("notes.txt"));
+    assert_eq!(error.to_string(), "Failed to read 'notes.txt': disk on fire");
+}
+
+#[test]
+fn ignores_other_domains() {
+    let details = ErrorDetails::with_error_info("NOT_FOUND", "example.com", HashMap::new());
+    let status = tonic::Status::with_error_details(Code::NotFound, "gone", details);
+    let error = ServerError::from(&status);
+    assert_eq!(error.code, Code::NotFound);
+    assert_eq!(error.reason, None);
+    assert_eq!(error.message, "gone");
+}
+
+#[test]
+fn reasons_round_trip() {
+    for reason in [Reason::NotFound, Reason::DirectoryNotEmpty, Reason::QuotaExceeded, Reason::Busy] {
+        assert_eq!(Reason::parse(reason.as_str()), Some(reason));
+    }
+    assert_eq!(Reason::parse("SOMETHING_ELSE"), None);
+}
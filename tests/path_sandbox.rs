@@ -0,0 +1,169 @@
+use grpc_files::sandbox::{PathError, RelativePath, Sandbox, SymlinkPolicy};
+use std::path::PathBuf;
+
+fn parse_err(raw: &str) -> PathError {
+    RelativePath::parse(raw).expect_err(raw)
+}
+
+/// A fresh storage root with an `outside` sibling directory next to it.
+fn storage() -> (PathBuf, PathBuf) {
+    let base = std::env::temp_dir().join(format!("grpc-files-sandbox-{}", uuid::Uuid::new_v4()));
+    let root = base.join("root");
+    let outside = base.join("outside");
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(root.join("docs/readme.txt"), "inside").unwrap();
+    std::fs::write(outside.join("secret.txt"), "outside").unwrap();
+    (root, outside)
+}
+
+#[test]
+fn rejects_parent_components() {
+    assert_eq!(parse_err(".."), PathError::ParentComponent);
+    assert_eq!(parse_err("a/../../b"), PathError::ParentComponent);
+    assert_eq!(parse_err("a/.."), PathError::ParentComponent);
+}
+
+#[test]
+fn rejects_absolute_paths() {
+    assert_eq!(parse_err("/etc/passwd"), PathError::Absolute);
+    assert_eq!(parse_err("C:\\Windows"), PathError::Absolute);
+    assert_eq!(parse_err("c:/Windows"), PathError::Absolute);
+}
+
+#[test]
+fn rejects_dangerous_characters() {
+    assert_eq!(parse_err("a\0b"), PathError::Nul);
+    assert_eq!(parse_err("a\\..\\b"), PathError::InvalidCharacter('\\'));
+    assert_eq!(parse_err("line\nbreak"), PathError::InvalidCharacter('\n'));
+    assert_eq!(parse_err("invoice\u{202E}fdp.exe"), PathError::InvalidCharacter('\u{202E}'));
+    assert_eq!(parse_err("zero\u{200B}width"), PathError::InvalidCharacter('\u{200B}'));
+    assert_eq!(parse_err("..\u{FF0F}etc"), PathError::InvalidCharacter('\u{FF0F}'));
+    assert_eq!(parse_err("a\u{2215}b"), PathError::InvalidCharacter('\u{2215}'));
+}
+
+#[test]
+fn rejects_overlong_names_and_paths() {
+    assert_eq!(parse_err(&"a".repeat(256)), PathError::TooLong);
+    assert!(RelativePath::parse(&"a".repeat(255)).is_ok());
+    let deep = vec!["abcdefgh"; 600].join("/");
+    assert_eq!(parse_err(&deep), PathError::TooLong);
+}
+
+#[test]
+fn normalises_separators_and_dots() {
+    assert_eq!(RelativePath::parse("a//b/./c/").unwrap().as_str(), "a/b/c");
+    assert_eq!(RelativePath::parse("./a").unwrap().as_str(), "a");
+    assert!(RelativePath::parse("").unwrap().is_root());
+    assert!(RelativePath::parse("./").unwrap().is_root());
+    // Only `..` itself is special; longer runs of dots are ordinary names
+    assert_eq!(RelativePath::parse("...").unwrap().as_str(), "...");
+    assert_eq!(RelativePath::parse("a..b").unwrap().as_str(), "a..b");
+    assert_eq!(RelativePath::parse("héllo wörld").unwrap().as_str(), "héllo wörld");
+}
+
+#[test]
+fn join_accepts_only_single_names() {
+    let dir = RelativePath::parse("docs").unwrap();
+    assert_eq!(dir.join("a.txt").unwrap().as_str(), "docs/a.txt");
+    assert_eq!(RelativePath::root().join("a.txt").unwrap().as_str(), "a.txt");
+    assert_eq!(dir.join("a/b"), Err(PathError::NotAName));
+    assert_eq!(dir.join(".."), Err(PathError::NotAName));
+    assert_eq!(dir.join("."), Err(PathError::NotAName));
+    assert_eq!(dir.join(""), Err(PathError::NotAName));
+    assert_eq!(dir.join("a\u{202E}b"), Err(PathError::InvalidCharacter('\u{202E}')));
+}
+
+#[test]
+fn parent_and_starts_with_work_on_components() {
+    let path = RelativePath::parse("a/b/c").unwrap();
+    assert_eq!(path.parent().unwrap().as_str(), "a/b");
+    assert_eq!(path.file_name(), Some("c"));
+    assert!(RelativePath::parse("a").unwrap().parent().unwrap().is_root());
+    assert!(RelativePath::root().parent().is_none());
+
+    let docs = RelativePath::parse("docs").unwrap();
+    assert!(RelativePath::parse("docs/a").unwrap().starts_with(&docs));
+    assert!(docs.starts_with(&docs));
+    assert!(!RelativePath::parse("docs2/a").unwrap().starts_with(&docs));
+    assert!(docs.starts_with(&RelativePath::root()));
+}
+
+#[test]
+fn join_path_and_split_first() {
+    let home: RelativePath = "homes/alice".parse().unwrap();
+    let path: RelativePath = "docs/a.txt".parse().unwrap();
+    assert_eq!(home.join_path(&path).as_str(), "homes/alice/docs/a.txt");
+    assert_eq!(home.join_path(&RelativePath::root()), home);
+    assert_eq!(RelativePath::root().join_path(&path), path);
+
+    let (first, rest) = path.split_first().unwrap();
+    assert_eq!((first, rest.as_str()), ("docs", "a.txt"));
+    let docs = RelativePath::parse("docs").unwrap();
+    let (first, rest) = docs.split_first().unwrap();
+    assert_eq!(first, "docs");
+    assert!(rest.is_root());
+    assert!(RelativePath::root().split_first().is_none());
+}
+
+#[test]
+fn resolves_inside_the_root() {
+    let (root, _) = storage();
+    let sandbox = Sandbox::new(&root, SymlinkPolicy::Follow).unwrap();
+
+    let existing = sandbox.resolve(&"docs/readme.txt".parse().unwrap()).unwrap();
+    assert_eq!(std::fs::read_to_string(existing).unwrap(), "inside");
+    let missing = sandbox.resolve(&"docs/new/file.txt".parse().unwrap()).unwrap();
+    assert!(missing.starts_with(sandbox.root()));
+    assert_eq!(sandbox.resolve(&RelativePath::root()).unwrap(), sandbox.root());
+}
+
+#[cfg(unix)]
+#[test]
+fn refuses_symlinks_that_escape() {
+    use std::os::unix::fs::symlink;
+
+    let (root, outside) = storage();
+    symlink(&outside, root.join("escape")).unwrap();
+    symlink(outside.join("secret.txt"), root.join("docs/secret.txt")).unwrap();
+    symlink(root.join("gone"), root.join("dangling")).unwrap();
+    let sandbox = Sandbox::new(&root, SymlinkPolicy::Follow).unwrap();
+
+    for raw in ["escape", "escape/secret.txt", "escape/new.txt", "docs/secret.txt", "dangling"] {
+        let path: RelativePath = raw.parse().unwrap();
+        assert_eq!(sandbox.resolve(&path), Err(PathError::Escapes), "{}", raw);
+    }
+    assert!(!sandbox.contains(&root.join("escape")));
+    assert!(sandbox.contains(&root.join("docs")));
+}
+
+#[cfg(unix)]
+#[test]
+fn follows_symlinks_inside_the_root() {
+    use std::os::unix::fs::symlink;
+
+    let (root, _) = storage();
+    symlink(root.join("docs"), root.join("shortcut")).unwrap();
+    let sandbox = Sandbox::new(&root, SymlinkPolicy::Follow).unwrap();
+
+    let resolved = sandbox.resolve(&"shortcut/readme.txt".parse().unwrap()).unwrap();
+    assert_eq!(std::fs::read_to_string(resolved).unwrap(), "inside");
+    assert!(sandbox.contains(&root.join("shortcut")));
+}
+
+#[cfg(unix)]
+#[test]
+fn refuse_policy_rejects_every_symlink() {
+    use std::os::unix::fs::symlink;
+
+    let (root, _) = storage();
+    symlink(root.join("docs"), root.join("shortcut")).unwrap();
+    let sandbox = Sandbox::new(&root, SymlinkPolicy::Refuse).unwrap();
+
+    assert_eq!(
+        sandbox.resolve(&"shortcut/readme.txt".parse().unwrap()),
+        Err(PathError::Symlink)
+    );
+    assert_eq!(sandbox.resolve(&"shortcut".parse().unwrap()), Err(PathError::Symlink));
+    assert!(sandbox.resolve(&"docs/readme.txt".parse().unwrap()).is_ok());
+}
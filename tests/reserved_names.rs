@@ -0,0 +1,38 @@
+use grpc_files::access::AccessTokens;
+use grpc_files::config::Config;
+use grpc_files::ssh::AuthorizedKeys;
+
+// Only the Unix socket hands out `uid:` names, so other identities can't pass
+// for a local user such as root
+
+#[test]
+fn access_tokens_cannot_take_uid_names() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "access_tokens": [{ "name": "uid:0", "sha256": "00".repeat(32) }],
+    }))
+    .unwrap();
+    let error = AccessTokens::load(&config).err().unwrap();
+    assert!(error.to_string().contains("reserved"), "{}", error);
+}
+
+#[test]
+fn ssh_keys_cannot_take_uid_names() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "sftp_authorized_keys": [{
+            "name": "uid:0",
+            "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJDahfSy2jhRRLqS9wBjQCmjyZ1C7WEK2WUTkEbJgHjW",
+        }],
+    }))
+    .unwrap();
+    let error = AuthorizedKeys::load(&config).err().unwrap();
+    assert!(error.to_string().contains("reserved"), "{}", error);
+}
+
+#[test]
+fn other_names_are_fine() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "access_tokens": [{ "name": "uid-0", "sha256": "00".repeat(32) }],
+    }))
+    .unwrap();
+    assert!(AccessTokens::load(&config).is_ok());
+}
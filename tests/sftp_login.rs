@@ -0,0 +1,154 @@
+use grpc_files::config::Config;
+use grpc_files::identity::SshIdentity;
+use grpc_files::ssh::{AuthorizedKeys, HostKey, SftpServer};
+use russh::client;
+use russh::keys::ssh_key::LineEnding;
+use russh::keys::ssh_key::private::Ed25519Keypair;
+use russh::keys::{PrivateKey, PrivateKeyWithHashAlg, PublicKey, PublicKeyOrCertificate};
+use russh_sftp::client::SftpSession;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+fn key(seed: u8) -> PrivateKey {
+    PrivateKey::from(Ed25519Keypair::from_seed(&[seed; 32]))
+}
+
+/// A running SFTP server that lets `alice` sign in with `key(1)`.
+struct Server {
+    addr: SocketAddr,
+    host_key: PublicKey,
+    /// Names of the callers the file service saw.
+    callers: Arc<Mutex<Vec<String>>>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+async fn server() -> Server {
+    let dir = std::env::temp_dir().join(format!("grpc-files-sftp-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let host_key = key(9);
+    let host_key_file = dir.join("ssh_host_ed25519_key");
+    std::fs::write(&host_key_file, host_key.to_openssh(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "sftp_host_key_file": host_key_file.to_string_lossy(),
+        "sftp_authorized_keys": [
+            { "name": "alice", "key": key(1).public_key().to_openssh().unwrap() },
+        ],
+    }))
+    .unwrap();
+
+    // Nothing is stored, which is enough to see who is calling
+    let callers = Arc::new(Mutex::new(Vec::new()));
+    let seen = callers.clone();
+    let service = tower::service_fn(move |request: http::Request<tonic::body::Body>| {
+        if let Some(identity) = request.extensions().get::<SshIdentity>() {
+            seen.lock().unwrap().push(identity.name.clone());
+        }
+        async { Ok::<_, Infallible>(tonic::Status::not_found("nothing stored here").into_http()) }
+    });
+    let sftp = SftpServer::new(HostKey::load(&config).unwrap(), AuthorizedKeys::load(&config).unwrap(), service);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let task = tokio::spawn(sftp.serve(listener, shutdown_rx));
+    Server {
+        addr,
+        host_key: host_key.public_key().clone(),
+        callers,
+        shutdown,
+        task,
+    }
+}
+
+struct Client {
+    host_key: PublicKey,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, key: &PublicKeyOrCertificate) -> Result<bool, Self::Error> {
+        Ok(matches!(key, PublicKeyOrCertificate::PublicKey { key, .. } if *key == self.host_key))
+    }
+}
+
+async fn sign_in(server: &Server, key: PrivateKey) -> (client::Handle<Client>, bool) {
+    let client = Client {
+        host_key: server.host_key.clone(),
+    };
+    let mut handle = client::connect(Arc::new(client::Config::default()), server.addr, client)
+        .await
+        .unwrap();
+    let auth = handle
+        .authenticate_publickey("anyone", PrivateKeyWithHashAlg::new(Arc::new(key), None))
+        .await
+        .unwrap();
+    (handle, auth.success())
+}
+
+async fn open_sftp(handle: &client::Handle<Client>) -> SftpSession {
+    let channel = handle.channel_open_session().await.unwrap();
+    channel.request_subsystem(true, "sftp").await.unwrap();
+    SftpSession::new(channel.into_stream()).await.unwrap()
+}
+
+#[tokio::test]
+async fn authorized_key_acts_as_its_client() {
+    let server = server().await;
+    let (handle, signed_in) = sign_in(&server, key(1)).await;
+    assert!(signed_in);
+
+    let sftp = open_sftp(&handle).await;
+    assert_eq!(sftp.canonicalize("volume/../docs/.").await.unwrap(), "/docs");
+    assert!(sftp.read_dir("/").await.is_err());
+    assert_eq!(*server.callers.lock().unwrap(), ["alice"]);
+}
+
+#[tokio::test]
+async fn unknown_key_is_refused() {
+    let server = server().await;
+    let (_handle, signed_in) = sign_in(&server, key(2)).await;
+    assert!(!signed_in);
+    assert!(server.callers.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn shutdown_hangs_up_on_idle_clients() {
+    let server = server().await;
+    let (handle, signed_in) = sign_in(&server, key(1)).await;
+    assert!(signed_in);
+    let _sftp = open_sftp(&handle).await;
+
+    server.shutdown.send(true).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), server.task)
+        .await
+        .expect("server still running")
+        .unwrap();
+    assert!(handle.is_closed());
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_open_files() {
+    let server = server().await;
+    let (handle, signed_in) = sign_in(&server, key(1)).await;
+    assert!(signed_in);
+    let sftp = open_sftp(&handle).await;
+    let file = sftp.create("/default/notes.txt").await.unwrap();
+
+    server.shutdown.send(true).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!server.task.is_finished());
+    assert!(!handle.is_closed());
+
+    // The upload fails on this server, but the file is closed all the same
+    drop(file);
+    tokio::time::timeout(Duration::from_secs(10), server.task)
+        .await
+        .expect("server still running")
+        .unwrap();
+}